@@ -142,6 +142,11 @@ impl ExtendedPublic {
 	pub fn public(&self) -> &Public {
 		&self.public
 	}
+
+	/// Chain code component of the extended key.
+	pub fn chain_code(&self) -> &H256 {
+		&self.chain_code
+	}
 }
 
 pub struct ExtendedKeyPair {
@@ -0,0 +1 @@
+ALTER TABLE keys ADD COLUMN encrypted_data_key VARCHAR;
@@ -0,0 +1,3 @@
+ALTER TABLE keys DROP COLUMN IF EXISTS derivation_path;
+DROP TABLE IF EXISTS derivation_indexes;
+DROP TABLE IF EXISTS seeds;
@@ -0,0 +1,18 @@
+CREATE TABLE seeds (
+    owner_id UUID PRIMARY KEY REFERENCES users (id) ON DELETE RESTRICT,
+    master_seed VARCHAR NOT NULL,
+    encrypted_data_key VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+    updated_at TIMESTAMP NOT NULL DEFAULT current_timestamp
+);
+
+SELECT diesel_manage_updated_at('seeds');
+
+CREATE TABLE derivation_indexes (
+    owner_id UUID NOT NULL REFERENCES users (id) ON DELETE RESTRICT,
+    currency VARCHAR NOT NULL,
+    next_index INTEGER NOT NULL,
+    PRIMARY KEY (owner_id, currency)
+);
+
+ALTER TABLE keys ADD COLUMN derivation_path VARCHAR;
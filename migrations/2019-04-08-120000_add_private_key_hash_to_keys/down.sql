@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS keys_private_key_hash_idx;
+ALTER TABLE keys DROP COLUMN IF EXISTS private_key_hash;
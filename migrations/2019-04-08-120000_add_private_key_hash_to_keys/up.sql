@@ -0,0 +1,2 @@
+ALTER TABLE keys ADD COLUMN private_key_hash VARCHAR;
+CREATE UNIQUE INDEX keys_private_key_hash_idx ON keys (private_key_hash, currency);
@@ -0,0 +1 @@
+ALTER TABLE users ADD COLUMN can_export_keys BOOLEAN NOT NULL DEFAULT FALSE;
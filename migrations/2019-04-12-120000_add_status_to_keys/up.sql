@@ -0,0 +1 @@
+ALTER TABLE keys ADD COLUMN status VARCHAR NOT NULL DEFAULT 'active';
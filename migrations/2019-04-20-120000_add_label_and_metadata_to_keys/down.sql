@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS keys_metadata_idx;
+DROP INDEX IF EXISTS keys_owner_id_label_idx;
+ALTER TABLE keys DROP COLUMN IF EXISTS metadata;
+ALTER TABLE keys DROP COLUMN IF EXISTS label;
@@ -0,0 +1,4 @@
+ALTER TABLE keys ADD COLUMN label VARCHAR;
+ALTER TABLE keys ADD COLUMN metadata JSONB NOT NULL DEFAULT '{}';
+CREATE INDEX keys_owner_id_label_idx ON keys (owner_id, label);
+CREATE INDEX keys_metadata_idx ON keys USING GIN (metadata jsonb_path_ops);
@@ -0,0 +1 @@
+DROP INDEX IF EXISTS keys_owner_id_created_at_id_idx;
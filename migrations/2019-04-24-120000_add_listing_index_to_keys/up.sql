@@ -0,0 +1 @@
+CREATE INDEX keys_owner_id_created_at_id_idx ON keys (owner_id, created_at, id);
@@ -0,0 +1,2 @@
+ALTER TABLE keys DROP COLUMN IF EXISTS signatures_count;
+ALTER TABLE keys DROP COLUMN IF EXISTS last_used_at;
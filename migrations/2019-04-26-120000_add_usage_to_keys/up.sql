@@ -0,0 +1,2 @@
+ALTER TABLE keys ADD COLUMN last_used_at TIMESTAMP;
+ALTER TABLE keys ADD COLUMN signatures_count BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+ALTER TABLE keys ADD COLUMN wallet_type VARCHAR;
+UPDATE keys SET wallet_type = 'p2pkh' WHERE currency = 'btc';
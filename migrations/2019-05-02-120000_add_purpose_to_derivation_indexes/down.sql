@@ -0,0 +1,4 @@
+DELETE FROM derivation_indexes WHERE purpose <> 44;
+ALTER TABLE derivation_indexes DROP CONSTRAINT derivation_indexes_pkey;
+ALTER TABLE derivation_indexes ADD PRIMARY KEY (owner_id, currency);
+ALTER TABLE derivation_indexes DROP COLUMN IF EXISTS purpose;
@@ -0,0 +1,3 @@
+ALTER TABLE derivation_indexes ADD COLUMN purpose INTEGER NOT NULL DEFAULT 44;
+ALTER TABLE derivation_indexes DROP CONSTRAINT derivation_indexes_pkey;
+ALTER TABLE derivation_indexes ADD PRIMARY KEY (owner_id, currency, purpose);
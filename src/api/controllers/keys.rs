@@ -1,7 +1,7 @@
 use super::super::error::*;
 use super::super::requests::*;
 use super::super::responses::*;
-use super::super::utils::{parse_body, response_with_model};
+use super::super::utils::{parse_body, parse_secret_body, response_with_model};
 use super::Context;
 use super::ControllerFuture;
 use failure::Fail;
@@ -13,35 +13,45 @@ pub fn get_keys(ctx: &Context, user_id: UserId) -> ControllerFuture {
     let keys_service = ctx.keys_service.clone();
     let maybe_token = ctx.get_auth_token();
     let path_and_query = ctx.uri.path_and_query();
-    let path_and_query_clone = ctx.uri.path_and_query();
+    // all params are optional, so query can be omitted
+    let query = ctx.uri.query().unwrap_or("");
     Box::new(
-        ctx.uri
-            .query()
-            .ok_or(ectx!(err ErrorContext::RequestMissingQuery, ErrorKind::BadRequest => path_and_query))
-            .and_then(|query| {
-                serde_qs::from_str::<GetKeysParams>(query).map_err(|e| {
-                    let e = format_err!("{}", e);
-                    ectx!(err e, ErrorContext::RequestQueryParams, ErrorKind::BadRequest => path_and_query_clone)
-                })
+        serde_qs::from_str::<GetKeysParams>(query)
+            .map_err(|e| {
+                let e = format_err!("{}", e);
+                ectx!(err e, ErrorContext::RequestQueryParams, ErrorKind::BadRequest => path_and_query)
             })
             .into_future()
             .and_then(move |input| {
                 let input_clone = input.clone();
+                let (cursor, limit) = (input.cursor.clone(), input.limit);
                 keys_service
-                    .list(maybe_token, user_id, input.offset, input.limit)
+                    .list(maybe_token, user_id, input.into(), cursor, limit)
                     .map_err(ectx!(convert => input_clone))
             })
-            .and_then(|keys| {
-                let keys: Vec<KeyResponse> = keys
-                    .iter()
-                    .map(|key| KeyResponse {
-                        id: key.id.clone(),
-                        blockchain_address: key.blockchain_address.clone(),
-                        currency: key.currency,
-                    })
-                    .collect();
-                response_with_model(&keys)
-            }),
+            .and_then(|page| response_with_model(&KeysPageResponse::from(page))),
+    )
+}
+
+pub fn get_key(ctx: &Context, user_id: UserId, key_id: KeyId) -> ControllerFuture {
+    let keys_service = ctx.keys_service.clone();
+    let maybe_token = ctx.get_auth_token();
+    Box::new(
+        keys_service
+            .get(maybe_token, user_id, key_id)
+            .map_err(ectx!(convert))
+            .and_then(|key| response_with_model(&KeyResponse::from(key))),
+    )
+}
+
+pub fn get_key_by_address(ctx: &Context, currency: Currency, address: BlockchainAddress) -> ControllerFuture {
+    let keys_service = ctx.keys_service.clone();
+    let maybe_token = ctx.get_auth_token();
+    Box::new(
+        keys_service
+            .find_by_address(maybe_token, currency, address)
+            .map_err(ectx!(convert))
+            .and_then(|key| response_with_model(&KeyResponse::from(key))),
     )
 }
 
@@ -53,22 +63,82 @@ pub fn post_keys(ctx: &Context, user_id: UserId) -> ControllerFuture {
             .and_then(move |input| {
                 let input_clone = input.clone();
                 keys_service
-                    .create(maybe_token, user_id, input.currency, input.id)
+                    .create(
+                        maybe_token,
+                        user_id,
+                        input.currency,
+                        input.wallet_type,
+                        input.id,
+                        input.label,
+                        input.metadata,
+                    )
+                    .map_err(ectx!(convert => input_clone))
+            })
+            .and_then(|key| response_with_model(&KeyResponse::from(key))),
+    )
+}
+
+pub fn post_keys_import(ctx: &Context, user_id: UserId) -> ControllerFuture {
+    let keys_service = ctx.keys_service.clone();
+    let maybe_token = ctx.get_auth_token();
+    Box::new(
+        parse_secret_body::<PostKeysImportRequest>(ctx.body.clone())
+            .and_then(move |input| {
+                let input_clone = input.clone();
+                keys_service
+                    .import(
+                        maybe_token,
+                        user_id,
+                        input.currency,
+                        input.wallet_type,
+                        input.id,
+                        input.private_key,
+                        input.label,
+                        input.metadata,
+                    )
+                    .map_err(ectx!(convert => input_clone))
+            })
+            .and_then(|key| response_with_model(&KeyResponse::from(key))),
+    )
+}
+
+pub fn patch_key(ctx: &Context, user_id: UserId, key_id: KeyId) -> ControllerFuture {
+    let keys_service = ctx.keys_service.clone();
+    let maybe_token = ctx.get_auth_token();
+    Box::new(
+        parse_body::<PatchKeyRequest>(ctx.body.clone())
+            .and_then(move |input| {
+                let input_clone = input.clone();
+                keys_service
+                    .update(maybe_token, user_id, key_id, input.into())
+                    .map_err(ectx!(convert => input_clone))
+            })
+            .and_then(|key| response_with_model(&KeyResponse::from(key))),
+    )
+}
+
+pub fn delete_key(ctx: &Context, user_id: UserId, key_id: KeyId) -> ControllerFuture {
+    let keys_service = ctx.keys_service.clone();
+    let maybe_token = ctx.get_auth_token();
+    Box::new(
+        keys_service
+            .delete(maybe_token, user_id, key_id)
+            .map_err(ectx!(convert))
+            .and_then(|key| response_with_model(&KeyResponse::from(key))),
+    )
+}
+
+pub fn post_key_export(ctx: &Context, key_id: KeyId) -> ControllerFuture {
+    let keys_service = ctx.keys_service.clone();
+    let maybe_token = ctx.get_auth_token();
+    Box::new(
+        parse_body::<PostKeyExportRequest>(ctx.body.clone())
+            .and_then(move |input| {
+                let input_clone = input.clone();
+                keys_service
+                    .export(maybe_token, key_id, input.public_key)
                     .map_err(ectx!(convert => input_clone))
             })
-            .and_then(|key| {
-                let Key {
-                    blockchain_address,
-                    currency,
-                    id,
-                    ..
-                } = key;
-                let key_response = KeyResponse {
-                    id,
-                    blockchain_address,
-                    currency,
-                };
-                response_with_model(&key_response)
-            }),
+            .and_then(|exported| response_with_model(&KeyExportResponse::from(exported))),
     )
 }
@@ -12,3 +12,25 @@ pub fn get_metrics(ctx: &Context) -> ControllerFuture {
             .and_then(|metrics| response_with_model(&metrics)),
     )
 }
+
+pub fn get_audit(ctx: &Context) -> ControllerFuture {
+    let metrics_service = ctx.metrics_service.clone();
+    let maybe_token = ctx.get_auth_token();
+    Box::new(
+        metrics_service
+            .audit(maybe_token)
+            .map_err(ectx!(convert))
+            .and_then(|audit| response_with_model(&audit)),
+    )
+}
+
+pub fn post_audit_quarantine(ctx: &Context) -> ControllerFuture {
+    let metrics_service = ctx.metrics_service.clone();
+    let maybe_token = ctx.get_auth_token();
+    Box::new(
+        metrics_service
+            .quarantine(maybe_token)
+            .map_err(ectx!(convert))
+            .and_then(|audit| response_with_model(&audit)),
+    )
+}
@@ -5,19 +5,22 @@ use futures::prelude::*;
 use hyper::{header::HeaderValue, header::AUTHORIZATION, Body, HeaderMap, Method, Response, Uri};
 
 use super::error::*;
+use super::utils::is_secret_path;
 use models::*;
-use services::{KeysService, MetricsService, TransactionsService};
+use services::{KeysService, MetricsService, SeedsService, TransactionsService};
 
 mod fallback;
 mod healthcheck;
 mod keys;
 mod metrics;
+mod seeds;
 mod transactions;
 
 pub use self::fallback::*;
 pub use self::healthcheck::*;
 pub use self::keys::*;
 pub use self::metrics::*;
+pub use self::seeds::*;
 pub use self::transactions::*;
 
 pub type ControllerFuture = Box<Future<Item = Response<Body>, Error = Error> + Send>;
@@ -29,6 +32,7 @@ pub struct Context {
     pub uri: Uri,
     pub headers: HeaderMap<HeaderValue>,
     pub keys_service: Arc<KeysService>,
+    pub seeds_service: Arc<SeedsService>,
     pub transactions_service: Arc<TransactionsService>,
     pub metrics_service: Arc<MetricsService>,
 }
@@ -52,12 +56,14 @@ impl Context {
 
 impl Display for Context {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let body = if is_secret_path(self.uri.path()) {
+            Some("********".to_string())
+        } else {
+            String::from_utf8(self.body.clone()).ok()
+        };
         f.write_str(&format!(
             "{} {}, headers: {:#?}, body: {:?}",
-            self.method,
-            self.uri,
-            self.headers,
-            String::from_utf8(self.body.clone()).ok()
+            self.method, self.uri, self.headers, body
         ))
     }
 }
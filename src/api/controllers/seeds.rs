@@ -0,0 +1,82 @@
+use super::super::error::*;
+use super::super::requests::*;
+use super::super::responses::*;
+use super::super::utils::{parse_secret_body, response_with_model};
+use super::Context;
+use super::ControllerFuture;
+use failure::Fail;
+use futures::prelude::*;
+use models::*;
+use serde_qs;
+
+pub fn get_xpub(ctx: &Context, user_id: UserId) -> ControllerFuture {
+    let seeds_service = ctx.seeds_service.clone();
+    let maybe_token = ctx.get_auth_token();
+    let path_and_query = ctx.uri.path_and_query();
+    let path_and_query_clone = ctx.uri.path_and_query();
+    Box::new(
+        ctx.uri
+            .query()
+            .ok_or(ectx!(err ErrorContext::RequestMissingQuery, ErrorKind::BadRequest => path_and_query))
+            .and_then(|query| {
+                serde_qs::from_str::<GetXpubParams>(query).map_err(|e| {
+                    let e = format_err!("{}", e);
+                    ectx!(err e, ErrorContext::RequestQueryParams, ErrorKind::BadRequest => path_and_query_clone)
+                })
+            })
+            .into_future()
+            .and_then(move |input| {
+                let input_clone = input.clone();
+                seeds_service
+                    .extended_public_key(maybe_token, user_id, input.currency, input.wallet_type)
+                    .map_err(ectx!(convert => input_clone))
+            })
+            .and_then(|(xpub, derivation_path, wallet_type)| {
+                response_with_model(&XpubResponse {
+                    xpub,
+                    derivation_path,
+                    wallet_type,
+                })
+            }),
+    )
+}
+
+pub fn post_mnemonic(ctx: &Context, user_id: UserId) -> ControllerFuture {
+    let seeds_service = ctx.seeds_service.clone();
+    let maybe_token = ctx.get_auth_token();
+    Box::new(
+        parse_secret_body::<PostMnemonicRequest>(ctx.body.clone())
+            .and_then(move |input| {
+                let input_clone = input.clone();
+                seeds_service
+                    .create_mnemonic(maybe_token, user_id, input.words, input.passphrase)
+                    .map_err(ectx!(convert => input_clone))
+            })
+            .and_then(|mnemonic| response_with_model(&PostMnemonicResponse { mnemonic })),
+    )
+}
+
+pub fn post_mnemonic_import(ctx: &Context, user_id: UserId) -> ControllerFuture {
+    let seeds_service = ctx.seeds_service.clone();
+    let maybe_token = ctx.get_auth_token();
+    Box::new(
+        parse_secret_body::<PostMnemonicImportRequest>(ctx.body.clone())
+            .and_then(move |input| {
+                let input_clone = input.clone();
+                seeds_service
+                    .import_mnemonic(
+                        maybe_token,
+                        user_id,
+                        input.mnemonic,
+                        input.passphrase,
+                        input.recover,
+                        input.wallet_types,
+                    )
+                    .map_err(ectx!(convert => input_clone))
+            })
+            .and_then(|keys| {
+                let keys = keys.into_iter().map(KeyResponse::from).collect();
+                response_with_model(&PostMnemonicImportResponse { keys })
+            }),
+    )
+}
@@ -35,6 +35,8 @@ pub enum ErrorSource {
 pub enum ErrorContext {
     #[fail(display = "controller context - error parsing config data")]
     Config,
+    #[fail(display = "controller context - error loading master key")]
+    MasterKey,
     #[fail(display = "controller context - error converting json data from request")]
     RequestJson,
     #[fail(display = "controller context - error parsing bytes into utf8 from request")]
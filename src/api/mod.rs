@@ -25,11 +25,13 @@ use r2d2;
 
 use self::controllers::*;
 use self::error::*;
+use self::utils::is_secret_path;
 use blockchain::BlockchainServiceImpl;
+use master_key::master_key_provider;
 use models::*;
 use prelude::*;
-use repos::{DbExecutorImpl, KeysRepoImpl, UsersRepoImpl};
-use services::{AuthServiceImpl, KeysServiceImpl, MetricsServiceImpl, TransactionsServiceImpl};
+use repos::{DbExecutorImpl, KeyEncryptionProvider, KeysRepoImpl, SeedsRepoImpl, UsersRepoImpl};
+use services::{AuthServiceImpl, KeysServiceImpl, MetricsServiceImpl, SeedsServiceImpl, TransactionsServiceImpl};
 
 #[derive(Clone)]
 pub struct ApiService {
@@ -37,6 +39,7 @@ pub struct ApiService {
     config: Config,
     db_pool: PgPool,
     cpu_pool: CpuPool,
+    key_encryption_provider: Arc<KeyEncryptionProvider>,
 }
 
 impl ApiService {
@@ -58,11 +61,16 @@ impl ApiService {
             database_url
         ))?;
         let cpu_pool = CpuPool::new(config.database.thread_pool_size);
+        // Master key is loaded once, so misconfigured provider fails on startup rather than on first request
+        let key_encryption_provider = KeysRepoImpl::new(&*master_key_provider(config))
+            .map_err(ectx!(try ErrorContext::MasterKey, ErrorKind::Internal))?
+            .key_encryption_provider();
         Ok(ApiService {
             config: config.clone(),
             server_address,
             db_pool,
             cpu_pool,
+            key_encryption_provider,
         })
     }
 }
@@ -79,6 +87,8 @@ impl Service for ApiService {
         let thread_pool = self.cpu_pool.clone();
         let db_executor = DbExecutorImpl::new(db_pool.clone(), thread_pool.clone());
         let config = self.config.clone();
+        let key_encryption_provider = self.key_encryption_provider.clone();
+        let secret_body = is_secret_path(parts.uri.path());
         Box::new(
             read_body(http_body)
                 .map_err(ectx!(ErrorSource::Hyper, ErrorKind::Internal))
@@ -86,10 +96,21 @@ impl Service for ApiService {
                     let router = router! {
                         GET /v1/users/{user_id: UserId}/keys => get_keys,
                         POST /v1/users/{user_id: UserId}/keys => post_keys,
+                        POST /v1/users/{user_id: UserId}/keys/import => post_keys_import,
+                        GET /v1/users/{user_id: UserId}/keys/{key_id: KeyId} => get_key,
+                        PATCH /v1/users/{user_id: UserId}/keys/{key_id: KeyId} => patch_key,
+                        DELETE /v1/users/{user_id: UserId}/keys/{key_id: KeyId} => delete_key,
+                        POST /v1/keys/{key_id: KeyId}/export => post_key_export,
+                        GET /v1/keys/by-address/{currency: Currency}/{address: BlockchainAddress} => get_key_by_address,
+                        GET /v1/users/{user_id: UserId}/xpub => get_xpub,
+                        POST /v1/users/{user_id: UserId}/mnemonic => post_mnemonic,
+                        POST /v1/users/{user_id: UserId}/mnemonic/import => post_mnemonic_import,
                         POST /v1/transactions => post_transactions,
                         POST /v1/approve => post_approve,
                         GET /healthcheck => get_healthcheck,
                         GET /v1/metrics => get_metrics,
+                        GET /v1/admin/audit => get_audit,
+                        POST /v1/admin/audit/quarantine => post_audit_quarantine,
                         _ => not_found,
                     };
                     let users_repo = Arc::new(UsersRepoImpl);
@@ -102,12 +123,26 @@ impl Service for ApiService {
                         config.blockchain.stq_approve_method_number.clone(),
                         config.blockchain.ethereum_chain_id.clone(),
                         config.blockchain.btc_network.clone(),
+                        config.blockchain.btc_coin_selection,
                     ));
-                    let keys_repo = Arc::new(KeysRepoImpl::new(config.blockchain.main_key.clone()));
+                    let keys_repo = Arc::new(KeysRepoImpl::with_key_encryption_provider(key_encryption_provider.clone()));
+                    let seeds_repo = Arc::new(SeedsRepoImpl::new(key_encryption_provider));
                     let keys_service = Arc::new(KeysServiceImpl::new(
                         auth_service.clone(),
                         blockchain_service.clone(),
                         keys_repo.clone(),
+                        seeds_repo.clone(),
+                        users_repo.clone(),
+                        config.blockchain.key_generation,
+                        db_executor.clone(),
+                    ));
+                    let seeds_service = Arc::new(SeedsServiceImpl::new(
+                        auth_service.clone(),
+                        blockchain_service.clone(),
+                        keys_repo.clone(),
+                        seeds_repo,
+                        users_repo.clone(),
+                        config.blockchain.key_generation,
                         db_executor.clone(),
                     ));
                     let transactions_service = Arc::new(TransactionsServiceImpl::new(
@@ -119,6 +154,7 @@ impl Service for ApiService {
                         db_executor.clone(),
                     ));
                     let metrics_service = Arc::new(MetricsServiceImpl::new(
+                        auth_service.clone(),
                         keys_repo.clone(),
                         blockchain_service.clone(),
                         db_executor.clone(),
@@ -130,6 +166,7 @@ impl Service for ApiService {
                         uri: parts.uri.clone(),
                         headers: parts.headers,
                         keys_service,
+                        seeds_service,
                         transactions_service,
                         metrics_service,
                     };
@@ -144,12 +181,17 @@ impl Service for ApiService {
                         .map_err(ectx!(ErrorSource::Hyper, ErrorKind::Internal))
                         .map(|body| (parts, body))
                 })
-                .map(|(parts, body)| {
+                .map(move |(parts, body)| {
+                    let logged_body = if secret_body {
+                        Some("********".to_string())
+                    } else {
+                        String::from_utf8(body.clone()).ok()
+                    };
                     debug!(
                         "Sent response with status {}, headers: {:#?}, body: {:?}",
                         parts.status.as_u16(),
                         parts.headers,
-                        String::from_utf8(body.clone()).ok()
+                        logged_body
                     );
                     Response::from_parts(parts, body.into())
                 })
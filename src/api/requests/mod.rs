@@ -1,17 +1,107 @@
+use std::collections::HashMap;
+
 use models::*;
+use serde_json::Value as JsonValue;
 
 #[derive(Debug, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct PostKeysRequest {
     pub id: KeyId,
     pub currency: Currency,
+    pub wallet_type: Option<WalletType>,
+    pub label: Option<KeyLabel>,
+    #[serde(default)]
+    pub metadata: KeyMetadata,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PostKeysImportRequest {
+    pub id: KeyId,
+    pub currency: Currency,
+    pub wallet_type: Option<WalletType>,
+    pub private_key: RawPrivateKey,
+    pub label: Option<KeyLabel>,
+    #[serde(default)]
+    pub metadata: KeyMetadata,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PatchKeyRequest {
+    pub status: Option<KeyStatus>,
+    pub label: Option<KeyLabel>,
+    pub metadata: Option<KeyMetadata>,
+}
+
+impl From<PatchKeyRequest> for KeyUpdate {
+    fn from(req: PatchKeyRequest) -> Self {
+        let PatchKeyRequest { status, label, metadata } = req;
+        KeyUpdate { status, label, metadata }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PostKeyExportRequest {
+    pub public_key: RecipientPublicKey,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct GetKeysParams {
-    pub limit: i64,
-    pub offset: i64,
+    pub limit: Option<i64>,
+    /// `nextCursor` of the previous page
+    pub cursor: Option<KeysCursor>,
+    pub currency: Option<Currency>,
+    pub status: Option<KeyStatus>,
+    pub label: Option<KeyLabel>,
+    /// String fields, that metadata of keys must contain, e.g. `metadata[merchant]=shop`
+    pub metadata: Option<HashMap<String, String>>,
+}
+
+impl From<GetKeysParams> for KeysFilter {
+    fn from(params: GetKeysParams) -> Self {
+        let metadata = params.metadata.map(|fields| {
+            let fields = fields.into_iter().map(|(field, value)| (field, JsonValue::String(value))).collect();
+            KeyMetadata::new(JsonValue::Object(fields))
+        });
+        KeysFilter {
+            currency: params.currency,
+            status: params.status,
+            label: params.label,
+            metadata,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GetXpubParams {
+    pub currency: Currency,
+    pub wallet_type: Option<WalletType>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PostMnemonicRequest {
+    pub words: usize,
+    #[serde(default)]
+    pub passphrase: Passphrase,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PostMnemonicImportRequest {
+    pub mnemonic: Mnemonic,
+    #[serde(default)]
+    pub passphrase: Passphrase,
+    /// Number of keys of each currency to recreate from the seed
+    #[serde(default)]
+    pub recover: HashMap<Currency, u32>,
+    /// Wallet types of recreated bitcoin keys, `recover` keys are recreated for each of them
+    #[serde(default)]
+    pub wallet_types: Vec<WalletType>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -25,6 +115,7 @@ pub struct PostTransactionsRequest {
     pub fee_price: f64,
     pub nonce: Option<u64>,
     pub utxos: Option<Vec<Utxo>>,
+    pub coin_selection: Option<CoinSelection>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -73,6 +164,7 @@ impl From<PostTransactionsRequest> for UnsignedTransaction {
             fee_price,
             nonce,
             utxos,
+            coin_selection,
         } = req;
 
         UnsignedTransaction {
@@ -84,6 +176,7 @@ impl From<PostTransactionsRequest> for UnsignedTransaction {
             fee_price,
             nonce,
             utxos,
+            coin_selection,
         }
     }
 }
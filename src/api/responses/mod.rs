@@ -1,3 +1,5 @@
+use chrono::{DateTime, Utc};
+
 use models::*;
 
 #[derive(Debug, Serialize, Clone)]
@@ -11,9 +13,92 @@ pub struct PostKeysResponse {
 #[derive(Debug, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct KeyResponse {
+    pub id: KeyId,
+    pub currency: Currency,
+    pub wallet_type: Option<WalletType>,
+    pub blockchain_address: BlockchainAddress,
+    pub derivation_path: Option<DerivationPath>,
+    pub status: KeyStatus,
+    pub label: Option<KeyLabel>,
+    pub metadata: KeyMetadata,
+    /// RFC 3339 time of the last signed transaction
+    pub last_used_at: Option<String>,
+    pub signatures_count: i64,
+}
+
+impl From<Key> for KeyResponse {
+    fn from(key: Key) -> Self {
+        KeyResponse {
+            id: key.id,
+            currency: key.currency,
+            wallet_type: key.wallet_type,
+            blockchain_address: key.blockchain_address,
+            derivation_path: key.derivation_path,
+            status: key.status,
+            label: key.label,
+            metadata: key.metadata,
+            last_used_at: key.last_used_at.map(|time| DateTime::<Utc>::from(time).to_rfc3339()),
+            signatures_count: key.signatures_count,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct KeysPageResponse {
+    pub keys: Vec<KeyResponse>,
+    pub total: i64,
+    pub next_cursor: Option<KeysCursor>,
+}
+
+impl From<KeysPage> for KeysPageResponse {
+    fn from(page: KeysPage) -> Self {
+        KeysPageResponse {
+            keys: page.keys.into_iter().map(KeyResponse::from).collect(),
+            total: page.total,
+            next_cursor: page.next_cursor,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyExportResponse {
     pub id: KeyId,
     pub currency: Currency,
     pub blockchain_address: BlockchainAddress,
+    pub encrypted_private_key: ExportedPrivateKey,
+}
+
+impl From<(Key, ExportedPrivateKey)> for KeyExportResponse {
+    fn from((key, encrypted_private_key): (Key, ExportedPrivateKey)) -> Self {
+        KeyExportResponse {
+            id: key.id,
+            currency: key.currency,
+            blockchain_address: key.blockchain_address,
+            encrypted_private_key,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct XpubResponse {
+    pub xpub: ExtendedPublicKey,
+    pub derivation_path: DerivationPath,
+    pub wallet_type: Option<WalletType>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PostMnemonicResponse {
+    pub mnemonic: Mnemonic,
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PostMnemonicImportResponse {
+    pub keys: Vec<KeyResponse>,
 }
 
 #[derive(Debug, Serialize, Clone)]
@@ -18,6 +18,22 @@ where
         .and_then(|string| serde_json::from_str::<T>(&string).map_err(ectx!(ErrorContext::RequestJson, ErrorKind::BadRequest => string)))
 }
 
+/// Same as `parse_body`, but doesn't attach the body to the error, since it contains secrets
+pub fn parse_secret_body<T>(body: Vec<u8>) -> impl Future<Item = T, Error = Error> + Send
+where
+    T: for<'de> Deserialize<'de> + Send,
+{
+    String::from_utf8(body)
+        .map_err(ectx!(ErrorContext::RequestUTF8, ErrorKind::BadRequest))
+        .into_future()
+        .and_then(|string| serde_json::from_str::<T>(&string).map_err(ectx!(ErrorContext::RequestJson, ErrorKind::BadRequest)))
+}
+
+/// Bodies of requests and responses on these paths contain secrets and must not be logged
+pub fn is_secret_path(path: &str) -> bool {
+    path.ends_with("/mnemonic") || path.ends_with("/mnemonic/import") || path.ends_with("/keys/import")
+}
+
 pub fn response_with_model<M>(model: &M) -> ControllerFuture
 where
     M: Debug + Serialize,
@@ -0,0 +1,44 @@
+use failure::{Backtrace, Context, Fail};
+use std::fmt;
+use std::fmt::Display;
+
+#[derive(Debug)]
+pub struct Error {
+    inner: Context<ErrorKind>,
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Debug, Fail, PartialEq, Eq)]
+pub enum ErrorKind {
+    #[fail(display = "backup error - internal")]
+    Internal,
+    #[fail(display = "backup error - error reading or writing backup file")]
+    Io,
+    #[fail(display = "backup error - malformed backup")]
+    Malformed,
+    #[fail(display = "backup error - wrong passphrase or corrupted backup")]
+    Decryption,
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Debug, Fail, PartialEq, Eq)]
+pub enum ErrorContext {
+    #[fail(display = "backup context - not a keystore backup")]
+    Header,
+    #[fail(display = "backup context - unsupported backup version")]
+    Version,
+    #[fail(display = "backup context - unsupported key derivation cost")]
+    KdfCost,
+    #[fail(display = "backup context - record is too long")]
+    RecordLength,
+    #[fail(display = "backup context - record authentication failed")]
+    Authentication,
+    #[fail(display = "backup context - backup is truncated")]
+    Truncated,
+    #[fail(display = "backup context - error converting record to or from json")]
+    Json,
+    #[fail(display = "backup context - checksum of records doesn't match")]
+    Checksum,
+}
+
+derive_error_impls!();
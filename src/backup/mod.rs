@@ -0,0 +1,546 @@
+//! Encrypted backup of users, keys, master seeds and derivation indexes.
+//!
+//! Backup file starts with a header: magic bytes, format version, scrypt cost, salt and
+//! nonce prefix. It's followed by records, each one is a big-endian `u32` length and
+//! JSON encrypted with AES-256-GCM. The key is derived from backup passphrase with scrypt.
+//! Every record is authenticated together with the header and its nonce contains the record
+//! number, so records can't be reordered or moved between backups. The last record holds
+//! the number of records and SHA-256 checksum of all of them, so truncated backup is detected.
+
+mod error;
+
+use std::io::{Read, Write};
+use std::mem;
+use std::time::SystemTime;
+
+use crypto::digest::Digest;
+use crypto::scrypt::{scrypt, ScryptParams};
+use crypto::sha2::Sha256;
+use failure::Fail;
+use rand;
+use rand::RngCore;
+use serde_json;
+
+pub use self::error::*;
+
+use models::*;
+use utils::{decode_hex, decrypt_aead, encode_hex, encrypt_aead, wipe, AEAD_NONCE_LEN};
+
+/// Current version of backup format
+pub const VERSION: u8 = 1;
+const MAGIC: &[u8] = b"KSBACKUP";
+/// scrypt cost parameter, 2^15 iterations take 32 MiB of memory
+const SCRYPT_LOG_N: u8 = 15;
+const MAX_SCRYPT_LOG_N: u8 = 20;
+const SALT_LEN: usize = 16;
+const NONCE_PREFIX_LEN: usize = 4;
+const HEADER_LEN: usize = 8 + 2 + SALT_LEN + NONCE_PREFIX_LEN;
+const MAX_RECORD_LEN: usize = 1 << 20;
+
+pub enum Record {
+    User(UserRecord),
+    Key(KeyRecord),
+    Seed(SeedRecord),
+    DerivationIndex(DerivationIndexRecord),
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UserRecord {
+    pub id: UserId,
+    pub name: String,
+    pub authentication_token: String,
+    pub can_export_keys: bool,
+    /// Missing in backups made before admin users were added
+    #[serde(default)]
+    pub is_admin: bool,
+}
+
+impl UserRecord {
+    pub fn from_user(user: &User) -> Self {
+        UserRecord {
+            id: user.id.clone(),
+            name: user.name.clone(),
+            authentication_token: user.authentication_token.raw().to_string(),
+            can_export_keys: user.can_export_keys,
+            is_admin: user.is_admin,
+        }
+    }
+
+    pub fn into_new_user(self) -> NewUser {
+        NewUser {
+            id: self.id,
+            name: self.name,
+            authentication_token: AuthenticationToken::new(self.authentication_token),
+            can_export_keys: self.can_export_keys,
+            is_admin: self.is_admin,
+        }
+    }
+}
+
+/// Key with its private key in plain text, so that backup can be restored with any master key.
+/// The private key is wiped from memory when the record is dropped.
+#[derive(Serialize, Deserialize)]
+pub struct KeyRecord {
+    pub id: KeyId,
+    pub currency: Currency,
+    pub blockchain_address: BlockchainAddress,
+    pub owner_id: UserId,
+    pub derivation_path: Option<DerivationPath>,
+    pub status: KeyStatus,
+    /// Label and metadata are missing in backups of keys created before they were added
+    #[serde(default)]
+    pub label: Option<KeyLabel>,
+    #[serde(default)]
+    pub metadata: KeyMetadata,
+    /// Missing in backups of keys created before wallet types were added
+    #[serde(default)]
+    pub wallet_type: Option<WalletType>,
+    /// Creation time and usage are missing in backups made before they were backed up
+    #[serde(default)]
+    pub created_at: Option<SystemTime>,
+    #[serde(default)]
+    pub last_used_at: Option<SystemTime>,
+    #[serde(default)]
+    pub signatures_count: i64,
+    private_key: String,
+}
+
+impl KeyRecord {
+    pub fn new(key: &Key, private_key: &PrivateKey) -> Self {
+        KeyRecord {
+            id: key.id.clone(),
+            currency: key.currency,
+            blockchain_address: key.blockchain_address.clone(),
+            owner_id: key.owner_id.clone(),
+            derivation_path: key.derivation_path.clone(),
+            status: key.status,
+            label: key.label.clone(),
+            metadata: key.metadata.clone(),
+            wallet_type: key.wallet_type,
+            created_at: Some(key.created_at),
+            last_used_at: key.last_used_at,
+            signatures_count: key.signatures_count,
+            private_key: private_key.raw().to_string(),
+        }
+    }
+
+    pub fn take_private_key(&mut self) -> PrivateKey {
+        PrivateKey::new(mem::replace(&mut self.private_key, String::new()))
+    }
+}
+
+impl Drop for KeyRecord {
+    fn drop(&mut self) {
+        unsafe { wipe(self.private_key.as_mut_vec()) };
+    }
+}
+
+/// Master seed in plain text, hex encoded. It's wiped from memory when the record is dropped.
+#[derive(Serialize, Deserialize)]
+pub struct SeedRecord {
+    pub owner_id: UserId,
+    master_seed: String,
+}
+
+impl SeedRecord {
+    pub fn new(seed: &Seed) -> Self {
+        SeedRecord {
+            owner_id: seed.owner_id.clone(),
+            master_seed: encode_hex(seed.master_seed.as_bytes()),
+        }
+    }
+
+    /// Returns `None` if the seed is not valid hex
+    pub fn take_master_seed(&mut self) -> Option<MasterSeed> {
+        let master_seed = mem::replace(&mut self.master_seed, String::new());
+        let decoded = decode_hex(&master_seed).ok().map(MasterSeed::new);
+        let mut master_seed = master_seed.into_bytes();
+        wipe(&mut master_seed);
+        decoded
+    }
+}
+
+impl Drop for SeedRecord {
+    fn drop(&mut self) {
+        unsafe { wipe(self.master_seed.as_mut_vec()) };
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DerivationIndexRecord {
+    pub owner_id: UserId,
+    pub currency: Currency,
+    pub purpose: i32,
+    pub next_index: i32,
+}
+
+impl DerivationIndexRecord {
+    pub fn new(index: &DerivationIndex) -> Self {
+        DerivationIndexRecord {
+            owner_id: index.owner_id.clone(),
+            currency: index.currency,
+            purpose: index.purpose,
+            next_index: index.next_index,
+        }
+    }
+
+    pub fn into_derivation_index(self) -> DerivationIndex {
+        DerivationIndex {
+            owner_id: self.owner_id,
+            currency: self.currency,
+            next_index: self.next_index,
+            purpose: self.purpose,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Frame {
+    User(UserRecord),
+    Key(KeyRecord),
+    Seed(SeedRecord),
+    DerivationIndex(DerivationIndexRecord),
+    End { records: u64, checksum: String },
+}
+
+impl Frame {
+    fn is_end(&self) -> bool {
+        match *self {
+            Frame::End { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+/// Writes records to backup, `finish` must be called after the last one
+pub struct BackupWriter<W: Write> {
+    writer: W,
+    key: Vec<u8>,
+    header: Vec<u8>,
+    records: u64,
+    hasher: Sha256,
+}
+
+impl<W: Write> BackupWriter<W> {
+    pub fn new(writer: W, passphrase: &str) -> Result<Self, Error> {
+        Self::with_scrypt_log_n(writer, passphrase, SCRYPT_LOG_N)
+    }
+
+    /// Same as `new` with scrypt cost `2^log_n`, lower cost makes passphrase easier to brute force
+    pub fn with_scrypt_log_n(mut writer: W, passphrase: &str, log_n: u8) -> Result<Self, Error> {
+        if log_n == 0 || log_n > MAX_SCRYPT_LOG_N {
+            return Err(ectx!(err ErrorContext::KdfCost, ErrorKind::Internal => log_n));
+        }
+        let mut random = rand::OsRng::new().map_err(ectx!(try ErrorKind::Internal))?;
+        let mut salt = [0u8; SALT_LEN];
+        random.fill_bytes(&mut salt);
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+        random.fill_bytes(&mut nonce_prefix);
+        let mut header = MAGIC.to_vec();
+        header.push(VERSION);
+        header.push(log_n);
+        header.extend_from_slice(&salt);
+        header.extend_from_slice(&nonce_prefix);
+        writer.write_all(&header).map_err(ectx!(try ErrorKind::Io))?;
+        Ok(BackupWriter {
+            writer,
+            key: derive_key(passphrase, &salt, log_n),
+            header,
+            records: 0,
+            hasher: Sha256::new(),
+        })
+    }
+
+    pub fn write(&mut self, record: Record) -> Result<(), Error> {
+        let frame = match record {
+            Record::User(user) => Frame::User(user),
+            Record::Key(key) => Frame::Key(key),
+            Record::Seed(seed) => Frame::Seed(seed),
+            Record::DerivationIndex(index) => Frame::DerivationIndex(index),
+        };
+        self.write_frame(&frame)?;
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Writes the last record with checksum, backup can't be restored without it
+    pub fn finish(mut self) -> Result<(), Error> {
+        let frame = Frame::End {
+            records: self.records,
+            checksum: self.hasher.result_str(),
+        };
+        self.write_frame(&frame)?;
+        self.writer.flush().map_err(ectx!(ErrorKind::Io))
+    }
+
+    fn write_frame(&mut self, frame: &Frame) -> Result<(), Error> {
+        let mut plaintext = serde_json::to_vec(frame).map_err(|_| ectx!(try err ErrorContext::Json, ErrorKind::Internal))?;
+        let ciphertext = encrypt_aead(&plaintext, &self.key, &nonce(&self.header, self.records), &self.header);
+        if !frame.is_end() {
+            self.hasher.input(&plaintext);
+        }
+        wipe(&mut plaintext);
+        let len = ciphertext.len() as u32;
+        let len = [(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8];
+        self.writer.write_all(&len).map_err(ectx!(try ErrorKind::Io))?;
+        self.writer.write_all(&ciphertext).map_err(ectx!(ErrorKind::Io))
+    }
+}
+
+impl<W: Write> Drop for BackupWriter<W> {
+    fn drop(&mut self) {
+        wipe(&mut self.key);
+    }
+}
+
+/// Reads records from backup, verifying the checksum after the last one
+pub struct BackupReader<R: Read> {
+    reader: R,
+    key: Vec<u8>,
+    header: Vec<u8>,
+    records: u64,
+    hasher: Sha256,
+    finished: bool,
+}
+
+impl<R: Read> BackupReader<R> {
+    pub fn new(mut reader: R, passphrase: &str) -> Result<Self, Error> {
+        let mut header = vec![0u8; HEADER_LEN];
+        reader
+            .read_exact(&mut header)
+            .map_err(ectx!(try ErrorContext::Header, ErrorKind::Malformed))?;
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(ectx!(err ErrorContext::Header, ErrorKind::Malformed));
+        }
+        let version = header[MAGIC.len()];
+        if version != VERSION {
+            return Err(ectx!(err ErrorContext::Version, ErrorKind::Malformed => version));
+        }
+        let log_n = header[MAGIC.len() + 1];
+        if log_n == 0 || log_n > MAX_SCRYPT_LOG_N {
+            return Err(ectx!(err ErrorContext::KdfCost, ErrorKind::Malformed => log_n));
+        }
+        let key = derive_key(passphrase, &header[MAGIC.len() + 2..MAGIC.len() + 2 + SALT_LEN], log_n);
+        Ok(BackupReader {
+            reader,
+            key,
+            header,
+            records: 0,
+            hasher: Sha256::new(),
+            finished: false,
+        })
+    }
+
+    /// Reads next record, returns `None` after the last one
+    pub fn read(&mut self) -> Result<Option<Record>, Error> {
+        if self.finished {
+            return Ok(None);
+        }
+        let mut len = [0u8; 4];
+        self.reader
+            .read_exact(&mut len)
+            .map_err(ectx!(try ErrorContext::Truncated, ErrorKind::Malformed))?;
+        let len = len.iter().fold(0usize, |acc, byte| (acc << 8) | *byte as usize);
+        if len > MAX_RECORD_LEN {
+            return Err(ectx!(err ErrorContext::RecordLength, ErrorKind::Malformed => len));
+        }
+        let mut ciphertext = vec![0u8; len];
+        self.reader
+            .read_exact(&mut ciphertext)
+            .map_err(ectx!(try ErrorContext::Truncated, ErrorKind::Malformed))?;
+        let records = self.records;
+        let mut plaintext = decrypt_aead(&ciphertext, &self.key, &nonce(&self.header, records), &self.header)
+            .ok_or(ectx!(try err ErrorContext::Authentication, ErrorKind::Decryption => records))?;
+        // Serde errors may contain parts of the record, so they are not attached
+        let frame = serde_json::from_slice::<Frame>(&plaintext);
+        if !frame.as_ref().map(Frame::is_end).unwrap_or(false) {
+            self.hasher.input(&plaintext);
+        }
+        wipe(&mut plaintext);
+        match frame.map_err(|_| ectx!(try err ErrorContext::Json, ErrorKind::Malformed => records))? {
+            Frame::User(user) => {
+                self.records += 1;
+                Ok(Some(Record::User(user)))
+            }
+            Frame::Key(key) => {
+                self.records += 1;
+                Ok(Some(Record::Key(key)))
+            }
+            Frame::Seed(seed) => {
+                self.records += 1;
+                Ok(Some(Record::Seed(seed)))
+            }
+            Frame::DerivationIndex(index) => {
+                self.records += 1;
+                Ok(Some(Record::DerivationIndex(index)))
+            }
+            Frame::End { records, checksum } => {
+                if records != self.records || checksum != self.hasher.result_str() {
+                    return Err(ectx!(err ErrorContext::Checksum, ErrorKind::Malformed => records));
+                }
+                self.finished = true;
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl<R: Read> Drop for BackupReader<R> {
+    fn drop(&mut self) {
+        wipe(&mut self.key);
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], log_n: u8) -> Vec<u8> {
+    let mut key = vec![0u8; 32];
+    scrypt(passphrase.as_bytes(), salt, &ScryptParams::new(log_n, 8, 1), &mut key);
+    key
+}
+
+/// Nonce prefix from the header followed by big-endian record number
+fn nonce(header: &[u8], record: u64) -> [u8; AEAD_NONCE_LEN] {
+    let mut nonce = [0u8; AEAD_NONCE_LEN];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(&header[HEADER_LEN - NONCE_PREFIX_LEN..]);
+    for i in 0..8 {
+        nonce[NONCE_PREFIX_LEN + i] = (record >> (56 - 8 * i)) as u8;
+    }
+    nonce
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    const PASSPHRASE: &str = "correct horse battery staple";
+
+    fn create_backup() -> Vec<u8> {
+        let mut buffer = Vec::new();
+        {
+            let mut backup = BackupWriter::with_scrypt_log_n(&mut buffer, PASSPHRASE, 1).unwrap();
+            let user = User::default();
+            let key = KeyRecord {
+                id: KeyId::default(),
+                currency: Currency::Eth,
+                blockchain_address: BlockchainAddress::new("d8f1c5b7a2e4d6f8091a2b3c4d5e6f708192a3b4".to_string()),
+                owner_id: user.id.clone(),
+                derivation_path: None,
+                status: KeyStatus::Disabled,
+                label: Some(KeyLabel::new("cold".to_string())),
+                metadata: KeyMetadata::default(),
+                wallet_type: None,
+                created_at: Some(UNIX_EPOCH + Duration::from_secs(1_500_000_000)),
+                last_used_at: Some(UNIX_EPOCH + Duration::from_secs(1_500_086_400)),
+                signatures_count: 7,
+                private_key: "e1b8c31f5ea3d7a0b5c0e8a9d8f1c5b7a2e4d6f8091a2b3c4d5e6f708192a3b4".to_string(),
+            };
+            let seed = SeedRecord {
+                owner_id: user.id.clone(),
+                master_seed: "000102030405060708090a0b0c0d0e0f".to_string(),
+            };
+            let index = DerivationIndexRecord {
+                owner_id: user.id.clone(),
+                currency: Currency::Btc,
+                purpose: 84,
+                next_index: 3,
+            };
+            let btc_key = KeyRecord {
+                id: KeyId::default(),
+                currency: Currency::Btc,
+                blockchain_address: BlockchainAddress::new("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4".to_string()),
+                owner_id: user.id.clone(),
+                derivation_path: None,
+                status: KeyStatus::Active,
+                label: None,
+                metadata: KeyMetadata::default(),
+                wallet_type: Some(WalletType::P2wpkh),
+                created_at: None,
+                last_used_at: None,
+                signatures_count: 0,
+                private_key: "KwDiBf89QgGbjEhKnhXJuH7LrciVrZi3qYjgd9M7rFU73sVHnoWn".to_string(),
+            };
+            backup.write(Record::User(UserRecord::from_user(&user))).unwrap();
+            backup.write(Record::Seed(seed)).unwrap();
+            backup.write(Record::DerivationIndex(index)).unwrap();
+            backup.write(Record::Key(key)).unwrap();
+            backup.write(Record::Key(btc_key)).unwrap();
+            backup.finish().unwrap();
+        }
+        buffer
+    }
+
+    fn read_all(data: &[u8], passphrase: &str) -> Result<Vec<Record>, Error> {
+        let mut backup = BackupReader::new(Cursor::new(data), passphrase)?;
+        let mut records = Vec::new();
+        while let Some(record) = backup.read()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn test_backup_restore() {
+        let data = create_backup();
+        let mut records = read_all(&data, PASSPHRASE).unwrap();
+        assert_eq!(records.len(), 5);
+        match records.pop() {
+            Some(Record::Key(key)) => {
+                assert_eq!(key.currency, Currency::Btc);
+                assert_eq!(key.wallet_type, Some(WalletType::P2wpkh));
+            }
+            _ => panic!("expected key record"),
+        }
+        match records.pop() {
+            Some(Record::Key(mut key)) => {
+                assert_eq!(key.status, KeyStatus::Disabled);
+                assert_eq!(key.wallet_type, None);
+                assert_eq!(key.created_at, Some(UNIX_EPOCH + Duration::from_secs(1_500_000_000)));
+                assert_eq!(key.last_used_at, Some(UNIX_EPOCH + Duration::from_secs(1_500_086_400)));
+                assert_eq!(key.signatures_count, 7);
+                assert_eq!(
+                    key.take_private_key().raw(),
+                    "e1b8c31f5ea3d7a0b5c0e8a9d8f1c5b7a2e4d6f8091a2b3c4d5e6f708192a3b4"
+                );
+            }
+            _ => panic!("expected key record"),
+        }
+        match records.pop() {
+            Some(Record::DerivationIndex(index)) => {
+                let index = index.into_derivation_index();
+                assert_eq!((index.currency, index.purpose, index.next_index), (Currency::Btc, 84, 3));
+            }
+            _ => panic!("expected derivation index record"),
+        }
+        match records.pop() {
+            Some(Record::Seed(mut seed)) => assert_eq!(
+                seed.take_master_seed().unwrap().as_bytes(),
+                &decode_hex("000102030405060708090a0b0c0d0e0f").unwrap()[..]
+            ),
+            _ => panic!("expected seed record"),
+        }
+        match records.pop() {
+            Some(Record::User(user)) => assert_eq!(user.name, "Anonymous"),
+            _ => panic!("expected user record"),
+        }
+    }
+
+    #[test]
+    fn test_invalid_backup() {
+        let data = create_backup();
+        let error = read_all(&data, "wrong passphrase").err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::Decryption);
+        let error = read_all(&data[..data.len() - 10], PASSPHRASE).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::Malformed);
+        let mut tampered = data.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        let error = read_all(&tampered, PASSPHRASE).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::Decryption);
+        let mut other_version = data.clone();
+        other_version[MAGIC.len()] = VERSION + 1;
+        let error = read_all(&other_version, PASSPHRASE).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::Malformed);
+    }
+}
@@ -0,0 +1,241 @@
+//! Bech32 encoding of SegWit addresses, https://github.com/bitcoin/bips/blob/master/bip-0173.mediawiki
+//! and its bech32m variant for witness versions 1 and later, https://github.com/bitcoin/bips/blob/master/bip-0350.mediawiki
+
+const CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const GENERATOR: [u32; 5] = [0x3b6a_57b2, 0x2650_8e6d, 0x1ea1_19fa, 0x3d42_33dd, 0x2a14_62b3];
+const CHECKSUM_LEN: usize = 6;
+const MAX_LEN: usize = 90;
+const BECH32_CONST: u32 = 1;
+const BECH32M_CONST: u32 = 0x2bc8_30a3;
+
+/// Checksum variant, it's decided by witness version
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Variant {
+    Bech32,
+    Bech32m,
+}
+
+impl Variant {
+    fn for_version(version: u8) -> Self {
+        if version == 0 {
+            Variant::Bech32
+        } else {
+            Variant::Bech32m
+        }
+    }
+
+    fn constant(self) -> u32 {
+        match self {
+            Variant::Bech32 => BECH32_CONST,
+            Variant::Bech32m => BECH32M_CONST,
+        }
+    }
+}
+
+/// Encodes witness program as SegWit address, e.g. `bc1q...` for version 0 and `bc1p...` for version 1 on mainnet
+pub fn encode_segwit_address(hrp: &str, version: u8, program: &[u8]) -> String {
+    let mut data = vec![version];
+    // conversion to 5 bit groups with padding can't fail
+    data.extend(convert_bits(program, 8, 5, true).unwrap_or_default());
+    encode(hrp, &data, Variant::for_version(version))
+}
+
+/// Decodes SegWit address with human readable part `hrp` into witness version and program.
+/// Returns `None` if the address is malformed, has a different `hrp` or an invalid checksum,
+/// including bech32 checksum of version 1+ address and bech32m checksum of version 0 address.
+pub fn decode_segwit_address(hrp: &str, address: &str) -> Option<(u8, Vec<u8>)> {
+    let (address_hrp, data, variant) = decode(address)?;
+    if address_hrp != hrp || data.is_empty() || variant != Variant::for_version(data[0]) {
+        return None;
+    }
+    let version = data[0];
+    let program = convert_bits(&data[1..], 5, 8, false)?;
+    if version > 16 || program.len() < 2 || program.len() > 40 {
+        return None;
+    }
+    // version 0 programs are either public key hashes or script hashes
+    if version == 0 && program.len() != 20 && program.len() != 32 {
+        return None;
+    }
+    Some((version, program))
+}
+
+/// Encodes 5 bit groups `data` with checksum
+fn encode(hrp: &str, data: &[u8], variant: Variant) -> String {
+    let checksum = create_checksum(hrp, data, variant);
+    let mut result = format!("{}1", hrp);
+    for value in data.iter().chain(checksum.iter()) {
+        result.push(CHARSET[*value as usize] as char);
+    }
+    result
+}
+
+/// Decodes string into human readable part, 5 bit groups of data without checksum and checksum variant
+fn decode(s: &str) -> Option<(String, Vec<u8>, Variant)> {
+    if s.len() > MAX_LEN || !s.is_ascii() {
+        return None;
+    }
+    // mixed case is not allowed
+    if s.to_lowercase() != s && s.to_uppercase() != s {
+        return None;
+    }
+    let s = s.to_lowercase();
+    let separator = s.rfind('1')?;
+    if separator == 0 || separator + CHECKSUM_LEN + 1 > s.len() {
+        return None;
+    }
+    let (hrp, data) = (&s[..separator], &s[separator + 1..]);
+    if hrp.bytes().any(|c| c < 33 || c > 126) {
+        return None;
+    }
+    let values: Option<Vec<u8>> = data
+        .bytes()
+        .map(|c| CHARSET.iter().position(|x| *x == c).map(|position| position as u8))
+        .collect();
+    let mut values = values?;
+    let variant = checksum_variant(hrp, &values)?;
+    let data_len = values.len() - CHECKSUM_LEN;
+    values.truncate(data_len);
+    Some((hrp.to_string(), values, variant))
+}
+
+fn polymod(values: &[u8]) -> u32 {
+    let mut checksum: u32 = 1;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = ((checksum & 0x01ff_ffff) << 5) ^ u32::from(*value);
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut result: Vec<u8> = hrp.bytes().map(|c| c >> 5).collect();
+    result.push(0);
+    result.extend(hrp.bytes().map(|c| c & 31));
+    result
+}
+
+fn create_checksum(hrp: &str, data: &[u8], variant: Variant) -> Vec<u8> {
+    let mut values = hrp_expand(hrp);
+    values.extend_from_slice(data);
+    values.extend_from_slice(&[0; CHECKSUM_LEN]);
+    let polymod = polymod(&values) ^ variant.constant();
+    (0..CHECKSUM_LEN).map(|i| ((polymod >> (5 * (5 - i))) & 31) as u8).collect()
+}
+
+/// Returns `None` if checksum is invalid in both variants
+fn checksum_variant(hrp: &str, data: &[u8]) -> Option<Variant> {
+    let mut values = hrp_expand(hrp);
+    values.extend_from_slice(data);
+    match polymod(&values) {
+        BECH32_CONST => Some(Variant::Bech32),
+        BECH32M_CONST => Some(Variant::Bech32m),
+        _ => None,
+    }
+}
+
+/// Regroups bits of `data` from `from` bit groups to `to` bit groups
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+    let mut acc: u32 = 0;
+    let mut bits: u32 = 0;
+    let mut result = Vec::new();
+    let max_value = (1 << to) - 1;
+    for value in data {
+        let value = u32::from(*value);
+        if value >> from != 0 {
+            return None;
+        }
+        acc = (acc << from) | value;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            result.push(((acc >> bits) & max_value) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            result.push(((acc << (to - bits)) & max_value) as u8);
+        }
+    } else if bits >= from || ((acc << (to - bits)) & max_value) != 0 {
+        return None;
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::{decode_hex, encode_hex};
+
+    // https://github.com/bitcoin/bips/blob/master/bip-0350.mediawiki#test-vectors-for-v0-v16-native-segregated-witness-addresses
+    #[test]
+    fn test_segwit_address() {
+        let cases = [
+            (
+                "bc",
+                "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4",
+                0,
+                "751e76e8199196d454941c45d1b3a323f1433bd6",
+            ),
+            (
+                "tb",
+                "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7",
+                0,
+                "1863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262",
+            ),
+            (
+                "bc",
+                "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0",
+                1,
+                "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            ),
+            (
+                "tb",
+                "tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c",
+                1,
+                "000000c4a5cad46221b2a187905e5266362b99d5e91c6ce24d165dab93e86433",
+            ),
+            ("bc", "bc1zw508d6qejxtdg4y5r3zarvaryvaxxpcs", 2, "751e76e8199196d454941c45d1b3a323"),
+            ("bc", "BC1SW50QGDZ25J", 16, "751e"),
+        ];
+        for (hrp, address, version, program) in &cases {
+            let (decoded_version, decoded) = decode_segwit_address(hrp, address).unwrap();
+            assert_eq!(decoded_version, *version);
+            assert_eq!(encode_hex(&decoded), *program);
+            let program = decode_hex(program).unwrap();
+            assert_eq!(encode_segwit_address(hrp, *version, &program), address.to_lowercase());
+        }
+    }
+
+    #[test]
+    fn test_invalid_segwit_address() {
+        let cases = [
+            // wrong network
+            ("tb", "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"),
+            // invalid checksum
+            ("bc", "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t5"),
+            // mixed case
+            ("tb", "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3Q0sl5k7"),
+            // invalid program length for version 0
+            ("bc", "BC1QR508D6QEJXTDG4Y5R3ZARVARYV98GJ9P"),
+            // invalid character
+            ("bc", "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3tb"),
+            // empty data
+            ("bc", "bc1gmk9yu"),
+            // bech32 checksum of version 1 address
+            ("bc", "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqh2y7hd"),
+            // bech32m checksum of version 0 address
+            ("bc", "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kemeawh"),
+            // invalid witness version
+            ("bc", "BC130XLXVLHEMJA6C4DQV22UAPCTQUPFHLXM9H8Z3K2E72Q4K9HCZ7VQ7ZWS8R"),
+        ];
+        for (hrp, address) in &cases {
+            assert_eq!(decode_segwit_address(hrp, address), None, "{}", address);
+        }
+    }
+}
@@ -1,21 +1,31 @@
 use btcchain::{OutPoint, Transaction, TransactionInput, TransactionOutput};
-use btccrypto::sha256;
+use btccrypto::dhash160;
 use btckey::generator::{Generator, Random};
 use btckey::{Address, DisplayLayout, Error as BtcKeyError, KeyPair, Network, Private as BtcPrivateKey, Type as AddressType};
-use btcprimitives::hash::H256;
-use btcscript::Builder as ScriptBuilder;
-use btcserialization::serialize;
+use btcprimitives::bytes::Bytes;
+use btcprimitives::hash::{H160, H256};
+use btcscript::{Builder as ScriptBuilder, Opcode, Script, Sighash, SignatureVersion, TransactionInputSigner};
+use btcserialization::{serialize, serialize_with_flags, SERIALIZE_TRANSACTION_WITNESS};
 use config::BtcNetwork;
 use failure::err_msg;
 
+use super::bech32::{decode_segwit_address, encode_segwit_address};
+use super::coin_selection::{coin_selector, FeeModel};
 use super::error::*;
-use super::utils::{bytes_to_hex, hex_to_bytes};
+use super::hd::{derive_secret, BTC_COIN_TYPE, BTC_TESTNET_COIN_TYPE};
+use super::utils::{bytes_to_hex, malformed_address_prefix, malformed_private_key};
 use super::BlockchainService;
 use models::*;
 use prelude::*;
+use utils::{decode_hex, wipe};
+
+const BASE58_ALPHABET: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const SIGHASH_ALL: u32 = 1;
 
 pub struct BitcoinService {
     btc_network: BtcNetwork,
+    /// Used for transactions that don't set their own
+    coin_selection: CoinSelection,
 }
 
 impl BitcoinService {
@@ -23,7 +33,7 @@ impl BitcoinService {
     // https://bitcoin.stackexchange.com/questions/3374/how-to-redeem-a-basic-tx
     fn sign_with_options(
         &self,
-        key: PrivateKey,
+        key: &PrivateKey,
         input_tx: UnsignedTransaction,
         rbf: bool,
         lock_time: Option<u32>,
@@ -35,25 +45,43 @@ impl BitcoinService {
             ErrorKind::InvalidUnsignedTransaction(error).into()
         })?;
 
-        let (input_utxos, amount) = (input_tx.utxos.clone().unwrap_or_default(), input_tx.value);
-        let utxos = self
-            .needed_utxos(&input_utxos, amount)?
-            .ok_or(ectx!(try err ErrorContext::WrongInputs, ErrorKind::InvalidUnsignedTransaction(ValidationError::NotEnoughUtxo) => input_utxos, amount))?;
-
-        let from_address = input_tx.from.clone().into_inner();
-        let address_from: Address = from_address.parse().map_err::<Error, _>(|cause: BtcKeyError| {
-            let cause = format_err!("{}", cause);
-            let error = ValidationError::MalformedAddress { value: from_address };
-            ectx!(err cause, ErrorKind::InvalidUnsignedTransaction(error))
-        })?;
-        if address_from.kind != AddressType::P2PKH {
-            let error = ValidationError::UnsupportedAddressType {
-                value: String::from("P2SH"),
-            };
-            return Err(ErrorKind::InvalidUnsignedTransaction(error).into());
+        let input_utxos = input_tx.utxos.clone().unwrap_or_default();
+        for utxo in &input_utxos {
+            utxo.value.u64().ok_or::<Error>({
+                let error = ValidationError::Overflow {
+                    number: utxo.value.inner().to_string(),
+                };
+                ErrorKind::InvalidUnsignedTransaction(error).into()
+            })?;
         }
-        let address_from_hash = address_from.hash;
-        let script_sig = ScriptBuilder::build_p2pkh(&address_from_hash);
+
+        let keypair = private_key_to_keypair(key)?;
+        let public = keypair.public();
+        let (wallet_type, address_from_hash) = self.parse_from_address(&input_tx.from, &keypair)?;
+        let script_sig = match wallet_type {
+            // Legacy inputs get script_sig once the transaction is complete and signed
+            WalletType::P2pkh => Bytes::default(),
+            // SegWit inputs are signed in witness
+            WalletType::P2wpkh => Bytes::default(),
+            // P2SH input only reveals the redeem script, that passes signing on to witness
+            WalletType::P2shP2wpkh => ScriptBuilder::default()
+                .push_bytes(&build_p2wpkh(&public.address_hash()))
+                .into_script()
+                .to_bytes(),
+        };
+        let output_script = self.output_script(&input_tx.to)?;
+        // Change goes back to the address of the key
+        let change_script = match wallet_type {
+            WalletType::P2pkh => ScriptBuilder::build_p2pkh(&address_from_hash),
+            WalletType::P2wpkh => build_p2wpkh(&address_from_hash),
+            WalletType::P2shP2wpkh => ScriptBuilder::build_p2sh(&address_from_hash),
+        };
+
+        let fee_model = fee_model(wallet_type, input_tx.fee_price, public.len(), &output_script, &change_script);
+        let coin_selection = input_tx.coin_selection.unwrap_or(self.coin_selection);
+        let utxos = coin_selector(coin_selection)
+            .select(&input_utxos, input_value, &fee_model)
+            .ok_or(ectx!(try err ErrorContext::WrongInputs, ErrorKind::InvalidUnsignedTransaction(ValidationError::NotEnoughUtxo) => input_utxos, input_value))?;
 
         let inputs: Result<Vec<TransactionInput>, Error> = utxos
             .iter()
@@ -71,220 +99,937 @@ impl BitcoinService {
                 let sequence = if rbf { u32::max_value() - 2 } else { u32::max_value() };
                 Ok(TransactionInput {
                     previous_output: outpoint,
-                    script_sig: script_sig.to_bytes(),
+                    script_sig: script_sig.clone(),
                     sequence,
                     script_witness: vec![],
                 })
             })
             .collect();
         let inputs = inputs?;
-        let to_address = input_tx.to.clone().into_inner();
-        let address_to = to_address.parse::<Address>().map_err::<Error, _>(|cause| {
-            let cause = err_msg(cause.to_string());
-            let error = ValidationError::MalformedAddress { value: to_address };
-            ectx!(err cause, ErrorKind::InvalidUnsignedTransaction(error))
-        })?;
 
-        let address_to_hash = address_to.hash;
-
-        let output_script = match address_to.kind {
-            AddressType::P2PKH => ScriptBuilder::build_p2pkh(&address_to_hash),
-            AddressType::P2SH => ScriptBuilder::build_p2sh(&address_to_hash),
-        };
-
-        let output = TransactionOutput {
-            value: input_value,
-            script_pubkey: output_script.to_bytes(),
-        };
-        let mut outputs = vec![output.clone()];
         let maybe_sum_inputs = utxos
             .iter()
             .fold(Some(Amount::new(0)), |acc, utxo| acc.and_then(|a| a.checked_add(utxo.value)));
         let sum_inputs = maybe_sum_inputs
             .and_then(|sum| sum.u64())
             .ok_or(ectx!(try err ErrorContext::Overflow, ErrorKind::Internal))?;
-        // Need to be strictly greater since we need to include fees as well
-        if sum_inputs <= output.value {
+        let fees = fee_model.fee(inputs.len(), false);
+        if sum_inputs < input_value.saturating_add(fees) {
             return Err(
-                ectx!(err ErrorContext::WrongInputs, ErrorKind::InvalidUnsignedTransaction(ValidationError::NotEnoughUtxo) => sum_inputs, output.value),
+                ectx!(err ErrorContext::WrongFee, ErrorKind::InvalidUnsignedTransaction(ValidationError::NotEnoughUtxo) => sum_inputs, input_value, fees),
             );
         };
-        let rest = sum_inputs - output.value;
-        let script = ScriptBuilder::build_p2pkh(&address_from_hash);
-        let output = TransactionOutput {
-            value: rest as u64,
-            script_pubkey: script.to_bytes(),
-        };
-        outputs.push(output);
+        let mut outputs = vec![TransactionOutput {
+            value: input_value,
+            script_pubkey: output_script.to_bytes(),
+        }];
+        // Fees are deducted from change, change that isn't worth an output is left to miners
+        let rest = sum_inputs - input_value;
+        let fees = fee_model.fee(inputs.len(), true);
+        if rest > fees && rest - fees > fee_model.cost_of_change() {
+            outputs.push(TransactionOutput {
+                value: rest - fees,
+                script_pubkey: change_script.to_bytes(),
+            });
+        }
         let mut tx = Transaction {
             version: 1,
-            inputs: inputs.clone(),
-            outputs: outputs,
+            inputs,
+            outputs,
             lock_time: lock_time.unwrap_or(0),
         };
-        // Estimating fees and deduct them from the last output (the one with address equal to input)
-        let tx_raw = serialize(&tx).take();
-        let fees = self.estimate_fees(input_tx.fee_price, inputs.len() as u64, tx_raw.len() as u64);
-        let outputs_len = tx.outputs.len();
-        {
-            let output_ref = tx
-                .outputs
-                .get_mut(outputs_len - 1)
-                .ok_or(ectx!(try err ErrorContext::NoTxOutputs, ErrorKind::Internal))?;
-            if fees >= output_ref.value {
-                return Err(
-                    ectx!(err ErrorContext::WrongFee, ErrorKind::InvalidUnsignedTransaction(ValidationError::NotEnoughUtxo) => tx_raw, fees, output_ref.value),
-                );
+        if wallet_type.is_segwit() {
+            let signer = TransactionInputSigner::from(tx.clone());
+            for (index, utxo) in utxos.iter().enumerate() {
+                let amount = utxo
+                    .value
+                    .u64()
+                    .ok_or(ectx!(try err ErrorContext::Overflow, ErrorKind::Internal => utxo.value))?;
+                tx.inputs[index].script_witness = p2wpkh_witness(&signer, index, amount, &keypair)?;
             }
-            output_ref.value -= fees;
-        };
-        // Calculating signature to insert into inputs script
+            let tx_raw = serialize_with_flags(&tx, SERIALIZE_TRANSACTION_WITNESS).take();
+            return Ok(RawTransaction::new(bytes_to_hex(&tx_raw)));
+        }
+        // Every input signs its own hash, where only this input has script_sig set to script of its previous output
+        let signer = TransactionInputSigner::from(tx.clone());
+        let prevout_script = ScriptBuilder::build_p2pkh(&address_from_hash);
+        let sighash = Sighash::from_u32(SignatureVersion::Base, SIGHASH_ALL);
+        for (index, input_ref) in tx.inputs.iter_mut().enumerate() {
+            let hash = signer.signature_hash_original(index, &prevout_script, SIGHASH_ALL, sighash);
+            let signature_with_sighash = sign_hash(&keypair, &hash)?;
+            input_ref.script_sig = ScriptBuilder::default()
+                .push_bytes(&signature_with_sighash)
+                .push_bytes(&*public)
+                .into_bytes();
+        }
         let tx_raw = serialize(&tx).take();
-        let mut tx_raw_with_sighash = tx_raw.clone();
-        // SIGHASH_ALL
-        tx_raw_with_sighash.extend([1, 0, 0, 0].iter());
-        let tx_hash = sha256(&sha256(&tx_raw_with_sighash).take());
-
-        let pk = hex_to_bytes(key.clone().into_inner()).map_err::<Error, _>(|cause| {
-            let error = ValidationError::MalformedPrivateKey {
-                value: key.clone().into_inner(),
-            };
-            ectx!(err cause, ErrorKind::InvalidPrivateKey(error))
-        })?;
-
-        let pk = BtcPrivateKey::from_layout(&pk).map_err::<Error, _>(|cause| {
-            let cause = err_msg(cause.to_string());
-            let error = ValidationError::MalformedPrivateKey {
-                value: key.clone().into_inner(),
-            };
-            ectx!(err cause, ErrorKind::InvalidPrivateKey(error))
-        })?;
+        let tx_raw_hex = bytes_to_hex(&tx_raw);
+        Ok(RawTransaction::new(tx_raw_hex))
+    }
 
-        let keypair = KeyPair::from_private(pk).map_err::<Error, _>(|cause| {
-            let cause = err_msg(cause.to_string());
-            let error = ValidationError::MalformedPrivateKey {
-                value: key.clone().into_inner(),
-            };
-            ectx!(err cause, ErrorKind::InvalidPrivateKey(error))
+    /// Address of the key that decides how inputs are signed: base58 P2PKH, bech32 P2WPKH or base58 P2SH
+    /// of the key's P2WPKH redeem script. Returns the hash of the address.
+    fn parse_from_address(&self, address: &BlockchainAddress, keypair: &KeyPair) -> Result<(WalletType, H160), Error> {
+        let from_address = address.clone().into_inner();
+        if let Some((version, program)) = decode_segwit_address(self.bech32_hrp(), &from_address) {
+            if version != 0 || program.len() != 20 {
+                let error = ValidationError::UnsupportedAddressType {
+                    value: format!("witness v{} program of {} bytes", version, program.len()),
+                };
+                return Err(ErrorKind::InvalidUnsignedTransaction(error).into());
+            }
+            return Ok((WalletType::P2wpkh, H160::from(&program[..])));
+        }
+        let address_from: Address = from_address.parse().map_err::<Error, _>(|cause: BtcKeyError| {
+            let cause = format_err!("{}", cause);
+            let error = ValidationError::MalformedAddress { value: from_address };
+            ectx!(err cause, ErrorKind::InvalidUnsignedTransaction(error))
         })?;
+        match address_from.kind {
+            AddressType::P2PKH => Ok((WalletType::P2pkh, address_from.hash)),
+            // other scripts can't be signed with a single key
+            AddressType::P2SH if address_from.hash == p2sh_p2wpkh_hash(keypair) => Ok((WalletType::P2shP2wpkh, address_from.hash)),
+            AddressType::P2SH => {
+                let error = ValidationError::UnsupportedAddressType {
+                    value: String::from("P2SH"),
+                };
+                Err(ErrorKind::InvalidUnsignedTransaction(error).into())
+            }
+        }
+    }
 
-        let signature = keypair.private().sign(&tx_hash).map_err::<Error, _>(|cause| {
+    /// Script of output paying to base58 or bech32 address, addresses of other networks are malformed
+    fn output_script(&self, address: &BlockchainAddress) -> Result<Script, Error> {
+        let to_address = address.clone().into_inner();
+        if let Some((version, program)) = decode_segwit_address(self.bech32_hrp(), &to_address) {
+            // P2WPKH and P2WSH for version 0, P2TR and future versions are paid the same way
+            return Ok(build_witness_program(version, &program));
+        }
+        let address_to = to_address.parse::<Address>().map_err::<Error, _>(|cause| {
             let cause = err_msg(cause.to_string());
-            ectx!(err cause, ErrorContext::Signature, ErrorKind::Internal => tx_hash)
+            let error = ValidationError::MalformedAddress { value: to_address.clone() };
+            ectx!(err cause, ErrorKind::InvalidUnsignedTransaction(error))
         })?;
-        let mut signature_with_sighash = signature.to_vec();
-        // SIGHASH_ALL
-        signature_with_sighash.push(1);
-        let public = keypair.public();
-        let script = ScriptBuilder::default()
-            .push_bytes(&signature_with_sighash)
-            .push_bytes(&*public)
-            .into_script();
-        // Updating input_script to have signature
-        for input_ref in tx.inputs.iter_mut() {
-            input_ref.script_sig = script.to_bytes();
+        if address_to.network != self.network() {
+            let error = ValidationError::MalformedAddress { value: to_address };
+            return Err(ErrorKind::InvalidUnsignedTransaction(error).into());
         }
-        let tx_raw = serialize(&tx).take();
-        let tx_raw_hex = bytes_to_hex(&tx_raw);
-        Ok(RawTransaction::new(tx_raw_hex))
+        Ok(match address_to.kind {
+            AddressType::P2PKH => ScriptBuilder::build_p2pkh(&address_to.hash),
+            AddressType::P2SH => ScriptBuilder::build_p2sh(&address_to.hash),
+        })
     }
 
-    fn estimate_fees(&self, fee_price: f64, inputs_count: u64, tx_size: u64) -> u64 {
-        let script_sig_size = 1 + 71 + 1 + 1 + 64;
-        let script_pubkey_size = 3 + 20 + 2;
-        let signature_bytes = (script_sig_size - script_pubkey_size) * inputs_count;
-        let estimated_final_size = (tx_size + signature_bytes) as f64;
-        println!("fee_price = {}, estimated_final_size = {}", fee_price, estimated_final_size);
-        (fee_price * estimated_final_size) as u64
+    fn keypair_address(&self, keypair: &KeyPair, wallet_type: WalletType) -> Result<BlockchainAddress, Error> {
+        // SegWit doesn't allow uncompressed public keys, such keys have to be imported as compressed WIF
+        if wallet_type.is_segwit() && keypair.public().len() != 33 {
+            return Err(malformed_private_key());
+        }
+        let address = match wallet_type {
+            WalletType::P2pkh => format!("{}", keypair.address()),
+            WalletType::P2wpkh => encode_segwit_address(self.bech32_hrp(), 0, &*keypair.public().address_hash()),
+            WalletType::P2shP2wpkh => {
+                let address = Address {
+                    kind: AddressType::P2SH,
+                    network: self.network(),
+                    hash: p2sh_p2wpkh_hash(keypair),
+                };
+                format!("{}", address)
+            }
+        };
+        Ok(BlockchainAddress::new(address))
     }
 }
 
 impl BlockchainService for BitcoinService {
     // https://en.bitcoin.it/wiki/OP_CHECKSIG
     // https://bitcoin.stackexchange.com/questions/3374/how-to-redeem-a-basic-tx
-    fn derive_address(&self, _currency: Currency, key: PrivateKey) -> Result<BlockchainAddress, Error> {
-        let key_bytes = hex_to_bytes(key.clone().into_inner()).map_err::<Error, _>(|cause| {
-            let error = ValidationError::MalformedPrivateKey {
-                value: key.clone().into_inner(),
-            };
-            ectx!(err cause, ErrorKind::InvalidPrivateKey(error))
-        })?;
-        let private: BtcPrivateKey = BtcPrivateKey::from_layout(&key_bytes).map_err::<Error, _>(|cause| {
-            let cause = err_msg(cause.to_string());
-            let error = ValidationError::MalformedPrivateKey {
-                value: key.clone().into_inner(),
-            };
-            ectx!(err cause, ErrorKind::InvalidPrivateKey(error))
-        })?;
-        let keypair = KeyPair::from_private(private).map_err::<Error, _>(|cause| {
-            let cause = err_msg(cause.to_string());
-            let error = ValidationError::MalformedPrivateKey {
-                value: key.clone().into_inner(),
+    fn derive_address(&self, _currency: Currency, wallet_type: Option<WalletType>, key: &PrivateKey) -> Result<BlockchainAddress, Error> {
+        let keypair = private_key_to_keypair(key)?;
+        self.keypair_address(&keypair, wallet_type.unwrap_or_default())
+    }
+
+    fn parse_private_key(&self, _currency: Currency, key: RawPrivateKey) -> Result<PrivateKey, Error> {
+        let private = if key.raw().len() == 64 {
+            let mut bytes = decode_hex(key.raw()).map_err(|_| malformed_private_key())?;
+            // Raw secrets don't say which public key their address is derived from, they are imported
+            // uncompressed as before. Compressed keys are imported as WIF, that keeps the compression flag.
+            let private = BtcPrivateKey {
+                network: self.network(),
+                secret: H256::from(&bytes[..]),
+                compressed: false,
             };
-            ectx!(err cause, ErrorKind::InvalidPrivateKey(error))
-        })?;
-        Ok(BlockchainAddress::new(format!("{}", keypair.address())))
+            wipe(&mut bytes);
+            private
+        } else {
+            let private: BtcPrivateKey = key.raw().parse().map_err(|_| malformed_private_key())?;
+            if private.network != self.network() {
+                return Err(malformed_private_key());
+            }
+            private
+        };
+        Ok(private_to_private_key(&private))
     }
 
-    fn sign(&self, key: PrivateKey, tx: UnsignedTransaction) -> Result<RawTransaction, Error> {
+    fn sign(&self, key: &PrivateKey, tx: UnsignedTransaction) -> Result<RawTransaction, Error> {
         self.sign_with_options(key, tx, false, None)
     }
 
-    fn approve(&self, _key: PrivateKey, _tx: ApproveInput) -> Result<RawTransaction, Error> {
+    fn approve(&self, _key: &PrivateKey, _tx: ApproveInput) -> Result<RawTransaction, Error> {
         unimplemented!()
     }
 
-    fn generate_key(&self, currency: Currency) -> Result<(PrivateKey, BlockchainAddress), Error> {
+    fn export_private_key(&self, _currency: Currency, key: &PrivateKey) -> Result<PrivateKey, Error> {
+        let keypair = private_key_to_keypair(key)?;
+        let mut wif = format!("{}", keypair.private()).into_bytes();
+        let exported = PrivateKey::from_bytes(&wif);
+        wipe(&mut wif);
+        Ok(exported)
+    }
+
+    fn generate_key(&self, currency: Currency, wallet_type: Option<WalletType>) -> Result<(PrivateKey, BlockchainAddress), Error> {
         assert_eq!(currency, Currency::Btc, "unexpected currency: {:?}", currency);
-        let network = match self.btc_network {
-            BtcNetwork::Test => Network::Testnet,
-            BtcNetwork::Main => Network::Mainnet,
-        };
-        let random = Random::new(network);
+        let random = Random::new(self.network());
         let keypair = random.generate().map_err(|e| {
             let e = format_err!("{}", e);
             ectx!(try err e, ErrorSource::Random, ErrorKind::Internal)
         })?;
-        let address = BlockchainAddress::new(format!("{}", keypair.address()));
-        let pk_bytes = bytes_to_hex(&keypair.private().layout());
-        let private_key = PrivateKey::new(pk_bytes);
+        let keypair = compressed_keypair(keypair.private())?;
+        let address = self.keypair_address(&keypair, wallet_type.unwrap_or_default())?;
+        Ok((private_to_private_key(keypair.private()), address))
+    }
+
+    fn generate_vanity_key(
+        &self,
+        currency: Currency,
+        prefix: &str,
+        iterations: usize,
+    ) -> Result<Option<(PrivateKey, BlockchainAddress)>, Error> {
+        assert_eq!(currency, Currency::Btc, "unexpected currency: {:?}", currency);
+        // Base58 address of a 25 bytes payload is at most 34 chars long
+        if prefix.is_empty() || prefix.len() > 34 || !prefix.chars().all(|c| BASE58_ALPHABET.contains(c)) {
+            return Err(malformed_address_prefix(prefix));
+        }
+        // P2PKH addresses start with the network version byte, so other prefixes are never found
+        let first_chars = match self.btc_network {
+            BtcNetwork::Test => "mn",
+            BtcNetwork::Main => "1",
+        };
+        if !prefix.starts_with(|c| first_chars.contains(c)) {
+            return Err(malformed_address_prefix(prefix));
+        }
+        let random = Random::new(self.network());
+        for _ in 0..iterations {
+            let keypair = random.generate().map_err(|e| {
+                let e = format_err!("{}", e);
+                ectx!(try err e, ErrorSource::Random, ErrorKind::Internal)
+            })?;
+            let keypair = compressed_keypair(keypair.private())?;
+            let address = format!("{}", keypair.address());
+            if address.starts_with(prefix) {
+                return Ok(Some((private_to_private_key(keypair.private()), BlockchainAddress::new(address))));
+            }
+        }
+        Ok(None)
+    }
+
+    fn account_derivation_path(&self, _currency: Currency, wallet_type: Option<WalletType>) -> DerivationPath {
+        let coin_type = match self.btc_network {
+            BtcNetwork::Test => BTC_TESTNET_COIN_TYPE,
+            BtcNetwork::Main => BTC_COIN_TYPE,
+        };
+        DerivationPath::account(derivation_purpose(wallet_type), coin_type, 0)
+    }
+
+    fn derive_key(
+        &self,
+        _currency: Currency,
+        wallet_type: Option<WalletType>,
+        seed: &MasterSeed,
+        path: &DerivationPath,
+    ) -> Result<(PrivateKey, BlockchainAddress), Error> {
+        let secret = derive_secret(seed, path)?;
+        let secret_bytes: &[u8] = &**secret;
+        let private = BtcPrivateKey {
+            network: self.network(),
+            secret: H256::from(secret_bytes),
+            // Compressed like random keys and keys of other BIP44 wallets
+            compressed: true,
+        };
+        let private_key = private_to_private_key(&private);
+        let keypair = KeyPair::from_private(private).map_err(|e| {
+            let e = format_err!("{}", e);
+            ectx!(try err e, ErrorContext::Derivation, ErrorKind::Internal)
+        })?;
+        let address = self.keypair_address(&keypair, wallet_type.unwrap_or_default())?;
         Ok((private_key, address))
     }
 }
 
 impl BitcoinService {
-    pub fn new(btc_network: BtcNetwork) -> Self {
-        BitcoinService { btc_network }
+    pub fn new(btc_network: BtcNetwork, coin_selection: CoinSelection) -> Self {
+        BitcoinService {
+            btc_network,
+            coin_selection,
+        }
     }
 
-    fn needed_utxos(&self, utxos: &[Utxo], value: Amount) -> Result<Option<Vec<Utxo>>, Error> {
-        let mut utxos = utxos.to_vec();
-        utxos.sort_by_key(|x| x.value);
-        let mut res = Vec::new();
-        let mut sum = Amount::new(0);
+    fn network(&self) -> Network {
+        match self.btc_network {
+            BtcNetwork::Test => Network::Testnet,
+            BtcNetwork::Main => Network::Mainnet,
+        }
+    }
 
-        for utxo in utxos.iter().rev() {
-            res.push(utxo.clone());
-            sum = sum
-                .checked_add(utxo.value)
-                .ok_or(ectx!(try err ErrorContext::Overflow, ErrorKind::Internal => sum, utxo.value))?;
+    /// Human readable part of bech32 addresses
+    fn bech32_hrp(&self) -> &'static str {
+        match self.btc_network {
+            BtcNetwork::Test => "tb",
+            BtcNetwork::Main => "bc",
         }
+    }
+}
 
-        if sum >= value {
-            return Ok(Some(res));
-        } else {
-            Ok(None)
+/// Private key is stored in layout format, that includes network and compression flag
+fn private_to_private_key(private: &BtcPrivateKey) -> PrivateKey {
+    let mut layout = private.layout();
+    let private_key = PrivateKey::from_bytes(&layout);
+    wipe(&mut layout);
+    private_key
+}
+
+/// New keys have compressed public keys, that make transactions smaller and are required by SegWit.
+/// Stored keys keep their compression flag, so uncompressed keys created before still sign.
+fn compressed_keypair(private: &BtcPrivateKey) -> Result<KeyPair, Error> {
+    let mut private = private.clone();
+    private.compressed = true;
+    KeyPair::from_private(private).map_err(|_| malformed_private_key())
+}
+
+/// Signs hash, signature is DER encoded with SIGHASH_ALL byte appended
+fn sign_hash(keypair: &KeyPair, hash: &H256) -> Result<Vec<u8>, Error> {
+    let signature = keypair.private().sign(hash).map_err::<Error, _>(|cause| {
+        let cause = err_msg(cause.to_string());
+        ectx!(err cause, ErrorContext::Signature, ErrorKind::Internal => hash)
+    })?;
+    let mut signature_with_sighash = signature.to_vec();
+    signature_with_sighash.push(SIGHASH_ALL as u8);
+    Ok(signature_with_sighash)
+}
+
+// https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+/// Signs P2WPKH input, returns its witness: signature and public key
+fn p2wpkh_witness(signer: &TransactionInputSigner, index: usize, amount: u64, keypair: &KeyPair) -> Result<Vec<Bytes>, Error> {
+    let public = keypair.public();
+    // script code of P2WPKH input is P2PKH script of the key hash
+    let script_code = ScriptBuilder::build_p2pkh(&public.address_hash());
+    let hash = signer.signature_hash(index, amount, &script_code, SignatureVersion::WitnessV0, SIGHASH_ALL);
+    let signature = sign_hash(keypair, &hash)?;
+    Ok(vec![signature.into(), public.to_vec().into()])
+}
+
+/// `OP_0 <20 bytes key hash>`
+fn build_p2wpkh(hash: &H160) -> Script {
+    build_witness_program(0, &**hash)
+}
+
+/// `OP_n <program>` of witness `version` from 0 to 16
+fn build_witness_program(version: u8, program: &[u8]) -> Script {
+    let opcode = match version {
+        0 => Opcode::OP_0,
+        // OP_1 to OP_16 are consecutive
+        _ => Opcode::from_u8(Opcode::OP_1 as u8 + version - 1).unwrap_or(Opcode::OP_16),
+    };
+    ScriptBuilder::default().push_opcode(opcode).push_bytes(program).into_script()
+}
+
+/// Sizes of transaction parts for fees. `public_key_size` is 33 bytes for compressed keys and 65 bytes
+/// for uncompressed ones. SegWit inputs pay for virtual size, where witness bytes count as a quarter of a byte.
+fn fee_model(wallet_type: WalletType, fee_price: f64, public_key_size: usize, output_script: &Script, change_script: &Script) -> FeeModel {
+    let output_size = |script: &Script| (8 + 1 + script.len()) as f64;
+    // version, inputs count, outputs count and lock time
+    let mut base_size = (4 + 1 + 1 + 4) as f64 + output_size(output_script);
+    // outpoint, script_sig length and sequence
+    let mut input_size = (36 + 1 + 4) as f64;
+    match wallet_type {
+        // script_sig pushes signature and public key
+        WalletType::P2pkh => input_size += (1 + 71 + 1 + public_key_size) as f64,
+        WalletType::P2wpkh | WalletType::P2shP2wpkh => {
+            // marker and flag
+            base_size += 2.0 / 4.0;
+            // witness stack items count, signature and public key
+            input_size += (1 + 1 + 72 + 1 + public_key_size) as f64 / 4.0;
+            if wallet_type == WalletType::P2shP2wpkh {
+                // push of the redeem script
+                input_size += (1 + 22) as f64;
+            }
         }
     }
+    FeeModel {
+        fee_price,
+        base_size,
+        input_size,
+        change_output_size: output_size(change_script),
+    }
+}
+
+/// Hash of P2SH address of the key's P2WPKH redeem script
+fn p2sh_p2wpkh_hash(keypair: &KeyPair) -> H160 {
+    dhash160(&build_p2wpkh(&keypair.public().address_hash()))
+}
+
+/// Errors don't contain the key
+fn private_key_to_keypair(key: &PrivateKey) -> Result<KeyPair, Error> {
+    key.with_bytes(|bytes| {
+        BtcPrivateKey::from_layout(bytes)
+            .ok()
+            .and_then(|private| KeyPair::from_private(private).ok())
+    })
+    .ok()
+    .and_then(|keypair| keypair)
+    .ok_or_else(malformed_private_key)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use btcscript::{verify_script, TransactionSignatureChecker, VerificationFlags};
+    use btcserialization::deserialize;
+
+    // https://en.bitcoin.it/wiki/Wallet_import_format
+    #[test]
+    fn test_parse_private_key() {
+        let bitcoin_service = BitcoinService::new(BtcNetwork::Main, CoinSelection::default());
+        let hex = "0c28fca386c7a227600b2fe50b7cae11ec86d3bf1fbe471be89827e19d72aa1d";
+        let wif = "5HueCGU8rMjxEXxiPuD5BDku4MkFqeZyd4dZ1jvhTVqvbTLvyTJ";
+        let from_hex = bitcoin_service
+            .parse_private_key(Currency::Btc, RawPrivateKey::new(hex.to_string()))
+            .unwrap();
+        let from_wif = bitcoin_service
+            .parse_private_key(Currency::Btc, RawPrivateKey::new(wif.to_string()))
+            .unwrap();
+        assert_eq!(from_hex.raw(), from_wif.raw());
+        assert_eq!(
+            bitcoin_service.derive_address(Currency::Btc, None, &from_wif).unwrap(),
+            BlockchainAddress::new("1GAehh7TsJAHuUAeKZcXf5CnwuGuGgyX2S".to_string())
+        );
+        // mainnet WIF is rejected on testnet
+        let bitcoin_service = BitcoinService::new(BtcNetwork::Test, CoinSelection::default());
+        assert!(bitcoin_service
+            .parse_private_key(Currency::Btc, RawPrivateKey::new(wif.to_string()))
+            .is_err());
+        assert!(bitcoin_service
+            .parse_private_key(
+                Currency::Btc,
+                RawPrivateKey::new("5HueCGU8rMjxEXxiPuD5BDku4MkFqeZyd4dZ1jvhTVqvbTLvyTX".to_string())
+            )
+            .is_err());
+    }
+    #[test]
+    fn test_compressed_keys() {
+        let bitcoin_service = BitcoinService::new(BtcNetwork::Main, CoinSelection::default());
+        let (private_key, address) = bitcoin_service.generate_key(Currency::Btc, None).unwrap();
+        assert_eq!(private_key_to_keypair(&private_key).unwrap().public().len(), 33);
+        assert_eq!(bitcoin_service.derive_address(Currency::Btc, None, &private_key).unwrap(), address);
+        let (private_key, _) = bitcoin_service.generate_vanity_key(Currency::Btc, "1", 1).unwrap().unwrap();
+        assert_eq!(private_key_to_keypair(&private_key).unwrap().public().len(), 33);
+
+        // the same secret as in test_parse_private_key, addresses differ by compression flag
+        let compressed_wif = "KwdMAjGmerYanjeui5SHS7JkmpZvVipYvB2LJGU1ZxJwYvP98617";
+        let uncompressed_wif = "5HueCGU8rMjxEXxiPuD5BDku4MkFqeZyd4dZ1jvhTVqvbTLvyTJ";
+        let compressed = bitcoin_service
+            .parse_private_key(Currency::Btc, RawPrivateKey::new(compressed_wif.to_string()))
+            .unwrap();
+        let uncompressed = bitcoin_service
+            .parse_private_key(Currency::Btc, RawPrivateKey::new(uncompressed_wif.to_string()))
+            .unwrap();
+        assert_eq!(
+            bitcoin_service.derive_address(Currency::Btc, None, &compressed).unwrap(),
+            BlockchainAddress::new("1LoVGDgRs9hTfTNJNuXKSpywcbdvwRXpmK".to_string())
+        );
+        assert_eq!(
+            bitcoin_service.derive_address(Currency::Btc, None, &uncompressed).unwrap(),
+            BlockchainAddress::new("1GAehh7TsJAHuUAeKZcXf5CnwuGuGgyX2S".to_string())
+        );
+        for (private_key, wif) in &[(&compressed, compressed_wif), (&uncompressed, uncompressed_wif)] {
+            let exported = bitcoin_service.export_private_key(Currency::Btc, private_key).unwrap();
+            let exported = exported.with_bytes(|bytes| String::from_utf8(bytes.to_vec()).unwrap()).unwrap();
+            assert_eq!(exported, *wif);
+        }
+
+        // compressed public key makes every input 32 bytes smaller, that also lowers fees
+        let tx = |from: &str| UnsignedTransaction {
+            id: TransactionId::default(),
+            from: BlockchainAddress::new(from.to_string()),
+            to: BlockchainAddress::new("14QxuxuS9apVWAiSvJx4fCy6dDPRzLVHNL".to_string()),
+            currency: Currency::Btc,
+            value: Amount::new(100000),
+            fee_price: 0.0,
+            nonce: None,
+            utxos: Some(vec![Utxo {
+                tx_hash: "90e56bda920e72e9caae86302c284f18255a419927a0649fca839faeca1b8610".to_string(),
+                value: Amount::new(8293863),
+                index: 0,
+            }]),
+            coin_selection: None,
+        };
+        let compressed_tx = bitcoin_service
+            .sign(&compressed, tx("1LoVGDgRs9hTfTNJNuXKSpywcbdvwRXpmK"))
+            .unwrap()
+            .into_inner();
+        let uncompressed_tx = bitcoin_service
+            .sign(&uncompressed, tx("1GAehh7TsJAHuUAeKZcXf5CnwuGuGgyX2S"))
+            .unwrap()
+            .into_inner();
+        assert!(compressed_tx.contains("2102d0de0aaeaefad02b8bdc8a01a1b8b11c696bd3d66a2c5f10780d95b7df42645c"));
+        assert!(compressed_tx.len() < uncompressed_tx.len());
+    }
+    #[test]
+    fn test_generate_vanity_key() {
+        let bitcoin_service = BitcoinService::new(BtcNetwork::Main, CoinSelection::default());
+        let (private_key, address) = bitcoin_service
+            .generate_vanity_key(Currency::Btc, "1S", usize::max_value())
+            .unwrap()
+            .unwrap();
+        assert!(address.clone().into_inner().starts_with("1S"));
+        assert_eq!(bitcoin_service.derive_address(Currency::Btc, None, &private_key).unwrap(), address);
+        assert!(bitcoin_service.generate_vanity_key(Currency::Btc, "1S", 0).unwrap().is_none());
+        for prefix in &["", "10", "1O", "1l"] {
+            assert!(bitcoin_service.generate_vanity_key(Currency::Btc, prefix, 1).is_err());
+        }
+    }
+    #[test]
+    fn test_generate_vanity_key_network() {
+        let bitcoin_service = BitcoinService::new(BtcNetwork::Main, CoinSelection::default());
+        for prefix in &["3", "m", "n", "S"] {
+            assert!(bitcoin_service.generate_vanity_key(Currency::Btc, prefix, 1).is_err());
+        }
+        let bitcoin_service = BitcoinService::new(BtcNetwork::Test, CoinSelection::default());
+        let (_, address) = bitcoin_service
+            .generate_vanity_key(Currency::Btc, "m", usize::max_value())
+            .unwrap()
+            .unwrap();
+        assert!(address.into_inner().starts_with('m'));
+        for prefix in &["1", "1S", "2"] {
+            assert!(bitcoin_service.generate_vanity_key(Currency::Btc, prefix, 1).is_err());
+        }
+    }
+    // https://github.com/bitcoin/bips/blob/master/bip-0084.mediawiki#test-vectors
+    #[test]
+    fn test_derive_p2wpkh_key() {
+        let bitcoin_service = BitcoinService::new(BtcNetwork::Main, CoinSelection::default());
+        let mnemonic =
+            Mnemonic::new("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about".to_string());
+        let seed = MasterSeed::from_mnemonic(&mnemonic, &Passphrase::default()).unwrap();
+        let wallet_type = Some(WalletType::P2wpkh);
+        let path = bitcoin_service.account_derivation_path(Currency::Btc, wallet_type).address(0);
+        assert_eq!(path.to_string(), "m/84'/0'/0'/0/0");
+        let (_, address) = bitcoin_service.derive_key(Currency::Btc, wallet_type, &seed, &path).unwrap();
+        assert_eq!(address.into_inner(), "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu");
+        let path = bitcoin_service.account_derivation_path(Currency::Btc, None);
+        assert_eq!(path.to_string(), "m/44'/0'/0'");
+    }
+    #[test]
+    fn test_derive_p2sh_p2wpkh_key() {
+        let bitcoin_service = BitcoinService::new(BtcNetwork::Main, CoinSelection::default());
+        let mnemonic =
+            Mnemonic::new("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about".to_string());
+        let seed = MasterSeed::from_mnemonic(&mnemonic, &Passphrase::default()).unwrap();
+        let wallet_type = Some(WalletType::P2shP2wpkh);
+        let path = bitcoin_service.account_derivation_path(Currency::Btc, wallet_type).address(0);
+        assert_eq!(path.to_string(), "m/49'/0'/0'/0/0");
+        let (_, address) = bitcoin_service.derive_key(Currency::Btc, wallet_type, &seed, &path).unwrap();
+        assert_eq!(address.into_inner(), "37VucYSaXLCAsxYyAPfbSi9eh4iEcbShgf");
+    }
+    #[test]
+    fn test_segwit_keys() {
+        let bitcoin_service = BitcoinService::new(BtcNetwork::Main, CoinSelection::default());
+        let private = BtcPrivateKey {
+            network: Network::Mainnet,
+            secret: "0000000000000000000000000000000000000000000000000000000000000001".parse().unwrap(),
+            compressed: true,
+        };
+        let private_key = private_to_private_key(&private);
+        assert_eq!(
+            bitcoin_service
+                .derive_address(Currency::Btc, Some(WalletType::P2wpkh), &private_key)
+                .unwrap(),
+            BlockchainAddress::new("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4".to_string())
+        );
+        assert_eq!(
+            bitcoin_service
+                .derive_address(Currency::Btc, Some(WalletType::P2shP2wpkh), &private_key)
+                .unwrap(),
+            BlockchainAddress::new("3JvL6Ymt8MVWiCNHC7oWU6nLeHNJKLZGLN".to_string())
+        );
+        assert_eq!(
+            BitcoinService::new(BtcNetwork::Test, CoinSelection::default())
+                .derive_address(Currency::Btc, Some(WalletType::P2shP2wpkh), &private_key)
+                .unwrap(),
+            BlockchainAddress::new("2NAUYAHhujozruyzpsFRP63mbrdaU5wnEpN".to_string())
+        );
+        let (private_key, address) = bitcoin_service.generate_key(Currency::Btc, Some(WalletType::P2wpkh)).unwrap();
+        assert!(address.clone().into_inner().starts_with("bc1q"));
+        assert_eq!(
+            bitcoin_service
+                .derive_address(Currency::Btc, Some(WalletType::P2wpkh), &private_key)
+                .unwrap(),
+            address
+        );
+        let bitcoin_service = BitcoinService::new(BtcNetwork::Test, CoinSelection::default());
+        let (_, address) = bitcoin_service.generate_key(Currency::Btc, Some(WalletType::P2wpkh)).unwrap();
+        assert!(address.into_inner().starts_with("tb1q"));
+        // uncompressed keys can't have SegWit addresses
+        let uncompressed = bitcoin_service
+            .parse_private_key(
+                Currency::Btc,
+                RawPrivateKey::new("0c28fca386c7a227600b2fe50b7cae11ec86d3bf1fbe471be89827e19d72aa1d".to_string()),
+            )
+            .unwrap();
+        assert!(bitcoin_service
+            .derive_address(Currency::Btc, Some(WalletType::P2wpkh), &uncompressed)
+            .is_err());
+        assert!(bitcoin_service
+            .derive_address(Currency::Btc, Some(WalletType::P2shP2wpkh), &uncompressed)
+            .is_err());
+    }
+    // Native P2WPKH example of https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+    #[test]
+    fn test_p2wpkh_witness() {
+        let tx: Transaction = "0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000".into();
+        let private = BtcPrivateKey {
+            network: Network::Mainnet,
+            secret: "619c335025c7f4012e556c2a58b2506e30b8511b53ade95ea316fd8c3286feb9".parse().unwrap(),
+            compressed: true,
+        };
+        let keypair = KeyPair::from_private(private).unwrap();
+        let public = keypair.public();
+        assert_eq!(
+            bytes_to_hex(&**public),
+            "025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357"
+        );
+        assert_eq!(public.address_hash(), "1d0f172a0ecb48aee1be1f2687d2963ae33f71a1".parse().unwrap());
+
+        let signer = TransactionInputSigner::from(tx);
+        let script_code = ScriptBuilder::build_p2pkh(&public.address_hash());
+        let hash = signer.signature_hash(1, 600_000_000, &script_code, SignatureVersion::WitnessV0, SIGHASH_ALL);
+        assert_eq!(
+            hash,
+            "c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670".parse().unwrap()
+        );
+
+        let witness = p2wpkh_witness(&signer, 1, 600_000_000, &keypair).unwrap();
+        assert_eq!(witness.len(), 2);
+        assert_eq!(&*witness[1], &**public);
+        let (sighash, signature) = witness[0].split_last().unwrap();
+        assert_eq!(*sighash, SIGHASH_ALL as u8);
+        assert!(public.verify(&hash, &signature.into()).unwrap());
+    }
+    #[test]
+    fn test_sign_p2wpkh() {
+        let bitcoin_service = BitcoinService::new(BtcNetwork::Main, CoinSelection::default());
+        let private = BtcPrivateKey {
+            network: Network::Mainnet,
+            secret: "0000000000000000000000000000000000000000000000000000000000000001".parse().unwrap(),
+            compressed: true,
+        };
+        let pk = private_to_private_key(&private);
+        let tx = UnsignedTransaction {
+            id: TransactionId::default(),
+            from: BlockchainAddress::new("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4".to_string()),
+            to: BlockchainAddress::new("14QxuxuS9apVWAiSvJx4fCy6dDPRzLVHNL".to_string()),
+            currency: Currency::Btc,
+            value: Amount::new(100000),
+            fee_price: 0.0,
+            nonce: None,
+            utxos: Some(vec![Utxo {
+                tx_hash: "90e56bda920e72e9caae86302c284f18255a419927a0649fca839faeca1b8610".to_string(),
+                value: Amount::new(8293863),
+                index: 0,
+            }]),
+            coin_selection: None,
+        };
+        let raw_tx = bitcoin_service.sign(&pk, tx.clone()).unwrap().into_inner();
+        // version, then SegWit marker and flag
+        assert!(raw_tx.starts_with("010000000001"));
+        // input with empty script_sig
+        assert!(raw_tx.contains("10861bcaae9f83ca9f64a02799415a25184f282c3086aecae9720e92da6be5900000000000ffffffff"));
+        // change goes back to the P2WPKH script
+        assert!(raw_tx.contains("160014751e76e8199196d454941c45d1b3a323f1433bd6"));
+        // witness ends with the compressed public key, followed by lock time
+        assert!(raw_tx.ends_with("210279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f8179800000000"));
+
+        // P2WSH addresses aren't keys
+        let tx = UnsignedTransaction {
+            from: BlockchainAddress::new("bc1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qccfmv3".to_string()),
+            ..tx
+        };
+        assert!(bitcoin_service.sign(&pk, tx).is_err());
+    }
+    // P2SH-P2WPKH example of https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki
+    #[test]
+    fn test_p2sh_p2wpkh_witness() {
+        let tx: Transaction = "0100000001db6b1b20aa0fd7b23880be2ecbd4a98130974cf4748fb66092ac4d3ceb1a54770100000000feffffff02b8b4eb0b000000001976a914a457b684d7f0d539a46a45bbc043f35b59d0d96388ac0008af2f000000001976a914fd270b1ee6abcaea97fea7ad0402e8bd8ad6d77c88ac92040000".into();
+        let private = BtcPrivateKey {
+            network: Network::Mainnet,
+            secret: "eb696a065ef48a2192da5b28b694f87544b30fae8327c4510137a922f32c6dcf".parse().unwrap(),
+            compressed: true,
+        };
+        let keypair = KeyPair::from_private(private).unwrap();
+        let public = keypair.public();
+        assert_eq!(
+            bytes_to_hex(&**public),
+            "03ad1d8e89212f0b92c74d23bb710c00662ad1470198ac48c43f7d6f93a2a26873"
+        );
+        assert_eq!(
+            p2sh_p2wpkh_hash(&keypair),
+            "4733f37cf4db86fbc2efed2500b4f4e49f312023".parse().unwrap()
+        );
+
+        let signer = TransactionInputSigner::from(tx);
+        let script_code = ScriptBuilder::build_p2pkh(&public.address_hash());
+        let hash = signer.signature_hash(0, 1_000_000_000, &script_code, SignatureVersion::WitnessV0, SIGHASH_ALL);
+        assert_eq!(
+            hash,
+            "64f3b0f4dd2bb3aa1ce8566d220cc74dda9df97d8490cc81d89d735c92e59fb6".parse().unwrap()
+        );
+
+        let witness = p2wpkh_witness(&signer, 0, 1_000_000_000, &keypair).unwrap();
+        assert_eq!(&*witness[1], &**public);
+        let (_, signature) = witness[0].split_last().unwrap();
+        assert!(public.verify(&hash, &signature.into()).unwrap());
+    }
+    #[test]
+    fn test_sign_p2sh_p2wpkh() {
+        let bitcoin_service = BitcoinService::new(BtcNetwork::Main, CoinSelection::default());
+        let private = BtcPrivateKey {
+            network: Network::Mainnet,
+            secret: "0000000000000000000000000000000000000000000000000000000000000001".parse().unwrap(),
+            compressed: true,
+        };
+        let pk = private_to_private_key(&private);
+        let tx = UnsignedTransaction {
+            id: TransactionId::default(),
+            from: BlockchainAddress::new("3JvL6Ymt8MVWiCNHC7oWU6nLeHNJKLZGLN".to_string()),
+            to: BlockchainAddress::new("14QxuxuS9apVWAiSvJx4fCy6dDPRzLVHNL".to_string()),
+            currency: Currency::Btc,
+            value: Amount::new(100000),
+            fee_price: 0.0,
+            nonce: None,
+            utxos: Some(vec![Utxo {
+                tx_hash: "90e56bda920e72e9caae86302c284f18255a419927a0649fca839faeca1b8610".to_string(),
+                value: Amount::new(8293863),
+                index: 0,
+            }]),
+            coin_selection: None,
+        };
+        let raw_tx = bitcoin_service.sign(&pk, tx.clone()).unwrap().into_inner();
+        assert!(raw_tx.starts_with("010000000001"));
+        // input pushes the P2WPKH redeem script
+        assert!(raw_tx.contains("10861bcaae9f83ca9f64a02799415a25184f282c3086aecae9720e92da6be59000000000171600147"));
+        // change goes back to the P2SH script
+        assert!(raw_tx.contains("17a914bcfeb728b584253d5f3f70bcb780e9ef218a68f487"));
+        assert!(raw_tx.ends_with("210279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f8179800000000"));
+
+        // P2SH address of another key's redeem script
+        let pk = PrivateKey::new("ef13c9b34216f7fbe84787ab9ff78f9fd516a1d72a78f071bfaaad97278fa86b5a9951c8c0".to_string());
+        assert!(bitcoin_service.sign(&pk, tx).is_err());
+    }
+    #[test]
+    fn test_sign_to_segwit() {
+        let bitcoin_service = BitcoinService::new(BtcNetwork::Main, CoinSelection::default());
+        let pk = PrivateKey::new("ef13c9b34216f7fbe84787ab9ff78f9fd516a1d72a78f071bfaaad97278fa86b5a9951c8c0".to_string());
+        let tx = |to: &str| UnsignedTransaction {
+            id: TransactionId::default(),
+            from: BlockchainAddress::new("1LooGrNiLscvpggq1AYqS2h3r9CVR4mar1".to_string()),
+            to: BlockchainAddress::new(to.to_string()),
+            currency: Currency::Btc,
+            value: Amount::new(100000),
+            fee_price: 0.0,
+            nonce: None,
+            utxos: Some(vec![Utxo {
+                tx_hash: "90e56bda920e72e9caae86302c284f18255a419927a0649fca839faeca1b8610".to_string(),
+                value: Amount::new(8293863),
+                index: 0,
+            }]),
+            coin_selection: None,
+        };
+        let cases = [
+            // P2WPKH
+            (
+                "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4",
+                "a086010000000000160014751e76e8199196d454941c45d1b3a323f1433bd6",
+            ),
+            // P2WSH
+            (
+                "bc1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qccfmv3",
+                "a0860100000000002200201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262",
+            ),
+            // P2TR
+            (
+                "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0",
+                "a08601000000000022512079be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            ),
+        ];
+        for (to, output) in &cases {
+            let raw_tx = bitcoin_service.sign(&pk, tx(to)).unwrap().into_inner();
+            assert!(raw_tx.contains(output), "{}", to);
+        }
+        for to in &[
+            // testnet addresses
+            "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7",
+            "ms3iZko2BcbigHBufFUum2Avg9PfozmZY4",
+            // bech32 checksum of version 1 address
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqh2y7hd",
+        ] {
+            match bitcoin_service.sign(&pk, tx(to)).unwrap_err().kind() {
+                ErrorKind::InvalidUnsignedTransaction(ValidationError::MalformedAddress { .. }) => (),
+                kind => panic!("Unexpected error {:?}", kind),
+            }
+        }
+    }
+    // signed transactions are checked by script interpreter, every input has to verify against its previous output
+    #[test]
+    fn test_verify_signed_inputs() {
+        let bitcoin_service = BitcoinService::new(BtcNetwork::Main, CoinSelection::default());
+        let private = BtcPrivateKey {
+            network: Network::Mainnet,
+            secret: "0000000000000000000000000000000000000000000000000000000000000001".parse().unwrap(),
+            compressed: true,
+        };
+        let pk = private_to_private_key(&private);
+        let utxos = vec![
+            Utxo {
+                tx_hash: "9e87538bdc1b83688af82fedb524ca647f102bef6c5b3a09774b5637e7702cc2".to_string(),
+                value: Amount::new(336474),
+                index: 1,
+            },
+            Utxo {
+                tx_hash: "1ef46531bf5da3d49be1458ff855094339ba6ff0e8812be27e4a6b7328d0acaa".to_string(),
+                value: Amount::new(335456),
+                index: 0,
+            },
+            Utxo {
+                tx_hash: "f8cb4a89b5197b4f53c64d75cc93724a925bfa1c7918e5a2468d24a8c0329e2e".to_string(),
+                value: Amount::new(125483),
+                index: 2,
+            },
+        ];
+        let flags = VerificationFlags::default().verify_p2sh(true).verify_witness(true);
+        for wallet_type in &[WalletType::P2pkh, WalletType::P2wpkh, WalletType::P2shP2wpkh] {
+            let from = bitcoin_service.derive_address(Currency::Btc, Some(*wallet_type), &pk).unwrap();
+            let tx = UnsignedTransaction {
+                id: TransactionId::default(),
+                from: from.clone(),
+                to: BlockchainAddress::new("14QxuxuS9apVWAiSvJx4fCy6dDPRzLVHNL".to_string()),
+                currency: Currency::Btc,
+                value: Amount::new(580520),
+                fee_price: 10.0,
+                nonce: None,
+                utxos: Some(utxos.clone()),
+                coin_selection: None,
+            };
+            let raw_tx = bitcoin_service.sign(&pk, tx).unwrap().into_inner();
+            let tx: Transaction = deserialize(&decode_hex(&raw_tx).unwrap()[..]).unwrap();
+            assert!(tx.inputs.len() > 1);
+            let script_pubkey = bitcoin_service.output_script(&from).unwrap();
+            for (index, input) in tx.inputs.iter().enumerate() {
+                let utxo = utxos
+                    .iter()
+                    .find(|utxo| utxo.tx_hash.parse::<H256>().unwrap().reversed() == input.previous_output.hash)
+                    .unwrap();
+                let checker = TransactionSignatureChecker {
+                    signer: tx.clone().into(),
+                    input_index: index,
+                    input_amount: utxo.value.u64().unwrap(),
+                };
+                let script_sig = input.script_sig.clone().into();
+                let result = verify_script(
+                    &script_sig,
+                    &script_pubkey,
+                    &input.script_witness,
+                    &flags,
+                    &checker,
+                    SignatureVersion::Base,
+                );
+                assert_eq!(result, Ok(()), "{} input {}", wallet_type, index);
+            }
+        }
+    }
+    #[test]
+    fn test_sign_coin_selection() {
+        let bitcoin_service = BitcoinService::new(BtcNetwork::Main, CoinSelection::LargestFirst);
+        let private = BtcPrivateKey {
+            network: Network::Mainnet,
+            secret: "0000000000000000000000000000000000000000000000000000000000000001".parse().unwrap(),
+            compressed: true,
+        };
+        let pk = private_to_private_key(&private);
+        let utxos = vec![
+            Utxo {
+                tx_hash: "9e87538bdc1b83688af82fedb524ca647f102bef6c5b3a09774b5637e7702cc2".to_string(),
+                value: Amount::new(336474),
+                index: 1,
+            },
+            Utxo {
+                tx_hash: "1ef46531bf5da3d49be1458ff855094339ba6ff0e8812be27e4a6b7328d0acaa".to_string(),
+                value: Amount::new(335456),
+                index: 0,
+            },
+            Utxo {
+                tx_hash: "f8cb4a89b5197b4f53c64d75cc93724a925bfa1c7918e5a2468d24a8c0329e2e".to_string(),
+                value: Amount::new(125483),
+                index: 2,
+            },
+        ];
+        let tx = |value: u128, coin_selection: Option<CoinSelection>| UnsignedTransaction {
+            id: TransactionId::default(),
+            from: BlockchainAddress::new("1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH".to_string()),
+            to: BlockchainAddress::new("14QxuxuS9apVWAiSvJx4fCy6dDPRzLVHNL".to_string()),
+            currency: Currency::Btc,
+            value: Amount::new(value),
+            fee_price: 10.0,
+            nonce: None,
+            utxos: Some(utxos.clone()),
+            coin_selection,
+        };
+        // values of spent utxos and number of outputs
+        let spent = |raw_tx: RawTransaction| -> (Vec<u64>, usize) {
+            let tx: Transaction = deserialize(&decode_hex(&raw_tx.into_inner()).unwrap()[..]).unwrap();
+            let values = tx
+                .inputs
+                .iter()
+                .map(|input| {
+                    let utxo = utxos
+                        .iter()
+                        .find(|utxo| utxo.tx_hash.parse::<H256>().unwrap().reversed() == input.previous_output.hash)
+                        .unwrap();
+                    utxo.value.u64().unwrap()
+                })
+                .collect();
+            (values, tx.outputs.len())
+        };
+        let cases = [
+            (400_000, None, vec![336474, 335456], 2),
+            (400_000, Some(CoinSelection::SmallestFirst), vec![125483, 335456], 2),
+            // inputs pay the value and fees exactly, without change
+            (457_559, Some(CoinSelection::BranchAndBound), vec![335456, 125483], 1),
+            (457_559, None, vec![336474, 335456], 2),
+        ];
+        for (value, coin_selection, values, outputs) in cases.iter() {
+            let raw_tx = bitcoin_service.sign(&pk, tx(*value, *coin_selection)).unwrap();
+            assert_eq!(spent(raw_tx), (values.clone(), *outputs), "{:?}", coin_selection);
+        }
+
+        match bitcoin_service.sign(&pk, tx(797_000, None)).unwrap_err().kind() {
+            ErrorKind::InvalidUnsignedTransaction(ValidationError::NotEnoughUtxo) => (),
+            kind => panic!("Unexpected error {:?}", kind),
+        }
+    }
     // https://testnet.blockchain.info/tx/5aed90d51d84d54d1093995f6d6a0e1e4503f40deefce942817bec6ad3cafe81?format=hex
     #[test]
     fn test_sign() {
-        let bitcoin_service = BitcoinService::new(BtcNetwork::Test);
+        let bitcoin_service = BitcoinService::new(BtcNetwork::Test, CoinSelection::default());
         let pk = PrivateKey::new("ef13c9b34216f7fbe84787ab9ff78f9fd516a1d72a78f071bfaaad97278fa86b5a9951c8c0".to_string());
         let tx = UnsignedTransaction {
             id: TransactionId::default(),
@@ -299,15 +1044,16 @@ mod tests {
                 value: Amount::new(8293863),
                 index: 0,
             }]),
+            coin_selection: None,
         };
         let raw_tx = bitcoin_service
-            .sign_with_options(pk, tx, true, Some(1436452))
+            .sign_with_options(&pk, tx, true, Some(1436452))
             .expect("Failed to sign");
         assert_eq!(raw_tx.into_inner(), "010000000110861bcaae9f83ca9f64a02799415a25184f282c3086aecae9720e92da6be590000000008a473044022065d8c5c83d1262e47447127aec29f78b80bce5cf8702f61679529019cc37bfa502204ca0377bd13ec7445b56e726c143f4da718e4424c2ec9acd68a58255f435992b0141049cd145484ef05dc259326651e942ecfa2c7f64bad3286e94e303eaf9b03edf0a844d63ad58c078e28a183438d0bccc75fd788522069ed79cee71736fade65124fdffffff02a0860100000000001976a9147e7ad15c2aa503c33520dee5bccd7d79ff2b44db88ac47077d00000000001976a914ffcdccfab05fa7df11e279da558d68f80daffc3788ac24eb1500".to_string());
     }
     #[test]
     fn test_sign_fees() {
-        let bitcoin_service = BitcoinService::new(BtcNetwork::Main);
+        let bitcoin_service = BitcoinService::new(BtcNetwork::Main, CoinSelection::default());
         let pk = PrivateKey::new("ef13c9b34216f7fbe84787ab9ff78f9fd516a1d72a78f071bfaaad97278fa86b5a9951c8c0".to_string());
         let tx = UnsignedTransaction {
             id: TransactionId::default(),
@@ -334,8 +1080,9 @@ mod tests {
                     index: 1,
                 },
             ]),
+            coin_selection: None,
         };
-        let raw_tx = bitcoin_service.sign_with_options(pk, tx, false, None).expect("Failed to sign");
-        assert_eq!(raw_tx.into_inner(), "0100000002c22c70e737564b77093a5b6cef2b107f64ca24b5ed2ff88a68831bdc8b53879e010000008b483045022100fc86e508d2c4aef3812d93248c31beaddcbaeb8784fdb0cc51b6474fe8ce73aa02203f1804cda08602add7664e47ea9cd68bb94ad0db1c613591db12668dbaf69a7e0141049cd145484ef05dc259326651e942ecfa2c7f64bad3286e94e303eaf9b03edf0a844d63ad58c078e28a183438d0bccc75fd788522069ed79cee71736fade65124ffffffffaaacd028736b4a7ee22b81e8f06fba39430955f88f45e19bd4a35dbf3165f41e010000008b483045022100fc86e508d2c4aef3812d93248c31beaddcbaeb8784fdb0cc51b6474fe8ce73aa02203f1804cda08602add7664e47ea9cd68bb94ad0db1c613591db12668dbaf69a7e0141049cd145484ef05dc259326651e942ecfa2c7f64bad3286e94e303eaf9b03edf0a844d63ad58c078e28a183438d0bccc75fd788522069ed79cee71736fade65124ffffffff02a8db0800000000001976a91425709e51d84c4eb753664a6625c059ff813d5c9c88ac52fe0000000000001976a914d94426b0fa8e42c0a8d0222c6097e8eaf6fada8d88ac00000000".to_string());
+        let raw_tx = bitcoin_service.sign_with_options(&pk, tx, false, None).expect("Failed to sign");
+        assert_eq!(raw_tx.into_inner(), "0100000002c22c70e737564b77093a5b6cef2b107f64ca24b5ed2ff88a68831bdc8b53879e010000008b483045022100a38815ef900f7e7741b3954f1bb4e46809ec810e1fd72a7b1bfae294aa7de0a602206be5c11c514a89c279bf4c0efac8251c2a35ef7ee4c5a4381fab63c1624e31bd0141049cd145484ef05dc259326651e942ecfa2c7f64bad3286e94e303eaf9b03edf0a844d63ad58c078e28a183438d0bccc75fd788522069ed79cee71736fade65124ffffffffaaacd028736b4a7ee22b81e8f06fba39430955f88f45e19bd4a35dbf3165f41e010000008b483045022100c0d2ee65a5e460ab7c3e7ce48dd7c8d9b747347bdff741ec678670bf6b546d56022051f1aa758edc95a3aa34122433dd94bfbce9a92e4447797e629131e58dafe2250141049cd145484ef05dc259326651e942ecfa2c7f64bad3286e94e303eaf9b03edf0a844d63ad58c078e28a183438d0bccc75fd788522069ed79cee71736fade65124ffffffff02a8db0800000000001976a91425709e51d84c4eb753664a6625c059ff813d5c9c88ac52fe0000000000001976a914d94426b0fa8e42c0a8d0222c6097e8eaf6fada8d88ac00000000".to_string());
     }
 }
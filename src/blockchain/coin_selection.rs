@@ -0,0 +1,245 @@
+//! Selection of UTXOs spent by bitcoin transactions. Every input adds its size to the transaction,
+//! so strategies pick inputs that pay the value together with the fees of these inputs.
+
+use models::*;
+
+/// Dust limit of P2PKH outputs, smaller change is left to miners
+pub const DUST_LIMIT: u64 = 546;
+/// Number of branches that branch and bound visits before it gives up
+const BNB_MAX_TRIES: usize = 100_000;
+
+/// Fees of transaction being built, sizes are in virtual bytes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeeModel {
+    /// Satoshis per virtual byte
+    pub fee_price: f64,
+    /// Transaction without inputs and change output
+    pub base_size: f64,
+    pub input_size: f64,
+    pub change_output_size: f64,
+}
+
+impl FeeModel {
+    /// Fees of transaction with `inputs_count` inputs
+    pub fn fee(&self, inputs_count: usize, with_change: bool) -> u64 {
+        let change_output_size = if with_change { self.change_output_size } else { 0.0 };
+        let size = self.base_size + self.input_size * inputs_count as f64 + change_output_size;
+        (self.fee_price * size) as u64
+    }
+
+    /// Fees that every input adds, rounded up
+    pub fn input_fee(&self) -> u64 {
+        (self.fee_price * self.input_size).ceil() as u64
+    }
+
+    /// Change is worth an output only if it's more than the fees of the output and of spending it later
+    pub fn cost_of_change(&self) -> u64 {
+        let cost = (self.fee_price * (self.change_output_size + self.input_size)).ceil() as u64;
+        cost.max(DUST_LIMIT)
+    }
+
+    fn base_fee(&self) -> u64 {
+        (self.fee_price * self.base_size).ceil() as u64
+    }
+}
+
+pub trait CoinSelector {
+    /// Returns UTXOs that pay `value` and fees of transaction without change, `None` if `utxos` are not enough.
+    /// UTXOs that cost more fees than they are worth are never selected.
+    fn select(&self, utxos: &[Utxo], value: u64, fees: &FeeModel) -> Option<Vec<Utxo>>;
+}
+
+pub fn coin_selector(coin_selection: CoinSelection) -> &'static CoinSelector {
+    match coin_selection {
+        CoinSelection::BranchAndBound => &BranchAndBound,
+        CoinSelection::LargestFirst => &LargestFirst,
+        CoinSelection::SmallestFirst => &SmallestFirst,
+    }
+}
+
+pub struct LargestFirst;
+
+impl CoinSelector for LargestFirst {
+    fn select(&self, utxos: &[Utxo], value: u64, fees: &FeeModel) -> Option<Vec<Utxo>> {
+        let mut utxos = spendable_utxos(utxos, fees);
+        utxos.sort_by(|a, b| b.value.cmp(&a.value));
+        accumulate(utxos, value, fees)
+    }
+}
+
+pub struct SmallestFirst;
+
+impl CoinSelector for SmallestFirst {
+    fn select(&self, utxos: &[Utxo], value: u64, fees: &FeeModel) -> Option<Vec<Utxo>> {
+        let mut utxos = spendable_utxos(utxos, fees);
+        utxos.sort_by_key(|utxo| utxo.value);
+        accumulate(utxos, value, fees)
+    }
+}
+
+/// Depth first search for inputs, which effective values (value minus input fee) exceed the target
+/// by less than the cost of change, https://murch.one/wp-content/uploads/2016/11/erhardt2016coinselection.pdf
+pub struct BranchAndBound;
+
+impl CoinSelector for BranchAndBound {
+    fn select(&self, utxos: &[Utxo], value: u64, fees: &FeeModel) -> Option<Vec<Utxo>> {
+        let mut utxos = spendable_utxos(utxos, fees);
+        utxos.sort_by(|a, b| b.value.cmp(&a.value));
+        let values: Vec<u64> = utxos.iter().map(|utxo| utxo_value(utxo) - fees.input_fee()).collect();
+        let target = value.saturating_add(fees.base_fee());
+        let mut search = Search {
+            values: &values,
+            target,
+            upper_bound: target.saturating_add(fees.cost_of_change()),
+            tries: 0,
+            selected: Vec::new(),
+            best: None,
+        };
+        let remaining = values.iter().fold(0u64, |acc, value| acc.saturating_add(*value));
+        search.run(0, 0, remaining);
+        match search.best {
+            Some((_, selected)) => Some(selected.into_iter().map(|index| utxos[index].clone()).collect()),
+            None => LargestFirst.select(&utxos, value, fees),
+        }
+    }
+}
+
+struct Search<'a> {
+    values: &'a [u64],
+    target: u64,
+    upper_bound: u64,
+    tries: usize,
+    selected: Vec<usize>,
+    /// Excess over target and indexes of the best selection
+    best: Option<(u64, Vec<usize>)>,
+}
+
+impl<'a> Search<'a> {
+    /// `remaining` is the sum of values, starting from `index`
+    fn run(&mut self, index: usize, current: u64, remaining: u64) {
+        if self.tries >= BNB_MAX_TRIES || self.best.as_ref().map_or(false, |(excess, _)| *excess == 0) {
+            return;
+        }
+        self.tries += 1;
+        if current > self.upper_bound || current.saturating_add(remaining) < self.target {
+            return;
+        }
+        if current >= self.target {
+            let excess = current - self.target;
+            if self.best.as_ref().map_or(true, |(best, _)| excess < *best) {
+                self.best = Some((excess, self.selected.clone()));
+            }
+            return;
+        }
+        let value = match self.values.get(index) {
+            Some(value) => *value,
+            None => return,
+        };
+        let remaining = remaining - value;
+        self.selected.push(index);
+        self.run(index + 1, current + value, remaining);
+        self.selected.pop();
+        self.run(index + 1, current, remaining);
+    }
+}
+
+/// Values of UTXOs are checked to fit `u64` before selection
+fn utxo_value(utxo: &Utxo) -> u64 {
+    utxo.value.u64().unwrap_or(0)
+}
+
+fn spendable_utxos(utxos: &[Utxo], fees: &FeeModel) -> Vec<Utxo> {
+    utxos.iter().filter(|utxo| utxo_value(utxo) > fees.input_fee()).cloned().collect()
+}
+
+fn accumulate(utxos: Vec<Utxo>, value: u64, fees: &FeeModel) -> Option<Vec<Utxo>> {
+    let mut selected = Vec::new();
+    let mut sum = 0u64;
+    for utxo in utxos {
+        sum = sum.saturating_add(utxo_value(&utxo));
+        selected.push(utxo);
+        if sum >= value.saturating_add(fees.fee(selected.len(), false)) {
+            return Some(selected);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utxos(values: &[u64]) -> Vec<Utxo> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(index, value)| Utxo {
+                tx_hash: "90e56bda920e72e9caae86302c284f18255a419927a0649fca839faeca1b8610".to_string(),
+                value: Amount::new(*value as u128),
+                index: index as u64,
+            })
+            .collect()
+    }
+
+    fn values(utxos: &[Utxo]) -> Vec<u64> {
+        utxos.iter().map(utxo_value).collect()
+    }
+
+    // legacy inputs of compressed keys, one output and change output
+    const FEES: FeeModel = FeeModel {
+        fee_price: 10.0,
+        base_size: 44.0,
+        input_size: 147.0,
+        change_output_size: 34.0,
+    };
+
+    #[test]
+    fn test_fee_model() {
+        assert_eq!(FEES.fee(2, false), 3380);
+        assert_eq!(FEES.fee(2, true), 3720);
+        assert_eq!(FEES.input_fee(), 1470);
+        assert_eq!(FEES.cost_of_change(), 1810);
+        let free = FeeModel { fee_price: 0.0, ..FEES };
+        assert_eq!(free.fee(10, true), 0);
+        assert_eq!(free.cost_of_change(), DUST_LIMIT);
+    }
+
+    #[test]
+    fn test_largest_first() {
+        let utxos = utxos(&[1000, 50_000, 30_000, 100_000]);
+        let selected = LargestFirst.select(&utxos, 120_000, &FEES).unwrap();
+        assert_eq!(values(&selected), vec![100_000, 50_000]);
+        // fees of the second input are not covered by the value of the largest one
+        let selected = LargestFirst.select(&utxos, 98_500, &FEES).unwrap();
+        assert_eq!(values(&selected), vec![100_000, 50_000]);
+        // 1000 satoshis input costs more than it's worth
+        assert!(LargestFirst.select(&utxos, 176_000, &FEES).is_none());
+    }
+
+    #[test]
+    fn test_smallest_first() {
+        let utxos = utxos(&[1000, 50_000, 30_000, 100_000]);
+        let selected = SmallestFirst.select(&utxos, 60_000, &FEES).unwrap();
+        assert_eq!(values(&selected), vec![30_000, 50_000]);
+        let free = FeeModel { fee_price: 0.0, ..FEES };
+        let selected = SmallestFirst.select(&utxos, 60_000, &free).unwrap();
+        assert_eq!(values(&selected), vec![1000, 30_000, 50_000]);
+        assert!(SmallestFirst.select(&utxos, 200_000, &FEES).is_none());
+    }
+
+    #[test]
+    fn test_branch_and_bound() {
+        let utxos = utxos(&[100_000, 50_000, 30_000, 20_000]);
+        // 50_000 + 30_000 pay value and fees exactly, without change
+        let value = 80_000 - FEES.fee(2, false);
+        let selected = BranchAndBound.select(&utxos, value, &FEES).unwrap();
+        assert_eq!(values(&selected), vec![50_000, 30_000]);
+        // excess is less than cost of change
+        let selected = BranchAndBound.select(&utxos, value - 1000, &FEES).unwrap();
+        assert_eq!(values(&selected), vec![50_000, 30_000]);
+        // no changeless selection, falls back to largest first
+        let selected = BranchAndBound.select(&utxos, 10_000, &FEES).unwrap();
+        assert_eq!(values(&selected), vec![100_000]);
+        assert!(BranchAndBound.select(&utxos, 200_000, &FEES).is_none());
+    }
+}
@@ -29,10 +29,10 @@ pub enum ErrorContext {
     MalformedStqContractAddress,
     #[fail(display = "overflow")]
     Overflow,
+    #[fail(display = "error deriving key from master seed")]
+    Derivation,
     #[fail(display = "error signing message")]
     Signature,
-    #[fail(display = "no transaction outputs")]
-    NoTxOutputs,
     #[fail(display = "wrong fees")]
     WrongFee,
     #[fail(display = "wrong inputs")]
@@ -44,6 +44,8 @@ pub enum ErrorContext {
 pub enum ValidationError {
     #[fail(display = "malformed address")]
     MalformedAddress { value: String },
+    #[fail(display = "malformed address prefix")]
+    MalformedAddressPrefix { value: String },
     #[fail(display = "malformed hex string")]
     MalformedHexString { value: String },
     #[fail(display = "malformed private key")]
@@ -2,15 +2,17 @@ use rlp;
 use std::str::FromStr;
 
 use super::error::*;
-use super::utils::{bytes_to_hex, hex_to_bytes};
+use super::hd::{derive_secret, ETH_COIN_TYPE};
+use super::utils::{bytes_to_hex, hex_to_bytes, malformed_address_prefix, malformed_private_key};
 use super::BlockchainService;
 use ethcore_transaction::{Action, Transaction};
 use ethereum_types::{H160, U256};
-use ethkey::{Generator, Random};
+use ethkey::{Generator, Prefix, Random};
 use ethkey::{KeyPair, Secret};
 use failure::err_msg;
 use models::*;
 use prelude::*;
+use utils::{decode_hex, wipe};
 
 #[derive(Default)]
 pub struct EthereumService {
@@ -43,28 +45,75 @@ impl EthereumService {
 }
 
 impl BlockchainService for EthereumService {
-    fn derive_address(&self, _currency: Currency, key: PrivateKey) -> Result<BlockchainAddress, Error> {
-        let secret = private_key_to_secret(key.clone())?;
-        Ok(BlockchainAddress::new(format!(
-            "{:x}",
-            KeyPair::from_secret(secret)
-                .map_err({
-                    let error = ValidationError::MalformedPrivateKey {
-                        value: key.clone().into_inner(),
-                    };
-                    ectx!(try ErrorKind::InvalidPrivateKey(error))
-                })?
-                .address()
-        )))
+    fn derive_address(&self, _currency: Currency, _wallet_type: Option<WalletType>, key: &PrivateKey) -> Result<BlockchainAddress, Error> {
+        let secret = private_key_to_secret(key)?;
+        let pair = KeyPair::from_secret(secret).map_err(|_| malformed_private_key())?;
+        Ok(BlockchainAddress::new(format!("{:x}", pair.address())))
+    }
+    fn parse_private_key(&self, _currency: Currency, key: RawPrivateKey) -> Result<PrivateKey, Error> {
+        let hex = key.raw().trim_start_matches("0x");
+        let mut bytes = decode_hex(hex).map_err(|_| malformed_private_key())?;
+        let secret = Secret::from_unsafe_slice(&bytes);
+        wipe(&mut bytes);
+        let secret = secret.map_err(|_| malformed_private_key())?;
+        Ok(PrivateKey::from_bytes(&secret))
     }
-    fn generate_key(&self, _currency: Currency) -> Result<(PrivateKey, BlockchainAddress), Error> {
+    fn export_private_key(&self, _currency: Currency, key: &PrivateKey) -> Result<PrivateKey, Error> {
+        // Secret is stored as is
+        Ok(PrivateKey::new(key.raw().to_string()))
+    }
+    fn generate_key(&self, _currency: Currency, _wallet_type: Option<WalletType>) -> Result<(PrivateKey, BlockchainAddress), Error> {
         let mut random = Random;
         let pair = random.generate().map_err(ectx!(try ErrorSource::Random, ErrorKind::Internal))?;
-        let private_key = PrivateKey::new(format!("{:x}", pair.secret()));
+        let private_key = PrivateKey::from_bytes(pair.secret());
+        let blockchain_address = BlockchainAddress::new(format!("{:x}", pair.address()));
+        Ok((private_key, blockchain_address))
+    }
+    fn generate_vanity_key(
+        &self,
+        _currency: Currency,
+        prefix: &str,
+        iterations: usize,
+    ) -> Result<Option<(PrivateKey, BlockchainAddress)>, Error> {
+        // Address is 20 bytes, prefix is matched by whole bytes
+        let prefix_bytes = match decode_hex(prefix) {
+            Ok(bytes) => bytes,
+            Err(_) => return Err(malformed_address_prefix(prefix)),
+        };
+        if prefix_bytes.is_empty() || prefix_bytes.len() > 20 {
+            return Err(malformed_address_prefix(prefix));
+        }
+        // Prefix generator fails only when iterations are exhausted, since random keys are always valid
+        let pair = match Prefix::new(prefix_bytes, iterations).generate() {
+            Ok(pair) => pair,
+            Err(_) => return Ok(None),
+        };
+        let private_key = PrivateKey::from_bytes(pair.secret());
+        let blockchain_address = BlockchainAddress::new(format!("{:x}", pair.address()));
+        Ok(Some((private_key, blockchain_address)))
+    }
+    fn account_derivation_path(&self, currency: Currency, _wallet_type: Option<WalletType>) -> DerivationPath {
+        // STQ keys are ethereum keys too, separate account keeps them apart from ETH keys
+        let account = match currency {
+            Currency::Stq => 1,
+            _ => 0,
+        };
+        DerivationPath::bip44_account(ETH_COIN_TYPE, account)
+    }
+    fn derive_key(
+        &self,
+        _currency: Currency,
+        _wallet_type: Option<WalletType>,
+        seed: &MasterSeed,
+        path: &DerivationPath,
+    ) -> Result<(PrivateKey, BlockchainAddress), Error> {
+        let secret = derive_secret(seed, path)?;
+        let pair = KeyPair::from_secret(secret).map_err(ectx!(try ErrorContext::Derivation, ErrorKind::Internal => path))?;
+        let private_key = PrivateKey::from_bytes(pair.secret());
         let blockchain_address = BlockchainAddress::new(format!("{:x}", pair.address()));
         Ok((private_key, blockchain_address))
     }
-    fn approve(&self, key: PrivateKey, input: ApproveInput) -> Result<RawTransaction, Error> {
+    fn approve(&self, key: &PrivateKey, input: ApproveInput) -> Result<RawTransaction, Error> {
         let ApproveInput {
             approve_address,
             value,
@@ -104,7 +153,7 @@ impl BlockchainService for EthereumService {
         let raw_hex_data = bytes_to_hex(&raw_data);
         Ok(RawTransaction::new(raw_hex_data))
     }
-    fn sign(&self, key: PrivateKey, tx: UnsignedTransaction) -> Result<RawTransaction, Error> {
+    fn sign(&self, key: &PrivateKey, tx: UnsignedTransaction) -> Result<RawTransaction, Error> {
         let UnsignedTransaction {
             from,
             to,
@@ -202,16 +251,12 @@ impl BlockchainService for EthereumService {
     }
 }
 
-fn private_key_to_secret(key: PrivateKey) -> Result<Secret, Error> {
-    let hex_pk = key.clone().into_inner();
-    let bytes = hex_to_bytes(hex_pk.clone()).map_err({
-        let error = ValidationError::MalformedPrivateKey { value: hex_pk.clone() };
-        ectx!(try ErrorKind::InvalidPrivateKey(error))
-    })?;
-    Secret::from_slice(&bytes).ok_or({
-        let error = ValidationError::MalformedPrivateKey { value: hex_pk };
-        ErrorKind::InvalidPrivateKey(error).into()
-    })
+/// Secret wipes its memory on drop, errors don't contain the key
+fn private_key_to_secret(key: &PrivateKey) -> Result<Secret, Error> {
+    key.with_bytes(Secret::from_slice)
+        .ok()
+        .and_then(|secret| secret)
+        .ok_or_else(malformed_private_key)
 }
 
 fn serialize_amount(amount: Amount) -> Vec<u8> {
@@ -237,6 +282,63 @@ mod tests {
     use super::super::BlockchainService;
     use super::*;
 
+    #[test]
+    fn test_derive_key() {
+        let ethereum_service = EthereumService::default();
+        // seed of "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about" mnemonic
+        let seed = MasterSeed::new(
+            decode_hex(
+                "5eb00bbddcf069084889a8ab9155568165f5c453ccb85e70811aaed6f6da5fc19a5ac40b389cd370d086206dec8aa6c43daea6690f20ad3d8d48b2d2ce9e38e4",
+            )
+            .unwrap(),
+        );
+        let path = ethereum_service.account_derivation_path(Currency::Eth, None).address(0);
+        assert_eq!(path.to_string(), "m/44'/60'/0'/0/0");
+        let (private_key, address) = ethereum_service.derive_key(Currency::Eth, None, &seed, &path).unwrap();
+        assert_eq!(
+            address,
+            BlockchainAddress::new("9858effd232b4033e47d90003d41ec34ecaeda94".to_string())
+        );
+        assert_eq!(ethereum_service.derive_address(Currency::Eth, None, &private_key).unwrap(), address);
+        let stq_path = ethereum_service.account_derivation_path(Currency::Stq, None).address(0);
+        assert_ne!(ethereum_service.derive_key(Currency::Stq, None, &seed, &stq_path).unwrap().1, address);
+    }
+
+    #[test]
+    fn test_parse_private_key() {
+        let ethereum_service = EthereumService::default();
+        let hex = "b3c0e85a511cc6d21423a386de29dcf2cda6b2f2fa5ebb47948401bbb90458db";
+        let private_key = ethereum_service
+            .parse_private_key(Currency::Eth, RawPrivateKey::new(hex.to_string()))
+            .unwrap();
+        assert_eq!(private_key.raw(), hex);
+        let prefixed = ethereum_service
+            .parse_private_key(Currency::Eth, RawPrivateKey::new(format!("0x{}", hex)))
+            .unwrap();
+        assert_eq!(prefixed.raw(), hex);
+        assert!(ethereum_service
+            .parse_private_key(Currency::Eth, RawPrivateKey::new(hex[2..].to_string()))
+            .is_err());
+        assert!(ethereum_service
+            .parse_private_key(Currency::Eth, RawPrivateKey::new("0".repeat(64)))
+            .is_err());
+    }
+
+    #[test]
+    fn test_generate_vanity_key() {
+        let ethereum_service = EthereumService::default();
+        let (private_key, address) = ethereum_service
+            .generate_vanity_key(Currency::Eth, "5a", usize::max_value())
+            .unwrap()
+            .unwrap();
+        assert!(address.clone().into_inner().starts_with("5a"));
+        assert_eq!(ethereum_service.derive_address(Currency::Eth, None, &private_key).unwrap(), address);
+        assert!(ethereum_service.generate_vanity_key(Currency::Eth, "5a", 0).unwrap().is_none());
+        for prefix in &["", "5", "5g", "0x5a"] {
+            assert!(ethereum_service.generate_vanity_key(Currency::Eth, prefix, 1).is_err());
+        }
+    }
+
     #[test]
     fn test_sign() {
         let ethereum_service = EthereumService {
@@ -262,6 +364,7 @@ mod tests {
                     fee_price: 30000000000.0f64,
                     nonce: Some(0),
                     utxos: None,
+                    coin_selection: None,
                 },
                 "f86e808506fc23ac00830186a09400d44dd2f6a2d2005326db58ec5137204c5cba5a89015af1d78b58c400008077a09bb23536f025bc054d87c68faf2dcb99141a0be6ab28ea888974d4a9b5d9473ca0436070757106922b3c65c81592d5c8ea55fac876b78b8c5ce946711ff8c74cb4",
             ),
@@ -275,13 +378,14 @@ mod tests {
                     fee_price: 30000000000.0f64,
                     nonce: Some(0),
                     utxos: None,
+                    coin_selection: None,
                 },
                 "f8ca808506fc23ac00830186a0941bf2092a42166b2ae19b7b23752e7d2dab5ba91a80b86423b872dd000000000000000000000000b3683b4de1fc502807464b55d151e8e2d2c19cb500000000000000000000000000d44dd2f6a2d2005326db58ec5137204c5cba5a0000000000000000000000000000000000000000000000015af1d78b58c4000078a0beba6b3493ea0a04c8fc45b4c85e44bbb6367cc4a96b200b35507ab80e8d5b03a03b164cd3c5a235b280b8fc47be3e58b14ee67992ada8fc64d6773175eda0f1b8",
             ),
         ];
         for case in cases.into_iter() {
             let (input, expected) = case.clone();
-            let output = ethereum_service.sign(private_key.clone(), input).unwrap();
+            let output = ethereum_service.sign(&private_key, input).unwrap();
             assert_eq!(output, RawTransaction::new(expected.to_string()));
         }
     }
@@ -316,7 +420,7 @@ mod tests {
         ];
         for case in cases.into_iter() {
             let (input, expected) = case.clone();
-            let output = ethereum_service.approve(private_key.clone(), input).unwrap();
+            let output = ethereum_service.approve(&private_key, input).unwrap();
             assert_eq!(output, RawTransaction::new(expected.to_string()));
         }
     }
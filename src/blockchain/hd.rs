@@ -0,0 +1,197 @@
+//! BIP32 key derivation from master seed
+
+use base58::ToBase58;
+use btccrypto::{checksum, dhash160};
+use ethkey::{Derivation, ExtendedKeyPair, Public, Secret};
+
+use super::error::*;
+use config::BtcNetwork;
+use models::*;
+
+/// BIP44 coin types, https://github.com/satoshilabs/slips/blob/master/slip-0044.md
+pub const BTC_COIN_TYPE: u32 = 0;
+pub const BTC_TESTNET_COIN_TYPE: u32 = 1;
+pub const ETH_COIN_TYPE: u32 = 60;
+
+/// BIP32 version bytes of serialized extended public keys
+const XPUB_VERSION: [u8; 4] = [0x04, 0x88, 0xb2, 0x1e];
+const TPUB_VERSION: [u8; 4] = [0x04, 0x35, 0x87, 0xcf];
+/// Version bytes of P2WPKH and P2SH-P2WPKH keys, https://github.com/satoshilabs/slips/blob/master/slip-0132.md
+const ZPUB_VERSION: [u8; 4] = [0x04, 0xb2, 0x47, 0x46];
+const VPUB_VERSION: [u8; 4] = [0x04, 0x5f, 0x1c, 0xf6];
+const YPUB_VERSION: [u8; 4] = [0x04, 0x9d, 0x7c, 0xb2];
+const UPUB_VERSION: [u8; 4] = [0x04, 0x4a, 0x52, 0x62];
+
+/// Derives secret at `path` from master seed
+pub fn derive_secret(seed: &MasterSeed, path: &DerivationPath) -> Result<Secret, Error> {
+    let (key_pair, _) = derive_key_pair(seed, path)?;
+    Ok(key_pair.secret().as_raw().clone())
+}
+
+/// Derives extended public key at `path` from master seed, serialized in BIP32 format:
+/// `xpub` for mainnet and `tpub` for testnet, `zpub` and `vpub` for P2WPKH keys, `ypub` and `upub` for P2SH-P2WPKH keys
+pub fn derive_extended_public_key(
+    seed: &MasterSeed,
+    path: &DerivationPath,
+    btc_network: &BtcNetwork,
+    wallet_type: Option<WalletType>,
+) -> Result<ExtendedPublicKey, Error> {
+    let (key_pair, parent_public) = derive_key_pair(seed, path)?;
+    let indexes = path
+        .indexes()
+        .map_err(ectx!(try ErrorContext::Derivation, ErrorKind::Internal => path))?;
+    let version = match (btc_network, wallet_type) {
+        (BtcNetwork::Main, Some(WalletType::P2wpkh)) => ZPUB_VERSION,
+        (BtcNetwork::Test, Some(WalletType::P2wpkh)) => VPUB_VERSION,
+        (BtcNetwork::Main, Some(WalletType::P2shP2wpkh)) => YPUB_VERSION,
+        (BtcNetwork::Test, Some(WalletType::P2shP2wpkh)) => UPUB_VERSION,
+        (BtcNetwork::Main, _) => XPUB_VERSION,
+        (BtcNetwork::Test, _) => TPUB_VERSION,
+    };
+    let fingerprint = match parent_public {
+        Some(public) => dhash160(&compress_public(&public))[0..4].to_vec(),
+        None => vec![0u8; 4],
+    };
+    let child_number = indexes.last().cloned().unwrap_or(0);
+    let mut data = Vec::with_capacity(82);
+    data.extend_from_slice(&version);
+    data.push(indexes.len() as u8);
+    data.extend_from_slice(&fingerprint);
+    data.extend_from_slice(&[
+        (child_number >> 24) as u8,
+        (child_number >> 16) as u8,
+        (child_number >> 8) as u8,
+        child_number as u8,
+    ]);
+    data.extend_from_slice(&**key_pair.public().chain_code());
+    data.extend_from_slice(&compress_public(key_pair.public().public()));
+    let data_checksum = checksum(&data);
+    data.extend_from_slice(&*data_checksum);
+    Ok(ExtendedPublicKey::new(data.to_base58()))
+}
+
+/// Derives extended key pair at `path` from master seed, together with public key of its parent
+fn derive_key_pair(seed: &MasterSeed, path: &DerivationPath) -> Result<(ExtendedKeyPair, Option<Public>), Error> {
+    let indexes = path
+        .indexes()
+        .map_err(ectx!(try ErrorContext::Derivation, ErrorKind::Internal => path))?;
+    let mut key_pair = ExtendedKeyPair::with_seed(seed.as_bytes()).map_err(|e| {
+        let e = format_err!("{:?}", e);
+        ectx!(try err e, ErrorContext::Derivation, ErrorKind::Internal)
+    })?;
+    let mut parent_public = None;
+    for index in indexes {
+        parent_public = Some(key_pair.public().public().clone());
+        // Derivation treats indexes with the highest bit set as hardened
+        key_pair = key_pair.derive(Derivation::from(index)).map_err(|e| {
+            let e = format_err!("{:?}", e);
+            ectx!(try err e, ErrorContext::Derivation, ErrorKind::Internal => path)
+        })?;
+    }
+    Ok((key_pair, parent_public))
+}
+
+/// SEC1 compressed form of uncompressed `x || y` public key
+fn compress_public(public: &Public) -> Vec<u8> {
+    let mut compressed = Vec::with_capacity(33);
+    compressed.push(0x02 | (public[63] & 1));
+    compressed.extend_from_slice(&public[0..32]);
+    compressed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::decode_hex;
+
+    const MNEMONIC: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+    // https://github.com/bitcoin/bips/blob/master/bip-0032.mediawiki#test-vector-1
+    #[test]
+    fn test_derive_secret() {
+        let seed = MasterSeed::new(decode_hex("000102030405060708090a0b0c0d0e0f").unwrap());
+        let cases = [
+            ("m", "e8f32e723decf4051aefac8e2c93c9c5b214313817cdb01a1494b917c8436b35"),
+            ("m/0'", "edb2e14f9ee77d26dd93b4ecede8d16ed408ce149b6cd80b0715a2d911a0afea"),
+            ("m/0'/1", "3c6cb8d0f6a264c91ea8b5030fadaa8e538b020f0a387421a12de9319dc93368"),
+            ("m/0'/1/2'", "cbce0d719ecf7431d88e6a89fa1483e02e35092af60c042b1df2ff59fa424dca"),
+            ("m/0'/1/2'/2", "0f479245fb19a38a1954c5c7c0ebab2f9bdfd96a17563ef28a6a4b1a2a764ef4"),
+            (
+                "m/0'/1/2'/2/1000000000",
+                "471b76e389e528d6de6d816857e012c5455051cad6660850e58372a6c3e6e7c8",
+            ),
+        ];
+        for (path, secret) in cases.iter() {
+            let path: DerivationPath = path.parse().unwrap();
+            assert_eq!(format!("{:x}", derive_secret(&seed, &path).unwrap()), *secret);
+        }
+    }
+
+    #[test]
+    fn test_derive_extended_public_key() {
+        let seed = MasterSeed::new(decode_hex("000102030405060708090a0b0c0d0e0f").unwrap());
+        let cases = [
+            (
+                "m",
+                "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8",
+            ),
+            (
+                "m/0'",
+                "xpub68Gmy5EdvgibQVfPdqkBBCHxA5htiqg55crXYuXoQRKfDBFA1WEjWgP6LHhwBZeNK1VTsfTFUHCdrfp1bgwQ9xv5ski8PX9rL2dZXvgGDnw",
+            ),
+        ];
+        for (path, xpub) in cases.iter() {
+            let path: DerivationPath = path.parse().unwrap();
+            let extended_public_key = derive_extended_public_key(&seed, &path, &BtcNetwork::Main, None).unwrap();
+            assert_eq!(extended_public_key.raw(), *xpub);
+        }
+        let path: DerivationPath = "m".parse().unwrap();
+        let tpub = derive_extended_public_key(&seed, &path, &BtcNetwork::Test, None).unwrap();
+        assert!(tpub.raw().starts_with("tpub"));
+    }
+
+    // https://github.com/bitcoin/bips/blob/master/bip-0084.mediawiki#test-vectors
+    #[test]
+    fn test_derive_segwit_extended_public_key() {
+        let mnemonic = Mnemonic::new(MNEMONIC.to_string());
+        let seed = MasterSeed::from_mnemonic(&mnemonic, &Passphrase::default()).unwrap();
+        let path = DerivationPath::account(BIP84_PURPOSE, BTC_COIN_TYPE, 0);
+        let zpub = derive_extended_public_key(&seed, &path, &BtcNetwork::Main, Some(WalletType::P2wpkh)).unwrap();
+        assert_eq!(
+            zpub.raw(),
+            "zpub6rFR7y4Q2AijBEqTUquhVz398htDFrtymD9xYYfG1m4wAcvPhXNfE3EfH1r1ADqtfSdVCToUG868RvUUkgDKf31mGDtKsAYz2oz2AGutZYs"
+        );
+        let path = DerivationPath::account(BIP84_PURPOSE, BTC_TESTNET_COIN_TYPE, 0);
+        let vpub = derive_extended_public_key(&seed, &path, &BtcNetwork::Test, Some(WalletType::P2wpkh)).unwrap();
+        assert_eq!(
+            vpub.raw(),
+            "vpub5Y6cjg78GGuNLsaPhmYsiw4gYX3HoQiRBiSwDaBXKUafCt9bNwWQiitDk5VZ5BVxYnQdwoTyXSs2JHRPAgjAvtbBrf8ZhDYe2jWAqvZVnsc"
+        );
+        let path = DerivationPath::account(BIP49_PURPOSE, BTC_COIN_TYPE, 0);
+        let ypub = derive_extended_public_key(&seed, &path, &BtcNetwork::Main, Some(WalletType::P2shP2wpkh)).unwrap();
+        assert_eq!(
+            ypub.raw(),
+            "ypub6Ww3ibxVfGzLrAH1PNcjyAWenMTbbAosGNB6VvmSEgytSER9azLDWCxoJwW7Ke7icmizBMXrzBx9979FfaHxHcrArf3zbeJJJUZPf663zsP"
+        );
+        let path = DerivationPath::account(BIP49_PURPOSE, BTC_TESTNET_COIN_TYPE, 0);
+        let upub = derive_extended_public_key(&seed, &path, &BtcNetwork::Test, Some(WalletType::P2shP2wpkh)).unwrap();
+        assert_eq!(
+            upub.raw(),
+            "upub5EFU65HtV5TeiSHmZZm7FUffBGy8UKeqp7vw43jYbvZPpoVsgU93oac7Wk3u6moKegAEWtGNF8DehrnHtv21XXEMYRUocHqguyjknFHYfgY"
+        );
+    }
+
+    #[test]
+    fn test_public_derivation_matches_private() {
+        let seed = MasterSeed::new(decode_hex("000102030405060708090a0b0c0d0e0f").unwrap());
+        let account = DerivationPath::bip44_account(BTC_COIN_TYPE, 0);
+        let (account_key_pair, _) = derive_key_pair(&seed, &account).unwrap();
+        // Watch-only derivation of external chain addresses, as done with xpub outside of keystore
+        let external = account_key_pair.public().derive(Derivation::from(0)).unwrap();
+        for index in 0..3 {
+            let public = external.derive(Derivation::from(index)).unwrap();
+            let (key_pair, _) = derive_key_pair(&seed, &account.address(index)).unwrap();
+            assert_eq!(public.public(), key_pair.public().public());
+        }
+    }
+}
@@ -8,21 +8,77 @@ use models::*;
 pub struct BlockchainServiceMock;
 
 impl BlockchainService for BlockchainServiceMock {
-    fn derive_address(&self, _currency: Currency, _key: PrivateKey) -> Result<BlockchainAddress, Error> {
-        unimplemented!()
+    fn derive_address(&self, _currency: Currency, _wallet_type: Option<WalletType>, key: &PrivateKey) -> Result<BlockchainAddress, Error> {
+        Ok(BlockchainAddress::new(format!("address:{}", key.raw())))
     }
-    fn generate_key(&self, _currency: Currency) -> Result<(PrivateKey, BlockchainAddress), Error> {
+    fn parse_private_key(&self, _currency: Currency, key: RawPrivateKey) -> Result<PrivateKey, Error> {
+        if key.raw().is_empty() {
+            return Err(ErrorKind::Internal.into());
+        }
+        Ok(PrivateKey::new(key.raw().to_string()))
+    }
+    fn export_private_key(&self, _currency: Currency, key: &PrivateKey) -> Result<PrivateKey, Error> {
+        Ok(PrivateKey::new(key.raw().to_string()))
+    }
+    fn generate_key(&self, _currency: Currency, _wallet_type: Option<WalletType>) -> Result<(PrivateKey, BlockchainAddress), Error> {
         let key: String = thread_rng().sample_iter(&Alphanumeric).take(30).collect();
         let address: String = thread_rng().sample_iter(&Alphanumeric).take(15).collect();
         Ok((PrivateKey::new(key), BlockchainAddress::new(address)))
     }
 
-    fn sign(&self, _key: PrivateKey, _tx: UnsignedTransaction) -> Result<RawTransaction, Error> {
+    fn generate_vanity_key(
+        &self,
+        _currency: Currency,
+        prefix: &str,
+        iterations: usize,
+    ) -> Result<Option<(PrivateKey, BlockchainAddress)>, Error> {
+        if prefix.is_empty() {
+            return Err(ErrorKind::Internal.into());
+        }
+        if iterations == 0 {
+            return Ok(None);
+        }
+        let (key, address) = self.generate_key(Currency::Eth, None)?;
+        Ok(Some((key, BlockchainAddress::new(format!("{}{}", prefix, address.into_inner())))))
+    }
+
+    fn account_derivation_path(&self, _currency: Currency, wallet_type: Option<WalletType>) -> DerivationPath {
+        DerivationPath::account(derivation_purpose(wallet_type), 0, 0)
+    }
+
+    fn derive_key(
+        &self,
+        _currency: Currency,
+        _wallet_type: Option<WalletType>,
+        seed: &MasterSeed,
+        path: &DerivationPath,
+    ) -> Result<(PrivateKey, BlockchainAddress), Error> {
+        let key = format!("{:?}:{}", seed.as_bytes(), path);
+        let address = format!("address:{}", key);
+        Ok((PrivateKey::new(key), BlockchainAddress::new(address)))
+    }
+
+    fn derive_extended_public_key(
+        &self,
+        _currency: Currency,
+        wallet_type: Option<WalletType>,
+        seed: &MasterSeed,
+        path: &DerivationPath,
+    ) -> Result<ExtendedPublicKey, Error> {
+        Ok(ExtendedPublicKey::new(format!(
+            "xpub:{:?}:{:?}:{}",
+            wallet_type,
+            seed.as_bytes(),
+            path
+        )))
+    }
+
+    fn sign(&self, _key: &PrivateKey, _tx: UnsignedTransaction) -> Result<RawTransaction, Error> {
         let tx: String = thread_rng().sample_iter(&Alphanumeric).take(30).collect();
         Ok(RawTransaction::new(tx))
     }
 
-    fn approve(&self, _key: PrivateKey, _tx: ApproveInput) -> Result<RawTransaction, Error> {
+    fn approve(&self, _key: &PrivateKey, _tx: ApproveInput) -> Result<RawTransaction, Error> {
         let tx: String = thread_rng().sample_iter(&Alphanumeric).take(30).collect();
         Ok(RawTransaction::new(tx))
     }
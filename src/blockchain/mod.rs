@@ -1,6 +1,9 @@
+mod bech32;
 mod bitcoin;
+mod coin_selection;
 mod error;
 mod ethereum;
+mod hd;
 #[cfg(test)]
 mod mocks;
 mod utils;
@@ -11,20 +14,56 @@ pub use self::mocks::*;
 
 use self::bitcoin::BitcoinService;
 use self::ethereum::EthereumService;
+use self::hd::derive_extended_public_key;
 use config::BtcNetwork;
 
 use models::*;
 
 pub trait BlockchainService: Send + Sync + 'static {
-    fn sign(&self, key: PrivateKey, tx: UnsignedTransaction) -> Result<RawTransaction, Error>;
-    fn approve(&self, key: PrivateKey, tx: ApproveInput) -> Result<RawTransaction, Error>;
-    fn generate_key(&self, currency: Currency) -> Result<(PrivateKey, BlockchainAddress), Error>;
-    fn derive_address(&self, currency: Currency, key: PrivateKey) -> Result<BlockchainAddress, Error>;
+    fn sign(&self, key: &PrivateKey, tx: UnsignedTransaction) -> Result<RawTransaction, Error>;
+    fn approve(&self, key: &PrivateKey, tx: ApproveInput) -> Result<RawTransaction, Error>;
+    /// `wallet_type` is the address type of bitcoin keys, it's ignored for other currencies
+    fn generate_key(&self, currency: Currency, wallet_type: Option<WalletType>) -> Result<(PrivateKey, BlockchainAddress), Error>;
+    /// Generates up to `iterations` random keys until address of one of them starts with `prefix` -
+    /// hex bytes for ethereum and base58 string for bitcoin. Returns `None` if no key is found.
+    fn generate_vanity_key(
+        &self,
+        currency: Currency,
+        prefix: &str,
+        iterations: usize,
+    ) -> Result<Option<(PrivateKey, BlockchainAddress)>, Error>;
+    fn derive_address(&self, currency: Currency, wallet_type: Option<WalletType>, key: &PrivateKey) -> Result<BlockchainAddress, Error>;
+    /// Converts user supplied private key to the format it's stored in. Accepts hex and also WIF for bitcoin.
+    /// Doesn't check that the key is valid for signing, `derive_address` does.
+    fn parse_private_key(&self, currency: Currency, key: RawPrivateKey) -> Result<PrivateKey, Error>;
+    /// Converts stored private key to the format wallets import: WIF (as ascii bytes) for bitcoin, secret for ethereum
+    fn export_private_key(&self, currency: Currency, key: &PrivateKey) -> Result<PrivateKey, Error>;
+    /// BIP44 account path (`m/purpose'/coin_type'/account'`) of keys of the currency. Bitcoin keys of
+    /// every wallet type have their own purpose, e.g. BIP84 `m/84'/...` for P2WPKH keys.
+    fn account_derivation_path(&self, currency: Currency, wallet_type: Option<WalletType>) -> DerivationPath;
+    /// Derives key at BIP32 `path` from master seed
+    fn derive_key(
+        &self,
+        currency: Currency,
+        wallet_type: Option<WalletType>,
+        seed: &MasterSeed,
+        path: &DerivationPath,
+    ) -> Result<(PrivateKey, BlockchainAddress), Error>;
+    /// Derives BIP32 serialized extended public key at `path` from master seed, so that
+    /// child keys can be derived without the seed. Version bytes tell wallets the address format of `wallet_type`.
+    fn derive_extended_public_key(
+        &self,
+        currency: Currency,
+        wallet_type: Option<WalletType>,
+        seed: &MasterSeed,
+        path: &DerivationPath,
+    ) -> Result<ExtendedPublicKey, Error>;
 }
 
 pub struct BlockchainServiceImpl {
     ethereum_service: EthereumService,
     bitcoin_service: BitcoinService,
+    btc_network: BtcNetwork,
 }
 
 impl BlockchainServiceImpl {
@@ -36,6 +75,7 @@ impl BlockchainServiceImpl {
         stq_approve_method_number: String,
         chain_id: Option<u64>,
         btc_network: BtcNetwork,
+        btc_coin_selection: CoinSelection,
     ) -> Self {
         let ethereum_service = EthereumService::new(
             stq_gas_limit,
@@ -45,35 +85,93 @@ impl BlockchainServiceImpl {
             stq_approve_method_number,
             chain_id,
         );
-        let bitcoin_service = BitcoinService::new(btc_network);
+        let bitcoin_service = BitcoinService::new(btc_network.clone(), btc_coin_selection);
         Self {
             ethereum_service,
             bitcoin_service,
+            btc_network,
         }
     }
 }
 
 impl BlockchainService for BlockchainServiceImpl {
-    fn sign(&self, key: PrivateKey, tx: UnsignedTransaction) -> Result<RawTransaction, Error> {
+    fn sign(&self, key: &PrivateKey, tx: UnsignedTransaction) -> Result<RawTransaction, Error> {
         match tx.currency {
             Currency::Eth | Currency::Stq => self.ethereum_service.sign(key, tx),
             Currency::Btc => self.bitcoin_service.sign(key, tx),
         }
     }
-    fn approve(&self, key: PrivateKey, input: ApproveInput) -> Result<RawTransaction, Error> {
+    fn approve(&self, key: &PrivateKey, input: ApproveInput) -> Result<RawTransaction, Error> {
         self.ethereum_service.approve(key, input)
     }
-    fn generate_key(&self, currency: Currency) -> Result<(PrivateKey, BlockchainAddress), Error> {
+    fn generate_key(&self, currency: Currency, wallet_type: Option<WalletType>) -> Result<(PrivateKey, BlockchainAddress), Error> {
         match currency {
-            Currency::Eth | Currency::Stq => self.ethereum_service.generate_key(currency),
-            Currency::Btc => self.bitcoin_service.generate_key(currency),
+            Currency::Eth | Currency::Stq => self.ethereum_service.generate_key(currency, wallet_type),
+            Currency::Btc => self.bitcoin_service.generate_key(currency, wallet_type),
         }
     }
 
-    fn derive_address(&self, currency: Currency, key: PrivateKey) -> Result<BlockchainAddress, Error> {
+    fn generate_vanity_key(
+        &self,
+        currency: Currency,
+        prefix: &str,
+        iterations: usize,
+    ) -> Result<Option<(PrivateKey, BlockchainAddress)>, Error> {
         match currency {
-            Currency::Eth | Currency::Stq => self.ethereum_service.derive_address(currency, key),
-            Currency::Btc => self.bitcoin_service.derive_address(currency, key),
+            Currency::Eth | Currency::Stq => self.ethereum_service.generate_vanity_key(currency, prefix, iterations),
+            Currency::Btc => self.bitcoin_service.generate_vanity_key(currency, prefix, iterations),
         }
     }
+
+    fn derive_address(&self, currency: Currency, wallet_type: Option<WalletType>, key: &PrivateKey) -> Result<BlockchainAddress, Error> {
+        match currency {
+            Currency::Eth | Currency::Stq => self.ethereum_service.derive_address(currency, wallet_type, key),
+            Currency::Btc => self.bitcoin_service.derive_address(currency, wallet_type, key),
+        }
+    }
+
+    fn parse_private_key(&self, currency: Currency, key: RawPrivateKey) -> Result<PrivateKey, Error> {
+        match currency {
+            Currency::Eth | Currency::Stq => self.ethereum_service.parse_private_key(currency, key),
+            Currency::Btc => self.bitcoin_service.parse_private_key(currency, key),
+        }
+    }
+
+    fn export_private_key(&self, currency: Currency, key: &PrivateKey) -> Result<PrivateKey, Error> {
+        match currency {
+            Currency::Eth | Currency::Stq => self.ethereum_service.export_private_key(currency, key),
+            Currency::Btc => self.bitcoin_service.export_private_key(currency, key),
+        }
+    }
+
+    fn account_derivation_path(&self, currency: Currency, wallet_type: Option<WalletType>) -> DerivationPath {
+        match currency {
+            Currency::Eth | Currency::Stq => self.ethereum_service.account_derivation_path(currency, wallet_type),
+            Currency::Btc => self.bitcoin_service.account_derivation_path(currency, wallet_type),
+        }
+    }
+
+    fn derive_key(
+        &self,
+        currency: Currency,
+        wallet_type: Option<WalletType>,
+        seed: &MasterSeed,
+        path: &DerivationPath,
+    ) -> Result<(PrivateKey, BlockchainAddress), Error> {
+        match currency {
+            Currency::Eth | Currency::Stq => self.ethereum_service.derive_key(currency, wallet_type, seed, path),
+            Currency::Btc => self.bitcoin_service.derive_key(currency, wallet_type, seed, path),
+        }
+    }
+
+    fn derive_extended_public_key(
+        &self,
+        _currency: Currency,
+        wallet_type: Option<WalletType>,
+        seed: &MasterSeed,
+        path: &DerivationPath,
+    ) -> Result<ExtendedPublicKey, Error> {
+        // Version bytes follow the bitcoin network for all currencies, so that tooling sees testnet keys on testnet
+        derive_extended_public_key(seed, path, &self.btc_network, wallet_type)
+    }
 }
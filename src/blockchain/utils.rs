@@ -9,6 +9,19 @@ pub fn bytes_to_hex(bytes: &[u8]) -> String {
     res
 }
 
+/// Error of malformed private key, that doesn't contain the key itself
+pub fn malformed_private_key() -> Error {
+    let error = ValidationError::MalformedPrivateKey {
+        value: "********".to_string(),
+    };
+    ErrorKind::InvalidPrivateKey(error).into()
+}
+
+pub fn malformed_address_prefix(prefix: &str) -> Error {
+    let error = ValidationError::MalformedAddressPrefix { value: prefix.to_string() };
+    ErrorKind::Validation(error).into()
+}
+
 pub fn hex_to_bytes(hex: String) -> Result<Vec<u8>, Error> {
     let chars: Vec<char> = hex.clone().chars().collect();
     chars
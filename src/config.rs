@@ -1,10 +1,11 @@
 use serde;
 use serde::{Deserialize, Deserializer};
 use std::env;
+use std::fmt::{self, Debug, Display};
 
 use config_crate::{Config as RawConfig, ConfigError, Environment, File};
 use logger::{FileLogConfig, GrayLogConfig};
-use models::BlockchainAddress;
+use models::{BlockchainAddress, CoinSelection};
 use sentry_integration::SentryConfig;
 
 #[derive(Debug, Deserialize, Clone)]
@@ -12,6 +13,10 @@ pub struct Config {
     pub server: Server,
     pub database: Database,
     pub blockchain: Blockchain,
+    #[serde(default)]
+    pub master_key: MasterKey,
+    #[serde(default)]
+    pub vanity: Vanity,
     pub sentry: Option<SentryConfig>,
     pub graylog: Option<GrayLogConfig>,
     pub filelog: Option<FileLogConfig>,
@@ -40,7 +45,91 @@ pub struct Blockchain {
     #[serde(deserialize_with = "deserialize_btc_network")]
     pub btc_network: BtcNetwork,
     pub stq_controller_address: BlockchainAddress,
-    pub main_key: String,
+    /// Hex-encoded master key, used only with `config` master key provider
+    pub main_key: Option<String>,
+    #[serde(default)]
+    pub key_generation: KeyGeneration,
+    /// Coin selection of bitcoin transactions that don't set their own
+    #[serde(default)]
+    pub btc_coin_selection: CoinSelection,
+}
+
+/// How new keys are generated
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyGeneration {
+    /// Unrelated random keys
+    Random,
+    /// BIP44 keys derived from master seed of the key owner
+    UserSeed,
+    /// BIP44 keys derived from master seed of the system user
+    SystemSeed,
+}
+
+impl Default for KeyGeneration {
+    fn default() -> Self {
+        KeyGeneration::Random
+    }
+}
+
+/// Source of the master key, that wraps data keys of private keys
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "provider", rename_all = "lowercase")]
+pub enum MasterKey {
+    /// Hex-encoded key in `blockchain.main_key`, for development only
+    Config,
+    /// Hex-encoded key in environment variable
+    Env { var: String },
+    /// Hex-encoded key in file, e.g. mounted Kubernetes secret
+    File { path: String },
+    /// AES secret key object on PKCS#11 token, e.g. SoftHSM
+    Pkcs11 {
+        module: String,
+        token_label: String,
+        key_label: String,
+        /// Generic secret key object, that hashes of private keys are computed with
+        hash_key_label: String,
+        pin: Pkcs11Pin,
+    },
+    /// Combined from Shamir shares, submitted on startup to local admin endpoint
+    Shares { host: String, port: String },
+}
+
+impl Default for MasterKey {
+    fn default() -> Self {
+        MasterKey::Config
+    }
+}
+
+/// User PIN of PKCS#11 token, masked in logs and in printed config
+#[derive(Deserialize, Clone)]
+pub struct Pkcs11Pin(String);
+mask_logs!(Pkcs11Pin);
+
+impl Pkcs11Pin {
+    pub fn raw(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Limits of the search for keys with address prefix, see `create_vanity_key` command
+#[derive(Debug, Deserialize, Clone)]
+pub struct Vanity {
+    /// Size of the thread pool, that the search runs on
+    pub threads: usize,
+    /// Maximum number of generated keys
+    pub iterations: usize,
+    pub timeout_secs: u64,
+}
+
+impl Default for Vanity {
+    fn default() -> Self {
+        Vanity {
+            threads: 2,
+            iterations: 10_000_000,
+            timeout_secs: 600,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -15,7 +15,9 @@ extern crate validator_derive;
 #[macro_use]
 extern crate sentry;
 
+extern crate base58;
 extern crate base64;
+extern crate bip39;
 extern crate bitcrypto as btccrypto;
 extern crate chain as btcchain;
 extern crate chrono;
@@ -31,6 +33,7 @@ extern crate gelf;
 extern crate hyper;
 extern crate hyper_tls;
 extern crate keys as btckey;
+extern crate pkcs11 as pkcs11_crate;
 extern crate primitives as btcprimitives;
 extern crate r2d2;
 extern crate rand;
@@ -50,9 +53,11 @@ extern crate validator;
 #[macro_use]
 mod macros;
 mod api;
+mod backup;
 mod blockchain;
 mod config;
 mod logger;
+mod master_key;
 mod models;
 mod prelude;
 mod repos;
@@ -61,13 +66,31 @@ mod sentry_integration;
 mod services;
 mod utils;
 
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::process;
+use std::sync::Arc;
+
 use diesel::pg::PgConnection;
 use diesel::r2d2::ConnectionManager;
 use futures_cpupool::CpuPool;
 
-use self::models::NewUser;
+use self::backup::{BackupReader, BackupWriter};
+use self::blockchain::{BlockchainService, BlockchainServiceImpl};
+use self::master_key::{master_key_provider, MasterKeyProvider, Share};
+use self::models::{decode_aes_key, Currency, KeyId, KeyMetadata, Mnemonic, NewKey, NewUser, Passphrase, WalletType};
 use self::prelude::*;
-use self::repos::{DbExecutor, DbExecutorImpl, Error as ReposError, UsersRepo, UsersRepoImpl};
+use self::repos::{
+    DbExecutor, DbExecutorImpl, Error as ReposError, Isolation, KeysRepo, KeysRepoImpl, MasterKeyEncryptionProvider, SeedsRepoImpl,
+    UsersRepo, UsersRepoImpl,
+};
+use self::services::{
+    check_recover_count, recover_keys, recovered_wallet_types, restore_backup, store_mnemonic_seed, write_backup, BackupReport,
+    Error as ServiceError, ErrorContext as ServiceErrorContext, ErrorKind as ServiceErrorKind, VanityKeyGenerator,
+};
+use self::utils::{decode_hex, encode_hex, format_error, wipe};
 use config::Config;
 
 pub fn hello() {
@@ -87,7 +110,7 @@ pub fn start_server() {
     api::start_server(config);
 }
 
-pub fn create_user(name: &str) {
+pub fn create_user(name: &str, can_export_keys: bool, is_admin: bool) {
     let config = get_config();
     let db_pool = create_db_pool(&config);
     let cpu_pool = CpuPool::new(1);
@@ -95,6 +118,8 @@ pub fn create_user(name: &str) {
     let db_executor = DbExecutorImpl::new(db_pool, cpu_pool);
     let mut new_user: NewUser = Default::default();
     new_user.name = name.to_string();
+    new_user.can_export_keys = can_export_keys;
+    new_user.is_admin = is_admin;
     let fut = db_executor.execute(move || -> Result<(), ReposError> {
         let user = users_repo.create(new_user).expect("Failed to create user");
         println!("{}", user.authentication_token.raw());
@@ -103,6 +128,427 @@ pub fn create_user(name: &str) {
     hyper::rt::run(fut.map(|_| ()).map_err(|_| ()));
 }
 
+pub fn migrate_keys() {
+    let config = get_config();
+    let db_pool = create_db_pool(&config);
+    let cpu_pool = CpuPool::new(1);
+    let keys_repo = match KeysRepoImpl::new(&*master_key_provider(&config)) {
+        Ok(keys_repo) => keys_repo,
+        Err(e) => {
+            eprintln!("Failed to load master key:\n{}", format_error(&e));
+            process::exit(1);
+        }
+    };
+    let db_executor = DbExecutorImpl::new(db_pool, cpu_pool);
+    let fut = db_executor
+        .execute_transaction_with_isolation(Isolation::Serializable, move || {
+            let migrated = keys_repo.migrate_to_envelope_encryption()?;
+            let hashed = keys_repo.hash_private_keys()?;
+            Ok((migrated, hashed))
+        })
+        .map(|(migrated, hashed)| {
+            println!("Migrated {} keys", migrated);
+            println!("Hashed {} keys", hashed);
+        })
+        .map_err(|e: ReposError| {
+            eprintln!("Failed to migrate keys, no keys were changed:\n{}", format_error(&e));
+            process::exit(1);
+        });
+    hyper::rt::run(fut);
+}
+
+/// Re-wraps data keys with the master key selected in `master_key` config section. The old main key is read
+/// from stdin, so this is also the way to move keys from a hex main key to a PKCS#11 token or to shares.
+pub fn rotate_main_key() {
+    let config = get_config();
+    let old_key = match decode_aes_key(&read_line("Old main key")) {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("Invalid old main key: {}", e);
+            process::exit(1);
+        }
+    };
+    let new_provider = match master_key_provider(&config).key_encryption_provider() {
+        Ok(provider) => provider,
+        Err(e) => {
+            eprintln!("Failed to load new master key:\n{}", format_error(&e));
+            process::exit(1);
+        }
+    };
+    let db_pool = create_db_pool(&config);
+    let cpu_pool = CpuPool::new(1);
+    let old_provider = MasterKeyEncryptionProvider::new(old_key);
+    let keys_repo = KeysRepoImpl::with_key_encryption_provider(new_provider.clone());
+    let seeds_repo = SeedsRepoImpl::new(new_provider);
+    let blockchain_service = create_blockchain_service(&config);
+    let db_executor = DbExecutorImpl::new(db_pool, cpu_pool);
+    let fut = db_executor
+        .execute_transaction_with_isolation(Isolation::Serializable, move || {
+            let keys_report = keys_repo.rotate_key_encryption_key(&old_provider, |key, private_key| {
+                blockchain_service
+                    .derive_address(key.currency, key.wallet_type, private_key)
+                    .map(|address| address == key.blockchain_address)
+                    .unwrap_or(false)
+            })?;
+            let seeds_report = seeds_repo.rotate_key_encryption_key(&old_provider)?;
+            Ok((keys_report, seeds_report))
+        })
+        .map(|(keys_report, seeds_report)| {
+            println!(
+                "Rotated {} keys, skipped {} already rotated keys",
+                keys_report.rotated, keys_report.skipped
+            );
+            println!(
+                "Rotated {} seeds, skipped {} already rotated seeds",
+                seeds_report.rotated, seeds_report.skipped
+            );
+        })
+        .map_err(|e: ReposError| {
+            eprintln!("Failed to rotate main key, no keys were changed:\n{}", format_error(&e));
+            process::exit(1);
+        });
+    hyper::rt::run(fut);
+}
+
+/// Generates mnemonic for the master seed of the system user, that keys are derived from with `system_seed` key generation
+pub fn create_mnemonic(words: usize) {
+    let config = get_config();
+    let passphrase = Passphrase::new(read_line("Passphrase (empty for none)"));
+    let mnemonic = match Mnemonic::generate(words) {
+        Ok(mnemonic) => mnemonic,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let db_pool = create_db_pool(&config);
+    let cpu_pool = CpuPool::new(1);
+    let key_encryption_provider = match master_key_provider(&config).key_encryption_provider() {
+        Ok(provider) => provider,
+        Err(e) => {
+            eprintln!("Failed to load master key:\n{}", format_error(&e));
+            process::exit(1);
+        }
+    };
+    let seeds_repo = SeedsRepoImpl::new(key_encryption_provider);
+    let users_repo = UsersRepoImpl;
+    let db_executor = DbExecutorImpl::new(db_pool, cpu_pool);
+    let fut = db_executor
+        .execute_transaction(move || -> Result<Mnemonic, ServiceError> {
+            let system_user = users_repo
+                .find_system_user()?
+                .ok_or(ectx!(try err ServiceErrorContext::NoSystemUser, ServiceErrorKind::Internal))?;
+            store_mnemonic_seed(&seeds_repo, system_user.id, &mnemonic, &passphrase)?;
+            Ok(mnemonic)
+        })
+        .map(|mnemonic| {
+            eprintln!("Write down the mnemonic, it is not stored and will not be shown again:");
+            println!("{}", mnemonic.raw());
+        })
+        .map_err(|e| {
+            eprintln!("Failed to create mnemonic:\n{}", format_error(&e));
+            process::exit(1);
+        });
+    hyper::rt::run(fut);
+}
+
+/// Restores master seed of the system user from mnemonic and recreates the first `recover` keys of each currency.
+/// Bitcoin keys are recreated for each of `wallet_types`, or with the default wallet type if none is given.
+pub fn import_mnemonic(recover: u32, wallet_types: &[&str]) {
+    let config = get_config();
+    let wallet_types = match wallet_types
+        .iter()
+        .map(|wallet_type| wallet_type.parse::<WalletType>())
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(wallet_types) => wallet_types,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let mnemonic = Mnemonic::new(read_line("Mnemonic"));
+    let passphrase = Passphrase::new(read_line("Passphrase (empty for none)"));
+    let db_pool = create_db_pool(&config);
+    let cpu_pool = CpuPool::new(1);
+    let keys_repo = match KeysRepoImpl::new(&*master_key_provider(&config)) {
+        Ok(keys_repo) => keys_repo,
+        Err(e) => {
+            eprintln!("Failed to load master key:\n{}", format_error(&e));
+            process::exit(1);
+        }
+    };
+    let seeds_repo = SeedsRepoImpl::new(keys_repo.key_encryption_provider());
+    let users_repo = UsersRepoImpl;
+    let blockchain_service = create_blockchain_service(&config);
+    let db_executor = DbExecutorImpl::new(db_pool, cpu_pool);
+    let fut = db_executor
+        .execute_transaction(move || -> Result<(), ServiceError> {
+            let system_user = users_repo
+                .find_system_user()?
+                .ok_or(ectx!(try err ServiceErrorContext::NoSystemUser, ServiceErrorKind::Internal))?;
+            let currencies = [Currency::Btc, Currency::Eth, Currency::Stq];
+            let recover_counts: HashMap<Currency, u32> = currencies.iter().map(|currency| (*currency, recover)).collect();
+            check_recover_count(&recover_counts, &wallet_types)?;
+            let seed = store_mnemonic_seed(&seeds_repo, system_user.id, &mnemonic, &passphrase)?;
+            for currency in &currencies {
+                for wallet_type in recovered_wallet_types(*currency, &wallet_types) {
+                    for key in recover_keys(&blockchain_service, &keys_repo, &seeds_repo, &seed, *currency, wallet_type, recover)? {
+                        let path = key.derivation_path.map(|path| path.to_string()).unwrap_or_default();
+                        println!("{} {} {}", key.currency, key.blockchain_address.into_inner(), path);
+                    }
+                }
+            }
+            Ok(())
+        })
+        .map_err(|e| {
+            eprintln!("Failed to import mnemonic, no keys were changed:\n{}", format_error(&e));
+            process::exit(1);
+        });
+    hyper::rt::run(fut);
+}
+
+/// Writes encrypted backup of all users, keys and master seeds to a new file at `path`. Reads backup passphrase from stdin
+pub fn backup(path: &str) {
+    let config = get_config();
+    let passphrase = read_line("Backup passphrase");
+    if passphrase.is_empty() || passphrase != read_line("Repeat backup passphrase") {
+        eprintln!("Backup passphrase is empty or doesn't match");
+        process::exit(1);
+    }
+    let keys_repo = match KeysRepoImpl::new(&*master_key_provider(&config)) {
+        Ok(keys_repo) => keys_repo,
+        Err(e) => {
+            eprintln!("Failed to load master key:\n{}", format_error(&e));
+            process::exit(1);
+        }
+    };
+    // Backup contains private keys, so it's never written over an existing file and is readable only by owner
+    let file = match OpenOptions::new().write(true).create_new(true).mode(0o600).open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Failed to create backup file {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+    let mut backup = match BackupWriter::new(BufWriter::new(file), &passphrase) {
+        Ok(backup) => backup,
+        Err(e) => {
+            let _ = fs::remove_file(path);
+            eprintln!("Failed to write backup:\n{}", format_error(&e));
+            process::exit(1);
+        }
+    };
+    let db_pool = create_db_pool(&config);
+    let cpu_pool = CpuPool::new(1);
+    let seeds_repo = SeedsRepoImpl::new(keys_repo.key_encryption_provider());
+    let users_repo = UsersRepoImpl;
+    let db_executor = DbExecutorImpl::new(db_pool, cpu_pool);
+    let path = path.to_string();
+    let fut = db_executor
+        .execute_transaction_with_isolation(Isolation::RepeatableRead, move || -> Result<BackupReport, ServiceError> {
+            let report = write_backup(&users_repo, &keys_repo, &seeds_repo, &mut backup)?;
+            backup.finish().map_err(ectx!(try convert))?;
+            Ok(report)
+        })
+        .map(|report| {
+            println!(
+                "Backed up {} users, {} keys, {} seeds and {} derivation indexes",
+                report.users, report.keys, report.seeds, report.derivation_indexes
+            )
+        })
+        .map_err(move |e| {
+            // Backup without the last record can't be restored anyway
+            let _ = fs::remove_file(&path);
+            eprintln!("Failed to write backup:\n{}", format_error(&e));
+            process::exit(1);
+        });
+    hyper::rt::run(fut);
+}
+
+/// Restores users, keys and master seeds from encrypted backup at `path`, existing ones are skipped. Reads backup passphrase from stdin
+pub fn restore(path: &str) {
+    let config = get_config();
+    let passphrase = read_line("Backup passphrase");
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Failed to open backup file {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+    let mut backup = match BackupReader::new(BufReader::new(file), &passphrase) {
+        Ok(backup) => backup,
+        Err(e) => {
+            eprintln!("Failed to read backup:\n{}", format_error(&e));
+            process::exit(1);
+        }
+    };
+    let db_pool = create_db_pool(&config);
+    let cpu_pool = CpuPool::new(1);
+    let keys_repo = match KeysRepoImpl::new(&*master_key_provider(&config)) {
+        Ok(keys_repo) => keys_repo,
+        Err(e) => {
+            eprintln!("Failed to load master key:\n{}", format_error(&e));
+            process::exit(1);
+        }
+    };
+    let seeds_repo = SeedsRepoImpl::new(keys_repo.key_encryption_provider());
+    let users_repo = UsersRepoImpl;
+    let blockchain_service = create_blockchain_service(&config);
+    let db_executor = DbExecutorImpl::new(db_pool, cpu_pool);
+    let fut = db_executor
+        .execute_transaction_with_isolation(Isolation::Serializable, move || {
+            restore_backup(&blockchain_service, &users_repo, &keys_repo, &seeds_repo, &mut backup)
+        })
+        .map(|report| {
+            println!(
+                "Restored {} users, {} keys, {} seeds and {} derivation indexes, skipped {} existing records",
+                report.users, report.keys, report.seeds, report.derivation_indexes, report.skipped
+            )
+        })
+        .map_err(|e| {
+            eprintln!("Failed to restore backup, no records were changed:\n{}", format_error(&e));
+            process::exit(1);
+        });
+    hyper::rt::run(fut);
+}
+
+/// Splits hex-encoded secret, e.g. main key, into `count` Shamir shares, any `threshold` of which restore it
+pub fn split_secret(threshold: u8, count: u8) {
+    let mut secret = match decode_hex(&read_line("Secret (hex)")) {
+        Ok(secret) => secret,
+        Err(e) => {
+            eprintln!("Invalid secret: {}", e);
+            return;
+        }
+    };
+    let shares = Share::split(&secret, threshold, count);
+    wipe(&mut secret);
+    match shares {
+        Ok(shares) => {
+            eprintln!("Hand each share to a different officer, shares are not stored and will not be shown again:");
+            for share in shares {
+                println!("{}", share.encode());
+            }
+        }
+        Err(e) => eprintln!("Failed to split secret:\n{}", format_error(&e)),
+    }
+}
+
+/// Restores hex-encoded secret from Shamir shares. Reads shares from stdin, one per line, until there are enough of them
+pub fn combine_secret() {
+    let mut shares: Vec<Share> = Vec::new();
+    loop {
+        let prompt = match shares.first() {
+            Some(first) => format!("Share {} of {}", shares.len() + 1, first.threshold()),
+            None => "Share".to_string(),
+        };
+        match read_line(&prompt).parse::<Share>() {
+            Ok(share) => shares.push(share),
+            Err(e) => {
+                eprintln!("Invalid share: {}", e);
+                return;
+            }
+        }
+        if shares.len() >= shares[0].threshold() as usize {
+            break;
+        }
+    }
+    match Share::combine(&shares) {
+        Ok(mut secret) => {
+            println!("{}", encode_hex(&secret));
+            wipe(&mut secret);
+        }
+        Err(e) => eprintln!("Failed to combine secret:\n{}", format_error(&e)),
+    }
+}
+
+/// Generates key of the system user with address starting with `prefix`, e.g. for a branded hot wallet.
+/// `iterations` and `timeout_secs` override limits from `vanity` config
+pub fn create_vanity_key(currency: &str, prefix: &str, iterations: Option<usize>, timeout_secs: Option<u64>) {
+    let mut config = get_config();
+    let currency = match currency.parse::<Currency>() {
+        Ok(currency) => currency,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    if let Some(iterations) = iterations {
+        config.vanity.iterations = iterations;
+    }
+    if let Some(timeout_secs) = timeout_secs {
+        config.vanity.timeout_secs = timeout_secs;
+    }
+    let db_pool = create_db_pool(&config);
+    let cpu_pool = CpuPool::new(1);
+    let keys_repo = match KeysRepoImpl::new(&*master_key_provider(&config)) {
+        Ok(keys_repo) => keys_repo,
+        Err(e) => {
+            eprintln!("Failed to load master key:\n{}", format_error(&e));
+            process::exit(1);
+        }
+    };
+    let users_repo = UsersRepoImpl;
+    let blockchain_service = Arc::new(create_blockchain_service(&config));
+    let vanity_key_generator = VanityKeyGenerator::new(blockchain_service, &config.vanity);
+    let db_executor = DbExecutorImpl::new(db_pool, cpu_pool);
+    eprintln!(
+        "Searching for {} address with prefix {} in up to {} keys for {} seconds",
+        currency, prefix, config.vanity.iterations, config.vanity.timeout_secs
+    );
+    let fut = vanity_key_generator
+        .generate(currency, prefix.to_string())
+        .and_then(move |(private_key, blockchain_address)| {
+            db_executor.execute_transaction(move || -> Result<_, ServiceError> {
+                let system_user = users_repo
+                    .find_system_user()?
+                    .ok_or(ectx!(try err ServiceErrorContext::NoSystemUser, ServiceErrorKind::Internal))?;
+                let new_key = NewKey {
+                    id: KeyId::default(),
+                    private_key,
+                    blockchain_address,
+                    currency,
+                    owner_id: system_user.id,
+                    derivation_path: None,
+                    label: None,
+                    metadata: KeyMetadata::default(),
+                    // vanity prefixes are base58, so bitcoin vanity keys are P2PKH
+                    wallet_type: WalletType::default_for(currency),
+                };
+                Ok(keys_repo.create(new_key)?)
+            })
+        })
+        .map(|key| println!("{} {} {:?}", key.currency, key.blockchain_address.into_inner(), key.id))
+        .map_err(|e| {
+            eprintln!("Failed to create vanity key:\n{}", format_error(&e));
+            process::exit(1);
+        });
+    hyper::rt::run(fut);
+}
+
+fn read_line(prompt: &str) -> String {
+    eprint!("{}: ", prompt);
+    let _ = io::stderr().flush();
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line).expect("Failed to read from stdin");
+    line.trim().to_string()
+}
+
+fn create_blockchain_service(config: &Config) -> BlockchainServiceImpl {
+    BlockchainServiceImpl::new(
+        config.blockchain.stq_gas_limit.clone(),
+        config.blockchain.eth_gas_limit.clone(),
+        config.blockchain.stq_contract_address.clone(),
+        config.blockchain.stq_transfer_from_method_number.clone(),
+        config.blockchain.stq_approve_method_number.clone(),
+        config.blockchain.ethereum_chain_id.clone(),
+        config.blockchain.btc_network.clone(),
+        config.blockchain.btc_coin_selection,
+    )
+}
+
 fn create_db_pool(config: &Config) -> PgPool {
     let database_url = config.database.url.clone();
     let manager = ConnectionManager::<PgConnection>::new(database_url.clone());
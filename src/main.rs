@@ -15,7 +15,46 @@ fn main() {
         keystore_lib::start_server();
     } else if let Some(matches) = matches.subcommand_matches("create_user") {
         let name = matches.value_of("name").unwrap();
-        keystore_lib::create_user(&name);
+        let can_export_keys = matches.is_present("can_export_keys");
+        let is_admin = matches.is_present("admin");
+        keystore_lib::create_user(&name, can_export_keys, is_admin);
+    } else if let Some(_) = matches.subcommand_matches("migrate_keys") {
+        keystore_lib::migrate_keys();
+    } else if let Some(_) = matches.subcommand_matches("rotate_main_key") {
+        keystore_lib::rotate_main_key();
+    } else if let Some(matches) = matches.subcommand_matches("create_mnemonic") {
+        let words = value_t!(matches, "words", usize).unwrap_or_else(|e| e.exit());
+        keystore_lib::create_mnemonic(words);
+    } else if let Some(matches) = matches.subcommand_matches("import_mnemonic") {
+        let recover = value_t!(matches, "recover", u32).unwrap_or_else(|e| e.exit());
+        let wallet_types: Vec<&str> = matches.values_of("wallet_type").map(|values| values.collect()).unwrap_or_default();
+        keystore_lib::import_mnemonic(recover, &wallet_types);
+    } else if let Some(matches) = matches.subcommand_matches("backup") {
+        let output = matches.value_of("output").unwrap();
+        keystore_lib::backup(&output);
+    } else if let Some(matches) = matches.subcommand_matches("restore") {
+        let input = matches.value_of("input").unwrap();
+        keystore_lib::restore(&input);
+    } else if let Some(matches) = matches.subcommand_matches("split_secret") {
+        let threshold = value_t!(matches, "threshold", u8).unwrap_or_else(|e| e.exit());
+        let shares = value_t!(matches, "shares", u8).unwrap_or_else(|e| e.exit());
+        keystore_lib::split_secret(threshold, shares);
+    } else if let Some(_) = matches.subcommand_matches("combine_secret") {
+        keystore_lib::combine_secret();
+    } else if let Some(matches) = matches.subcommand_matches("create_vanity_key") {
+        let currency = matches.value_of("currency").unwrap();
+        let prefix = matches.value_of("prefix").unwrap();
+        let iterations = if matches.is_present("iterations") {
+            Some(value_t!(matches, "iterations", usize).unwrap_or_else(|e| e.exit()))
+        } else {
+            None
+        };
+        let timeout = if matches.is_present("timeout") {
+            Some(value_t!(matches, "timeout", u64).unwrap_or_else(|e| e.exit()))
+        } else {
+            None
+        };
+        keystore_lib::create_vanity_key(&currency, &prefix, iterations, timeout);
     } else {
         let _ = app.print_help();
         println!("\n")
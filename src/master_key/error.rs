@@ -0,0 +1,61 @@
+use failure::{Backtrace, Context, Fail};
+use std::fmt;
+use std::fmt::Display;
+
+#[derive(Debug)]
+pub struct Error {
+    inner: Context<ErrorKind>,
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Debug, Fail, PartialEq, Eq)]
+pub enum ErrorKind {
+    #[fail(display = "master key error - internal")]
+    Internal,
+    #[fail(display = "master key error - malformed key")]
+    MalformedKey,
+    #[fail(display = "master key error - malformed share")]
+    MalformedShare,
+    #[fail(display = "master key error - invalid shares")]
+    InvalidShares,
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Debug, Fail, PartialEq, Eq)]
+pub enum ErrorContext {
+    #[fail(display = "master key context - main_key is missing in blockchain config")]
+    MissingConfigKey,
+    #[fail(display = "master key context - error reading environment variable")]
+    EnvVar,
+    #[fail(display = "master key context - error reading file")]
+    File,
+    #[fail(display = "master key context - PKCS#11 token not found")]
+    TokenNotFound,
+    #[fail(display = "master key context - key not found on PKCS#11 token")]
+    KeyNotFound,
+    #[fail(display = "master key context - key on PKCS#11 token can't be extracted, it wraps data keys on the token")]
+    KeyNotExtractable,
+    #[fail(display = "master key context - threshold must be at least 2 and not more than number of shares")]
+    InvalidThreshold,
+    #[fail(display = "master key context - not enough shares")]
+    NotEnoughShares,
+    #[fail(display = "master key context - shares are from different splits")]
+    SharesMismatch,
+    #[fail(display = "master key context - share with the same index was already submitted")]
+    DuplicateShare,
+    #[fail(display = "master key context - checksum of combined secret doesn't match")]
+    Checksum,
+    #[fail(display = "master key context - admin endpoint failed")]
+    AdminServer,
+}
+
+#[allow(dead_code)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Fail)]
+pub enum ErrorSource {
+    #[fail(display = "master key source - PKCS#11 module")]
+    Pkcs11,
+    #[fail(display = "master key source - error inside of Hyper library")]
+    Hyper,
+}
+
+derive_error_impls!();
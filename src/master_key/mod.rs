@@ -0,0 +1,167 @@
+//! Master key is the key encryption key, that wraps data keys of private keys.
+//! Providers load it from the source selected in `master_key` config section.
+
+mod error;
+mod pkcs11;
+mod shamir;
+mod shares;
+
+use std::env;
+use std::fs;
+use std::sync::Arc;
+
+pub use self::error::*;
+pub use self::pkcs11::Pkcs11MasterKeyProvider;
+pub use self::shamir::Share;
+pub use self::shares::SharesMasterKeyProvider;
+
+use config::{Config, MasterKey as MasterKeyConfig};
+use models::{check_aes_key, decode_aes_key};
+use repos::{KeyEncryptionProvider, MasterKeyEncryptionProvider};
+use utils::wipe;
+
+pub trait MasterKeyProvider: Send + Sync + 'static {
+    /// Loads 256-bit AES master key
+    fn master_key(&self) -> Result<Vec<u8>, Error>;
+
+    /// Creates provider, that wraps data keys with the master key. By default the master key
+    /// is loaded into memory, providers of non-extractable keys wrap data keys where the key is stored.
+    fn key_encryption_provider(&self) -> Result<Arc<KeyEncryptionProvider>, Error> {
+        let master_key = self.master_key()?;
+        Ok(Arc::new(MasterKeyEncryptionProvider::new(master_key)))
+    }
+}
+
+/// Creates master key provider selected in config
+pub fn master_key_provider(config: &Config) -> Arc<MasterKeyProvider> {
+    match config.master_key.clone() {
+        MasterKeyConfig::Config => Arc::new(ConfigMasterKeyProvider::new(config.blockchain.main_key.clone())),
+        MasterKeyConfig::Env { var } => Arc::new(EnvMasterKeyProvider::new(var)),
+        MasterKeyConfig::File { path } => Arc::new(FileMasterKeyProvider::new(path)),
+        MasterKeyConfig::Pkcs11 {
+            module,
+            token_label,
+            key_label,
+            hash_key_label,
+            pin,
+        } => Arc::new(Pkcs11MasterKeyProvider::new(
+            module,
+            token_label,
+            key_label,
+            hash_key_label,
+            pin.raw().to_string(),
+        )),
+        MasterKeyConfig::Shares { host, port } => Arc::new(SharesMasterKeyProvider::new(host, port)),
+    }
+}
+
+/// Reads hex-encoded master key from `blockchain.main_key` config param
+pub struct ConfigMasterKeyProvider {
+    main_key: Option<String>,
+}
+
+impl ConfigMasterKeyProvider {
+    pub fn new(main_key: Option<String>) -> Self {
+        ConfigMasterKeyProvider { main_key }
+    }
+}
+
+impl MasterKeyProvider for ConfigMasterKeyProvider {
+    fn master_key(&self) -> Result<Vec<u8>, Error> {
+        let main_key = self
+            .main_key
+            .as_ref()
+            .ok_or(ectx!(try err ErrorContext::MissingConfigKey, ErrorKind::Internal))?;
+        decode_master_key(main_key)
+    }
+}
+
+/// Reads hex-encoded master key from environment variable
+pub struct EnvMasterKeyProvider {
+    var: String,
+}
+
+impl EnvMasterKeyProvider {
+    pub fn new(var: String) -> Self {
+        EnvMasterKeyProvider { var }
+    }
+}
+
+impl MasterKeyProvider for EnvMasterKeyProvider {
+    fn master_key(&self) -> Result<Vec<u8>, Error> {
+        let var = self.var.clone();
+        let value = env::var(&self.var).map_err(ectx!(try ErrorContext::EnvVar, ErrorKind::Internal => var))?;
+        decode_master_key(&value)
+    }
+}
+
+/// Reads hex-encoded master key from file, e.g. mounted Kubernetes secret
+pub struct FileMasterKeyProvider {
+    path: String,
+}
+
+impl FileMasterKeyProvider {
+    pub fn new(path: String) -> Self {
+        FileMasterKeyProvider { path }
+    }
+}
+
+impl MasterKeyProvider for FileMasterKeyProvider {
+    fn master_key(&self) -> Result<Vec<u8>, Error> {
+        let path = self.path.clone();
+        let value = fs::read_to_string(&self.path).map_err(ectx!(try ErrorContext::File, ErrorKind::Internal => path))?;
+        decode_master_key(&value)
+    }
+}
+
+fn decode_master_key(value: &str) -> Result<Vec<u8>, Error> {
+    // Secrets mounted from files and env usually end with a newline
+    decode_aes_key(value.trim()).map_err(ectx!(ErrorKind::MalformedKey))
+}
+
+/// Checks that combined secret is a master key, the secret is wiped if it's not
+fn check_master_key(mut key: Vec<u8>) -> Result<Vec<u8>, Error> {
+    let result = check_aes_key(&key).map_err(ectx!(ErrorKind::MalformedKey));
+    if result.is_err() {
+        wipe(&mut key);
+    }
+    result.map(|_| key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Write;
+
+    const MASTER_KEY: &str = "9e41f07d31e44062c688c08aa99e57d8fda2cba546da31f6a075e1a252f48a93";
+
+    #[test]
+    fn test_config_provider() {
+        let provider = ConfigMasterKeyProvider::new(Some(MASTER_KEY.to_string()));
+        assert_eq!(provider.master_key().unwrap().len(), 32);
+        let provider = ConfigMasterKeyProvider::new(None);
+        assert!(provider.master_key().is_err());
+    }
+
+    #[test]
+    fn test_env_provider() {
+        env::set_var("KEYSTORE_TEST_MASTER_KEY", format!("{}\n", MASTER_KEY));
+        let provider = EnvMasterKeyProvider::new("KEYSTORE_TEST_MASTER_KEY".to_string());
+        assert_eq!(provider.master_key().unwrap(), decode_aes_key(MASTER_KEY).unwrap());
+        env::set_var("KEYSTORE_TEST_MASTER_KEY", "9e41f07d");
+        assert_eq!(provider.master_key().unwrap_err().kind(), ErrorKind::MalformedKey);
+        let provider = EnvMasterKeyProvider::new("KEYSTORE_TEST_MISSING_MASTER_KEY".to_string());
+        assert_eq!(provider.master_key().unwrap_err().kind(), ErrorKind::Internal);
+    }
+
+    #[test]
+    fn test_file_provider() {
+        let path = env::temp_dir().join("keystore_test_master_key");
+        File::create(&path).and_then(|mut file| writeln!(file, "{}", MASTER_KEY)).unwrap();
+        let provider = FileMasterKeyProvider::new(path.to_string_lossy().to_string());
+        assert_eq!(provider.master_key().unwrap(), decode_aes_key(MASTER_KEY).unwrap());
+        fs::remove_file(&path).unwrap();
+        assert!(provider.master_key().is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use std::mem;
+use std::ptr;
+use std::sync::{Arc, Mutex};
+
+use pkcs11_crate::types::*;
+use pkcs11_crate::Ctx;
+use rand;
+use rand::RngCore;
+
+use super::error::*;
+use super::MasterKeyProvider;
+use repos::{Error as ReposError, ErrorContext as ReposErrorContext, ErrorKind as ReposErrorKind, KeyEncryptionProvider};
+use utils::{decode_hex, encode_hex, AEAD_NONCE_LEN};
+
+/// Prefix of data keys wrapped on PKCS#11 token, hex-encoded IV and ciphertext with tag follow it
+const PKCS11_V1_PREFIX: &str = "pkcs11v1:";
+const GCM_TAG_BITS: usize = 128;
+
+/// Wraps data keys with AES secret key object on PKCS#11 token.
+///
+/// The key objects are looked up by `CKA_LABEL` on the token with `token_label`. Data keys are
+/// encrypted and decrypted with AES-GCM on the token, so the key doesn't have to be extractable
+/// and never leaves the token. Hashes of private keys are HMAC-SHA256 computed on the token with
+/// a separate generic secret key, so that the AES key is used only with random IVs.
+/// Locally it can be tested with SoftHSM:
+///
+/// ```text
+/// softhsm2-util --init-token --free --label keystore --pin 1234 --so-pin 1234
+/// pkcs11-tool --module /usr/lib/softhsm/libsofthsm2.so --login --pin 1234 \
+///     --token-label keystore --keygen --key-type AES:32 --label master_key --sensitive
+/// pkcs11-tool --module /usr/lib/softhsm/libsofthsm2.so --login --pin 1234 \
+///     --token-label keystore --keygen --key-type GENERIC:32 --label hash_key --sensitive
+/// ```
+///
+/// Keys wrapped with a hex main key are moved to the token with `rotate_main_key` command,
+/// run with this provider in config and the old main key on stdin.
+pub struct Pkcs11MasterKeyProvider {
+    module: String,
+    token_label: String,
+    key_label: String,
+    hash_key_label: String,
+    pin: String,
+}
+
+impl Pkcs11MasterKeyProvider {
+    pub fn new(module: String, token_label: String, key_label: String, hash_key_label: String, pin: String) -> Self {
+        Pkcs11MasterKeyProvider {
+            module,
+            token_label,
+            key_label,
+            hash_key_label,
+            pin,
+        }
+    }
+
+    fn find_slot(&self, ctx: &Ctx) -> Result<CK_SLOT_ID, Error> {
+        let slots = ctx
+            .get_slot_list(true)
+            .map_err(ectx!(try ErrorSource::Pkcs11, ErrorKind::Internal))?;
+        for slot in slots {
+            let token_info = ctx
+                .get_token_info(slot)
+                .map_err(ectx!(try ErrorSource::Pkcs11, ErrorKind::Internal => slot))?;
+            // Token labels are padded with spaces to 32 bytes
+            if String::from_utf8_lossy(&token_info.label).trim_end() == self.token_label {
+                return Ok(slot);
+            }
+        }
+        let token_label = self.token_label.clone();
+        Err(ectx!(err ErrorContext::TokenNotFound, ErrorKind::Internal => token_label))
+    }
+
+    fn find_key(&self, ctx: &Ctx, session: CK_SESSION_HANDLE, key_label: &str) -> Result<CK_OBJECT_HANDLE, Error> {
+        let template = vec![
+            CK_ATTRIBUTE::new(CKA_CLASS).with_ck_ulong(&CKO_SECRET_KEY),
+            CK_ATTRIBUTE::new(CKA_LABEL).with_string(&key_label.to_string()),
+        ];
+        ctx.find_objects_init(session, &template)
+            .map_err(ectx!(try ErrorSource::Pkcs11, ErrorKind::Internal))?;
+        let objects = ctx.find_objects(session, 1);
+        ctx.find_objects_final(session)
+            .map_err(ectx!(try ErrorSource::Pkcs11, ErrorKind::Internal))?;
+        let key_label = key_label.to_string();
+        objects
+            .map_err(ectx!(try ErrorSource::Pkcs11, ErrorKind::Internal))?
+            .into_iter()
+            .next()
+            .ok_or(ectx!(err ErrorContext::KeyNotFound, ErrorKind::Internal => key_label))
+    }
+
+    /// Opens session, that stays logged in while the provider is used
+    fn open_session(&self) -> Result<Pkcs11Session, Error> {
+        let module = self.module.clone();
+        let ctx = Ctx::new_and_initialize(&self.module).map_err(ectx!(try ErrorSource::Pkcs11, ErrorKind::Internal => module))?;
+        let slot = self.find_slot(&ctx)?;
+        let session = ctx
+            .open_session(slot, CKF_SERIAL_SESSION, None, None)
+            .map_err(ectx!(try ErrorSource::Pkcs11, ErrorKind::Internal => slot))?;
+        // Session is closed on drop, also when login or lookup of the keys fails
+        let mut pkcs11_session = Pkcs11Session {
+            ctx,
+            session,
+            key: 0,
+            hash_key: 0,
+        };
+        pkcs11_session
+            .ctx
+            .login(session, CKU_USER, Some(&self.pin))
+            .map_err(ectx!(try ErrorSource::Pkcs11, ErrorKind::Internal))?;
+        pkcs11_session.key = self.find_key(&pkcs11_session.ctx, session, &self.key_label)?;
+        pkcs11_session.hash_key = self.find_key(&pkcs11_session.ctx, session, &self.hash_key_label)?;
+        Ok(pkcs11_session)
+    }
+}
+
+impl MasterKeyProvider for Pkcs11MasterKeyProvider {
+    fn master_key(&self) -> Result<Vec<u8>, Error> {
+        let key_label = self.key_label.clone();
+        Err(ectx!(err ErrorContext::KeyNotExtractable, ErrorKind::Internal => key_label))
+    }
+
+    fn key_encryption_provider(&self) -> Result<Arc<KeyEncryptionProvider>, Error> {
+        let session = self.open_session()?;
+        Ok(Arc::new(Pkcs11KeyEncryptionProvider {
+            session: Mutex::new(session),
+        }))
+    }
+}
+
+struct Pkcs11Session {
+    ctx: Ctx,
+    session: CK_SESSION_HANDLE,
+    key: CK_OBJECT_HANDLE,
+    hash_key: CK_OBJECT_HANDLE,
+}
+
+// Session is used only under the mutex of `Pkcs11KeyEncryptionProvider`
+unsafe impl Send for Pkcs11Session {}
+
+impl Drop for Pkcs11Session {
+    fn drop(&mut self) {
+        let _ = self.ctx.logout(self.session);
+        let _ = self.ctx.close_session(self.session);
+    }
+}
+
+/// Encrypts data keys with AES-GCM and hashes private keys with HMAC-SHA256 on PKCS#11 token.
+/// Operations of one session can't be interleaved, so they are serialized.
+pub struct Pkcs11KeyEncryptionProvider {
+    session: Mutex<Pkcs11Session>,
+}
+
+impl Pkcs11KeyEncryptionProvider {
+    fn encrypt(&self, key: &[u8], iv: &[u8], associated_data: &[u8]) -> Result<Vec<u8>, ReposError> {
+        let mut params = gcm_params(iv, associated_data);
+        let mechanism = gcm_mechanism(&mut params);
+        let session = self.session.lock().unwrap();
+        session
+            .ctx
+            .encrypt_init(session.session, &mechanism, session.key)
+            .map_err(ectx!(try ReposErrorContext::Encryption, ReposErrorKind::Internal))?;
+        session
+            .ctx
+            .encrypt(session.session, key)
+            .map_err(ectx!(ReposErrorContext::Encryption, ReposErrorKind::Internal))
+    }
+
+    fn decrypt(&self, encrypted: &[u8], iv: &[u8], associated_data: &[u8]) -> Result<Vec<u8>, ReposError> {
+        let mut params = gcm_params(iv, associated_data);
+        let mechanism = gcm_mechanism(&mut params);
+        let session = self.session.lock().unwrap();
+        session
+            .ctx
+            .decrypt_init(session.session, &mechanism, session.key)
+            .map_err(ectx!(try ReposErrorContext::Decryption, ReposErrorKind::Internal))?;
+        // Fails with CKR_ENCRYPTED_DATA_INVALID if the tag doesn't match
+        session
+            .ctx
+            .decrypt(session.session, encrypted)
+            .map_err(ectx!(ReposErrorContext::Decryption, ReposErrorKind::Internal))
+    }
+}
+
+impl KeyEncryptionProvider for Pkcs11KeyEncryptionProvider {
+    fn wrap_key(&self, key: &[u8], associated_data: &[u8]) -> Result<String, ReposError> {
+        let mut random = rand::OsRng::new().map_err(ectx!(try ReposErrorContext::Encryption, ReposErrorKind::Internal))?;
+        let mut iv = [0u8; AEAD_NONCE_LEN];
+        random.fill_bytes(&mut iv);
+        let encrypted = self.encrypt(key, &iv, associated_data)?;
+        let mut res = PKCS11_V1_PREFIX.to_string();
+        res.push_str(&encode_hex(&iv));
+        res.push_str(&encode_hex(&encrypted));
+        Ok(res)
+    }
+
+    fn unwrap_key(&self, wrapped_key: &str, associated_data: &[u8]) -> Result<Vec<u8>, ReposError> {
+        // Data keys wrapped with the master key outside of the token can't be unwrapped here
+        if !wrapped_key.starts_with(PKCS11_V1_PREFIX) {
+            return Err(ectx!(err ReposErrorContext::Decryption, ReposErrorKind::Internal));
+        }
+        let wrapped_key = decode_hex(&wrapped_key[PKCS11_V1_PREFIX.len()..])
+            .map_err(ectx!(try ReposErrorContext::Decryption, ReposErrorKind::Internal))?;
+        if wrapped_key.len() < AEAD_NONCE_LEN + GCM_TAG_BITS / 8 {
+            return Err(ectx!(err ReposErrorContext::Decryption, ReposErrorKind::Internal));
+        }
+        let (iv, encrypted) = wrapped_key.split_at(AEAD_NONCE_LEN);
+        self.decrypt(encrypted, iv, associated_data)
+    }
+
+    fn hash_key(&self, key: &[u8]) -> Result<String, ReposError> {
+        let mechanism = CK_MECHANISM {
+            mechanism: CKM_SHA256_HMAC,
+            pParameter: ptr::null_mut(),
+            ulParameterLen: 0,
+        };
+        let session = self.session.lock().unwrap();
+        session
+            .ctx
+            .sign_init(session.session, &mechanism, session.hash_key)
+            .map_err(ectx!(try ReposErrorContext::Encryption, ReposErrorKind::Internal))?;
+        session
+            .ctx
+            .sign(session.session, key)
+            .map(|hmac| encode_hex(&hmac))
+            .map_err(ectx!(ReposErrorContext::Encryption, ReposErrorKind::Internal))
+    }
+}
+
+/// `CK_GCM_PARAMS` of PKCS#11 v2.40
+#[repr(C)]
+#[allow(non_snake_case)]
+struct GcmParams {
+    pIv: CK_BYTE_PTR,
+    ulIvLen: CK_ULONG,
+    ulIvBits: CK_ULONG,
+    pAAD: CK_BYTE_PTR,
+    ulAADLen: CK_ULONG,
+    ulTagBits: CK_ULONG,
+}
+
+/// Parameters point to `iv` and `associated_data`, so they must outlive the operation
+fn gcm_params(iv: &[u8], associated_data: &[u8]) -> GcmParams {
+    GcmParams {
+        pIv: iv.as_ptr() as CK_BYTE_PTR,
+        ulIvLen: iv.len() as CK_ULONG,
+        ulIvBits: (iv.len() * 8) as CK_ULONG,
+        pAAD: associated_data.as_ptr() as CK_BYTE_PTR,
+        ulAADLen: associated_data.len() as CK_ULONG,
+        ulTagBits: GCM_TAG_BITS as CK_ULONG,
+    }
+}
+
+fn gcm_mechanism(params: &mut GcmParams) -> CK_MECHANISM {
+    CK_MECHANISM {
+        mechanism: CKM_AES_GCM,
+        pParameter: params as *mut GcmParams as CK_VOID_PTR,
+        ulParameterLen: mem::size_of::<GcmParams>() as CK_ULONG,
+    }
+}
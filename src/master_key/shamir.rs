@@ -0,0 +1,232 @@
+//! Shamir's secret sharing over GF(256), used to split the master key between officers.
+//!
+//! Every byte of the secret is shared independently with a random polynomial of degree
+//! `threshold - 1`. A short checksum of the secret is shared together with it, so that
+//! combining mismatched or mistyped shares fails instead of producing a wrong key.
+
+use std::str::FromStr;
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use failure::Fail;
+use rand;
+use rand::RngCore;
+
+use super::error::*;
+use utils::{decode_hex, encode_hex, wipe};
+
+const CHECKSUM_LEN: usize = 4;
+
+/// One of the shares of a secret, encoded as `<threshold>-<index>-<hex value>`.
+///
+/// Doesn't implement `Debug`, since a share is secret on its own.
+pub struct Share {
+    threshold: u8,
+    index: u8,
+    value: Vec<u8>,
+}
+
+impl Share {
+    /// Splits secret into `count` shares, any `threshold` of which restore it
+    pub fn split(secret: &[u8], threshold: u8, count: u8) -> Result<Vec<Share>, Error> {
+        if threshold < 2 || threshold > count {
+            return Err(ectx!(err ErrorContext::InvalidThreshold, ErrorKind::InvalidShares => threshold, count));
+        }
+        let mut random = rand::OsRng::new().map_err(ectx!(try ErrorKind::Internal))?;
+        let mut data = secret.to_vec();
+        data.extend_from_slice(&checksum(secret));
+        let mut shares: Vec<Share> = (1..=count)
+            .map(|index| Share {
+                threshold,
+                index,
+                value: Vec::with_capacity(data.len()),
+            })
+            .collect();
+        let mut coefficients = vec![0u8; threshold as usize];
+        for byte in data.iter() {
+            coefficients[0] = *byte;
+            random.fill_bytes(&mut coefficients[1..]);
+            for share in shares.iter_mut() {
+                let y = evaluate(&coefficients, share.index);
+                share.value.push(y);
+            }
+        }
+        wipe(&mut coefficients);
+        wipe(&mut data);
+        Ok(shares)
+    }
+
+    /// Restores secret from at least `threshold` shares of it
+    pub fn combine(shares: &[Share]) -> Result<Vec<u8>, Error> {
+        let first = shares
+            .first()
+            .ok_or(ectx!(try err ErrorContext::NotEnoughShares, ErrorKind::InvalidShares))?;
+        let threshold = first.threshold;
+        let len = first.value.len();
+        for (i, share) in shares.iter().enumerate() {
+            if share.threshold != threshold || share.value.len() != len {
+                return Err(ectx!(err ErrorContext::SharesMismatch, ErrorKind::InvalidShares => share.index));
+            }
+            if shares[..i].iter().any(|other| other.index == share.index) {
+                return Err(ectx!(err ErrorContext::DuplicateShare, ErrorKind::InvalidShares => share.index));
+            }
+        }
+        if shares.len() < threshold as usize || len <= CHECKSUM_LEN {
+            let received = shares.len();
+            return Err(ectx!(err ErrorContext::NotEnoughShares, ErrorKind::InvalidShares => received, threshold));
+        }
+        let shares = &shares[..threshold as usize];
+        let mut data: Vec<u8> = (0..len).map(|i| interpolate(shares, i)).collect();
+        let valid = {
+            let (secret, sum) = data.split_at(len - CHECKSUM_LEN);
+            checksum(secret) == sum
+        };
+        if !valid {
+            wipe(&mut data);
+            return Err(ectx!(err ErrorContext::Checksum, ErrorKind::InvalidShares));
+        }
+        data.truncate(len - CHECKSUM_LEN);
+        Ok(data)
+    }
+
+    pub fn threshold(&self) -> u8 {
+        self.threshold
+    }
+
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    pub fn encode(&self) -> String {
+        format!("{}-{}-{}", self.threshold, self.index, encode_hex(&self.value))
+    }
+}
+
+impl FromStr for Share {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().splitn(3, '-');
+        let threshold = parts.next().and_then(|part| part.parse::<u8>().ok());
+        let index = parts.next().and_then(|part| part.parse::<u8>().ok());
+        let value = parts.next().and_then(|part| decode_hex(part).ok());
+        match (threshold, index, value) {
+            (Some(threshold), Some(index), Some(value)) => {
+                // Constructed before the check, so that the value is wiped on drop
+                let share = Share { threshold, index, value };
+                if share.threshold >= 2 && share.index > 0 {
+                    Ok(share)
+                } else {
+                    Err(ErrorKind::MalformedShare.into())
+                }
+            }
+            (_, _, Some(mut value)) => {
+                wipe(&mut value);
+                Err(ErrorKind::MalformedShare.into())
+            }
+            _ => Err(ErrorKind::MalformedShare.into()),
+        }
+    }
+}
+
+impl Drop for Share {
+    fn drop(&mut self) {
+        wipe(&mut self.value);
+    }
+}
+
+fn checksum(secret: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let mut hasher = Sha256::new();
+    hasher.input(secret);
+    let mut hash = [0u8; 32];
+    hasher.result(&mut hash);
+    let mut result = [0u8; CHECKSUM_LEN];
+    result.copy_from_slice(&hash[..CHECKSUM_LEN]);
+    result
+}
+
+/// Evaluates polynomial with `coefficients` (lowest degree first) at `x` with Horner's method
+fn evaluate(coefficients: &[u8], x: u8) -> u8 {
+    coefficients.iter().rev().fold(0, |acc, coefficient| gf_mul(acc, x) ^ coefficient)
+}
+
+/// Lagrange interpolation of `i`-th bytes of shares at zero
+fn interpolate(shares: &[Share], i: usize) -> u8 {
+    shares.iter().fold(0, |acc, share| {
+        let basis = shares
+            .iter()
+            .filter(|other| other.index != share.index)
+            .fold(1, |basis, other| gf_mul(basis, gf_div(other.index, other.index ^ share.index)));
+        acc ^ gf_mul(share.value[i], basis)
+    })
+}
+
+/// Multiplication in GF(256) with AES reduction polynomial x^8 + x^4 + x^3 + x + 1
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut result = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            result ^= a;
+        }
+        let carry = a & 0x80 != 0;
+        a <<= 1;
+        if carry {
+            a ^= 0x1b;
+        }
+        b >>= 1;
+    }
+    result
+}
+
+/// Division in GF(256), `b` must be non-zero. The inverse is `b^254`, since `b^255 = 1`.
+fn gf_div(a: u8, b: u8) -> u8 {
+    let mut inverse = 1;
+    for _ in 0..254 {
+        inverse = gf_mul(inverse, b);
+    }
+    gf_mul(a, inverse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "9e41f07d31e44062c688c08aa99e57d8fda2cba546da31f6a075e1a252f48a93";
+
+    #[test]
+    fn test_gf() {
+        assert_eq!(gf_mul(0x57, 0x83), 0xc1);
+        for a in 1..=255u8 {
+            assert_eq!(gf_mul(gf_div(1, a), a), 1);
+        }
+    }
+
+    #[test]
+    fn test_split_combine() {
+        let secret = decode_hex(SECRET).unwrap();
+        let shares = Share::split(&secret, 3, 5).unwrap();
+        assert_eq!(shares.len(), 5);
+        for (a, b, c) in &[(0, 1, 2), (4, 2, 0), (1, 3, 4)] {
+            let subset: Vec<Share> = [*a, *b, *c].iter().map(|i| shares[*i].encode().parse().unwrap()).collect();
+            assert_eq!(Share::combine(&subset).unwrap(), secret);
+        }
+        let subset: Vec<Share> = shares.iter().take(2).map(|share| share.encode().parse().unwrap()).collect();
+        assert_eq!(Share::combine(&subset).unwrap_err().kind(), ErrorKind::InvalidShares);
+        assert_eq!(Share::split(&secret, 1, 5).err().map(|e| e.kind()), Some(ErrorKind::InvalidShares));
+        assert_eq!(Share::split(&secret, 6, 5).err().map(|e| e.kind()), Some(ErrorKind::InvalidShares));
+    }
+
+    #[test]
+    fn test_combine_invalid() {
+        let secret = decode_hex(SECRET).unwrap();
+        let shares = Share::split(&secret, 2, 3).unwrap();
+        let other_shares = Share::split(&secret, 2, 3).unwrap();
+        let mixed: Vec<Share> = vec![shares[0].encode().parse().unwrap(), other_shares[1].encode().parse().unwrap()];
+        assert_eq!(Share::combine(&mixed).unwrap_err().kind(), ErrorKind::InvalidShares);
+        let duplicate: Vec<Share> = vec![shares[0].encode().parse().unwrap(), shares[0].encode().parse().unwrap()];
+        assert_eq!(Share::combine(&duplicate).unwrap_err().kind(), ErrorKind::InvalidShares);
+        assert_eq!("2-0-abcd".parse::<Share>().err().map(|e| e.kind()), Some(ErrorKind::MalformedShare));
+        assert_eq!("2-1-abc".parse::<Share>().err().map(|e| e.kind()), Some(ErrorKind::MalformedShare));
+        assert_eq!("abcd".parse::<Share>().err().map(|e| e.kind()), Some(ErrorKind::MalformedShare));
+    }
+}
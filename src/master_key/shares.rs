@@ -0,0 +1,214 @@
+use std::net::SocketAddr;
+use std::str;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use failure::Fail;
+use futures::future;
+use futures::prelude::*;
+use futures::sync::oneshot;
+use hyper;
+use hyper::service::service_fn;
+use hyper::{Body, Method, Request, Response, Server};
+use serde_json::{self, Value as JsonValue};
+
+use super::check_master_key;
+use super::error::*;
+use super::shamir::Share;
+use super::MasterKeyProvider;
+use utils::{log_warn, read_body, wipe};
+
+/// Path of the admin endpoint, that accepts shares as plain text `POST` body
+const SHARES_PATH: &str = "/v1/admin/shares";
+
+/// Combines master key from Shamir shares, submitted by officers to local admin endpoint.
+///
+/// The endpoint is served on its own event loop until enough shares are received,
+/// so the server doesn't start serving API before it can decrypt keys. Shares are
+/// produced with `split_secret` command and can be submitted e.g. with:
+///
+/// ```text
+/// curl -X POST --data-binary @share.txt http://127.0.0.1:8001/v1/admin/shares
+/// ```
+pub struct SharesMasterKeyProvider {
+    host: String,
+    port: String,
+}
+
+impl SharesMasterKeyProvider {
+    pub fn new(host: String, port: String) -> Self {
+        SharesMasterKeyProvider { host, port }
+    }
+}
+
+impl MasterKeyProvider for SharesMasterKeyProvider {
+    fn master_key(&self) -> Result<Vec<u8>, Error> {
+        let address = format!("{}:{}", self.host, self.port)
+            .parse::<SocketAddr>()
+            .map_err(ectx!(try ErrorContext::AdminServer, ErrorKind::Internal => self.host, self.port))?;
+        let (key_tx, key_rx) = oneshot::channel();
+        thread::spawn(move || serve_shares(address, key_tx));
+        key_rx.wait().map_err(ectx!(try ErrorContext::AdminServer, ErrorKind::Internal))?
+    }
+}
+
+/// Collects shares until there are enough of them to combine the secret
+#[derive(Default)]
+struct SharesCollector {
+    shares: Vec<Share>,
+}
+
+impl SharesCollector {
+    /// Adds share, returns combined secret once `threshold` shares are collected
+    fn add(&mut self, share: Share) -> Result<Option<Vec<u8>>, Error> {
+        if let Some(first) = self.shares.first() {
+            if first.threshold() != share.threshold() {
+                return Err(ectx!(err ErrorContext::SharesMismatch, ErrorKind::InvalidShares => share.index()));
+            }
+        }
+        if self.shares.iter().any(|other| other.index() == share.index()) {
+            return Err(ectx!(err ErrorContext::DuplicateShare, ErrorKind::InvalidShares => share.index()));
+        }
+        let threshold = share.threshold() as usize;
+        self.shares.push(share);
+        if self.shares.len() < threshold {
+            return Ok(None);
+        }
+        let result = Share::combine(&self.shares);
+        if result.is_ok() {
+            self.shares.clear();
+        } else {
+            // Only the share that completed the set is dropped, so that the others don't have to be submitted again
+            self.shares.pop();
+        }
+        result.map(Some)
+    }
+
+    /// Number of collected shares and number of shares required
+    fn progress(&self) -> (usize, u8) {
+        (self.shares.len(), self.shares.first().map(|share| share.threshold()).unwrap_or(0))
+    }
+}
+
+struct SharesState {
+    collector: SharesCollector,
+    key_tx: Option<oneshot::Sender<Result<Vec<u8>, Error>>>,
+    shutdown_tx: Option<oneshot::Sender<()>>,
+}
+
+impl SharesState {
+    fn finish(&mut self, result: Result<Vec<u8>, Error>) {
+        if let Some(key_tx) = self.key_tx.take() {
+            let _ = key_tx.send(result);
+        }
+        if let Some(shutdown_tx) = self.shutdown_tx.take() {
+            let _ = shutdown_tx.send(());
+        }
+    }
+}
+
+fn serve_shares(address: SocketAddr, key_tx: oneshot::Sender<Result<Vec<u8>, Error>>) {
+    let (shutdown_tx, shutdown_rx) = oneshot::channel();
+    let state = Arc::new(Mutex::new(SharesState {
+        collector: SharesCollector::default(),
+        key_tx: Some(key_tx),
+        shutdown_tx: Some(shutdown_tx),
+    }));
+    hyper::rt::run(future::lazy(move || {
+        let builder = match Server::try_bind(&address) {
+            Ok(builder) => builder,
+            Err(e) => {
+                let e = ectx!(err e, ErrorSource::Hyper, ErrorKind::Internal => address);
+                state.lock().unwrap().finish(Err(e));
+                return future::Either::A(future::ok(()));
+            }
+        };
+        let service_state = state.clone();
+        let new_service = move || {
+            let state = service_state.clone();
+            service_fn(move |req| handle_share(req, state.clone()))
+        };
+        info!("Waiting for master key shares on http://{}{}", address, SHARES_PATH);
+        let server = builder.serve(new_service).with_graceful_shutdown(shutdown_rx).map_err(move |e| {
+            let e = ectx!(err e, ErrorSource::Hyper, ErrorKind::Internal => address);
+            state.lock().unwrap().finish(Err(e));
+        });
+        future::Either::B(server)
+    }));
+}
+
+fn handle_share(req: Request<Body>, state: Arc<Mutex<SharesState>>) -> Box<Future<Item = Response<Body>, Error = hyper::Error> + Send> {
+    if req.method() != &Method::POST || req.uri().path() != SHARES_PATH {
+        return Box::new(future::ok(response(404, serde_json::json!({"description": "Not found"}))));
+    }
+    Box::new(read_body(req.into_body()).map(move |mut body| {
+        let share = str::from_utf8(&body)
+            .map_err(|_| Error::from(ErrorKind::MalformedShare))
+            .and_then(|body| body.parse::<Share>());
+        wipe(&mut body);
+        let mut state = state.lock().unwrap();
+        let result = share.and_then(|share| state.collector.add(share));
+        match result {
+            Ok(Some(key)) => match check_master_key(key) {
+                Ok(key) => {
+                    info!("Master key is combined from shares");
+                    state.finish(Ok(key));
+                    response(200, serde_json::json!({"description": "Master key is combined"}))
+                }
+                Err(e) => {
+                    log_warn(&e);
+                    response(422, serde_json::json!({"description": "Combined secret is not a master key"}))
+                }
+            },
+            Ok(None) => {
+                let (received, threshold) = state.collector.progress();
+                info!("Received master key share {} of {}", received, threshold);
+                response(202, serde_json::json!({"received": received, "threshold": threshold}))
+            }
+            Err(e) => {
+                log_warn(&e);
+                match e.kind() {
+                    ErrorKind::MalformedShare => response(400, serde_json::json!({"description": "Malformed share"})),
+                    _ => response(422, serde_json::json!({"description": e.to_string()})),
+                }
+            }
+        }
+    }))
+}
+
+fn response(status: u16, body: JsonValue) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::decode_hex;
+
+    const MASTER_KEY: &str = "9e41f07d31e44062c688c08aa99e57d8fda2cba546da31f6a075e1a252f48a93";
+
+    #[test]
+    fn test_collector() {
+        let master_key = decode_hex(MASTER_KEY).unwrap();
+        let shares = Share::split(&master_key, 2, 3).unwrap();
+        let other_shares = Share::split(&master_key, 3, 3).unwrap();
+        let mut collector = SharesCollector::default();
+        assert_eq!(collector.add(shares[2].encode().parse().unwrap()).unwrap(), None);
+        assert_eq!(collector.progress(), (1, 2));
+        let mismatch = collector.add(other_shares[0].encode().parse().unwrap()).unwrap_err();
+        assert_eq!(mismatch.kind(), ErrorKind::InvalidShares);
+        let duplicate = collector.add(shares[2].encode().parse().unwrap()).unwrap_err();
+        assert_eq!(duplicate.kind(), ErrorKind::InvalidShares);
+        // share of another secret fails checksum and is dropped alone
+        let other_secret = Share::split(&decode_hex(&MASTER_KEY.replace("9e", "9f")).unwrap(), 2, 3).unwrap();
+        let checksum = collector.add(other_secret[0].encode().parse().unwrap()).unwrap_err();
+        assert_eq!(checksum.kind(), ErrorKind::InvalidShares);
+        assert_eq!(collector.progress(), (1, 2));
+        assert_eq!(collector.add(shares[0].encode().parse().unwrap()).unwrap(), Some(master_key));
+        assert_eq!(collector.progress(), (0, 0));
+    }
+}
@@ -0,0 +1,17 @@
+/// Strategy that picks the UTXOs spent by a bitcoin transaction
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum CoinSelection {
+    /// Searches for inputs that pay the value and fees without change, falls back to `LargestFirst`
+    BranchAndBound,
+    /// Spends the largest UTXOs first, so transactions have as few inputs as possible
+    LargestFirst,
+    /// Spends the smallest UTXOs first, consolidating them while fees are low
+    SmallestFirst,
+}
+
+impl Default for CoinSelection {
+    fn default() -> Self {
+        CoinSelection::LargestFirst
+    }
+}
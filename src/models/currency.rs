@@ -2,10 +2,12 @@ use diesel::deserialize::{self, FromSql};
 use diesel::pg::Pg;
 use diesel::serialize::{self, IsNull, Output, ToSql};
 use diesel::sql_types::VarChar;
+use failure::Error as FailureError;
 use std::fmt;
 use std::io::Write;
+use std::str::FromStr;
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, FromSqlRow, AsExpression, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, FromSqlRow, AsExpression, Clone, Copy)]
 #[sql_type = "VarChar"]
 #[serde(rename_all = "lowercase")]
 pub enum Currency {
@@ -24,6 +26,18 @@ impl fmt::Display for Currency {
     }
 }
 
+impl FromStr for Currency {
+    type Err = FailureError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "eth" => Ok(Currency::Eth),
+            "stq" => Ok(Currency::Stq),
+            "btc" => Ok(Currency::Btc),
+            _ => Err(format_err!("Unknown currency: {}", s)),
+        }
+    }
+}
+
 impl FromSql<VarChar, Pg> for Currency {
     fn from_sql(data: Option<&[u8]>) -> deserialize::Result<Self> {
         match data {
@@ -1,16 +1,34 @@
-use std::time::SystemTime;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use diesel::sql_types::{Uuid as SqlUuid, VarChar};
+use base64;
+use diesel::sql_types::{Jsonb, Uuid as SqlUuid, VarChar};
+use ethkey::crypto::ecies;
+use ethkey::{public_is_valid, Public};
+use failure::Error as FailureError;
 use rand::RngCore;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value as JsonValue};
 use std::fmt::{self, Debug, Display};
 use uuid::Uuid;
 
 use super::currency::Currency;
+use super::key_status::KeyStatus;
+use super::seed::DerivationPath;
 use super::user::UserId;
+use super::wallet_type::WalletType;
 use schema::keys;
-use utils::{decode_hex, decrypt as aes_dec, encode_hex, encrypt as aes_enc};
+use utils::{decode_hex, decrypt as aes_dec, decrypt_aead, encode_hex, encrypt_aead, wipe, AEAD_NONCE_LEN};
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, FromSqlRow, AsExpression, Clone)]
+/// Version prefix of key material encrypted with AES-256-GCM.
+/// Key material without version prefix is encrypted with AES-256-CBC (legacy format).
+const AEAD_V1_PREFIX: &str = "v1:";
+/// Length of legacy AES-256-CBC iv
+const CBC_IV_LEN: usize = 16;
+/// Length of AES keys used for key material encryption
+const AES_KEY_LEN: usize = 32;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, FromSqlRow, AsExpression, Clone)]
 #[sql_type = "SqlUuid"]
 pub struct KeyId(Uuid);
 derive_newtype_sql!(key_id, SqlUuid, KeyId, KeyId);
@@ -27,10 +45,31 @@ impl Debug for KeyId {
     }
 }
 
-/// Hex encoded private key
-#[derive(Clone, PartialEq, Eq)]
+impl FromStr for KeyId {
+    type Err = FailureError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let uuid = s.parse().map_err(|_| format_err!("Failed to parse key_id: {}", s))?;
+        Ok(KeyId(uuid))
+    }
+}
+
+/// Hex encoded private key. Memory is wiped on drop. The key can't be cloned
+/// and doesn't implement `Debug`, so it can't end up in logs or error contexts.
 pub struct PrivateKey(String);
-mask_logs!(PrivateKey);
+
+/// Private key as supplied for import: hex, or WIF for bitcoin
+#[derive(Deserialize, Clone, PartialEq, Eq)]
+pub struct RawPrivateKey(String);
+mask_logs!(RawPrivateKey);
+
+/// Hex encoded uncompressed secp256k1 public key of export recipient,
+/// with or without `04` prefix
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct RecipientPublicKey(String);
+
+/// Hex encoded private key, encrypted with ECIES to recipient public key
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct ExportedPrivateKey(String);
 
 /// Hex encoded encrypted private key
 #[derive(FromSqlRow, AsExpression, Clone, PartialEq, Eq)]
@@ -39,22 +78,125 @@ pub struct EncryptedPrivateKey(String);
 derive_newtype_sql!(encrypted_private_key, VarChar, EncryptedPrivateKey, EncryptedPrivateKey);
 mask_logs!(EncryptedPrivateKey);
 
+/// Random key used to encrypt exactly one private key (data encryption key)
+pub struct DataKey(Vec<u8>);
+mask_logs!(DataKey);
+
+/// Hex encoded data key, encrypted with key encryption key
+#[derive(FromSqlRow, AsExpression, Clone, PartialEq, Eq)]
+#[sql_type = "VarChar"]
+pub struct EncryptedDataKey(String);
+derive_newtype_sql!(encrypted_data_key, VarChar, EncryptedDataKey, EncryptedDataKey);
+mask_logs!(EncryptedDataKey);
+
+/// Hex encoded HMAC of private key under a key derived from key encryption key.
+/// Identifies the same private key without decrypting stored keys.
+#[derive(Debug, FromSqlRow, AsExpression, Clone, PartialEq, Eq)]
+#[sql_type = "VarChar"]
+pub struct PrivateKeyHash(String);
+derive_newtype_sql!(private_key_hash, VarChar, PrivateKeyHash, PrivateKeyHash);
+
+impl RawPrivateKey {
+    pub fn new(data: String) -> Self {
+        RawPrivateKey(data)
+    }
+
+    pub fn raw(&self) -> &str {
+        &self.0
+    }
+}
+
+impl RecipientPublicKey {
+    pub fn new(data: String) -> Self {
+        RecipientPublicKey(data)
+    }
+
+    pub fn raw(&self) -> &str {
+        &self.0
+    }
+
+    /// Parses and validates public key as a point on secp256k1 curve
+    pub fn to_public(&self) -> Result<Public, FailureError> {
+        let hex = self.0.trim_start_matches("0x");
+        let bytes = decode_hex(hex)?;
+        let bytes = match bytes.len() {
+            64 => &bytes[..],
+            65 if bytes[0] == 4 => &bytes[1..],
+            len => return Err(format_err!("Invalid public key length: {}", len)),
+        };
+        let public = Public::from_slice(bytes);
+        if !public_is_valid(&public) {
+            return Err(format_err!("Public key is not on secp256k1 curve"));
+        }
+        Ok(public)
+    }
+}
+
+impl ExportedPrivateKey {
+    pub fn new(data: String) -> Self {
+        ExportedPrivateKey(data)
+    }
+
+    pub fn raw(&self) -> &str {
+        &self.0
+    }
+}
+
 impl PrivateKey {
     pub fn new(data: String) -> Self {
         PrivateKey(data)
     }
 
-    pub fn into_inner(self) -> String {
-        self.0
+    /// Hex encodes key bytes without intermediate copies of the key
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        const HEX: &[u8; 16] = b"0123456789abcdef";
+        let mut hex = String::with_capacity(bytes.len() * 2);
+        for byte in bytes {
+            hex.push(HEX[(byte >> 4) as usize] as char);
+            hex.push(HEX[(byte & 0x0f) as usize] as char);
+        }
+        PrivateKey(hex)
     }
 
-    pub fn from_encrypted(encrypted_pk: EncryptedPrivateKey, aes_key: &str) -> Self {
-        let key = decode_hex(aes_key);
-        let encrypted = encrypted_pk.into_inner();
-        let iv = decode_hex(&encrypted[0..32]);
-        let encrypted = decode_hex(&encrypted[32..]);
-        let decrypted = aes_dec(&encrypted, &key, &iv).unwrap();
-        PrivateKey(encode_hex(&decrypted))
+    pub fn raw(&self) -> &str {
+        &self.0
+    }
+
+    /// Calls `f` with decoded key bytes, that are wiped right after the call
+    pub fn with_bytes<F, T>(&self, f: F) -> Result<T, FailureError>
+    where
+        F: FnOnce(&[u8]) -> T,
+    {
+        let mut bytes = decode_hex(&self.0).map_err(|_| format_err!("Malformed private key"))?;
+        let res = f(&bytes);
+        wipe(&mut bytes);
+        Ok(res)
+    }
+
+    /// Encrypts private key with ECIES to recipient public key, so that only
+    /// the holder of the corresponding secret can decrypt it.
+    pub fn export(&self, recipient: &RecipientPublicKey) -> Result<ExportedPrivateKey, FailureError> {
+        let public = recipient.to_public()?;
+        let encrypted = self
+            .with_bytes(|plain| ecies::encrypt(&public, &[], plain))?
+            .map_err(|e| format_err!("Ecies encryption failed: {}", e))?;
+        Ok(ExportedPrivateKey(encode_hex(&encrypted)))
+    }
+
+    /// Decrypts private key. `associated_data` must be the same as used for encryption,
+    /// otherwise decryption fails.
+    pub fn from_encrypted(encrypted_pk: &EncryptedPrivateKey, key: &[u8], associated_data: &[u8]) -> Result<Self, FailureError> {
+        let mut decrypted = decrypt_key_material(&encrypted_pk.0, key, associated_data)?;
+        let private_key = PrivateKey::from_bytes(&decrypted);
+        wipe(&mut decrypted);
+        Ok(private_key)
+    }
+}
+
+impl Drop for PrivateKey {
+    fn drop(&mut self) {
+        // Zeros are valid utf-8, so the string stays valid
+        unsafe { wipe(self.0.as_mut_vec()) }
     }
 }
 
@@ -67,19 +209,122 @@ impl EncryptedPrivateKey {
         self.0
     }
 
-    pub fn from_private_key(pk: PrivateKey, aes_key: &str) -> Self {
-        let key = decode_hex(aes_key);
-        let mut random = rand::OsRng::new().unwrap();
-        let mut iv = [0u8; 16];
-        random.fill_bytes(&mut iv);
-        let decrypted = decode_hex(&pk.into_inner());
-        let encrypted = aes_enc(&decrypted, &key, &iv).unwrap();
-        let mut res = encode_hex(&iv);
-        res.push_str(&encode_hex(&encrypted));
-        EncryptedPrivateKey(res)
+    pub fn raw(&self) -> &str {
+        &self.0
+    }
+
+    /// Encrypts private key with AES-256-GCM. `associated_data` is authenticated,
+    /// but not encrypted, so the same data is required for decryption.
+    pub fn from_private_key(pk: &PrivateKey, key: &[u8], associated_data: &[u8]) -> Result<Self, FailureError> {
+        pk.with_bytes(|decrypted| encrypt_key_material(decrypted, key, associated_data))?
+            .map(EncryptedPrivateKey)
+    }
+
+    /// Returns true if private key is encrypted with AES-256-CBC and needs migration
+    pub fn is_legacy(&self) -> bool {
+        !self.0.starts_with(AEAD_V1_PREFIX)
+    }
+}
+
+impl DataKey {
+    pub fn new(data: Vec<u8>) -> Self {
+        DataKey(data)
+    }
+
+    /// Generates new random data key
+    pub fn generate() -> Result<Self, FailureError> {
+        let mut random = rand::OsRng::new()?;
+        let mut data = vec![0u8; AES_KEY_LEN];
+        random.fill_bytes(&mut data);
+        Ok(DataKey(data))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Drop for DataKey {
+    fn drop(&mut self) {
+        wipe(&mut self.0);
+    }
+}
+
+impl EncryptedDataKey {
+    pub fn new(data: String) -> Self {
+        EncryptedDataKey(data)
+    }
+
+    pub fn raw(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PrivateKeyHash {
+    pub fn new(data: String) -> Self {
+        PrivateKeyHash(data)
+    }
+
+    pub fn raw(&self) -> &str {
+        &self.0
     }
 }
 
+/// Encrypts key material with AES-256-GCM and random nonce.
+/// Result is `v1:` prefix followed by hex encoded nonce, ciphertext and tag.
+pub fn encrypt_key_material(data: &[u8], key: &[u8], associated_data: &[u8]) -> Result<String, FailureError> {
+    check_aes_key(key)?;
+    let mut random = rand::OsRng::new()?;
+    let mut nonce = [0u8; AEAD_NONCE_LEN];
+    random.fill_bytes(&mut nonce);
+    let encrypted = encrypt_aead(data, key, &nonce, associated_data);
+    let mut res = AEAD_V1_PREFIX.to_string();
+    res.push_str(&encode_hex(&nonce));
+    res.push_str(&encode_hex(&encrypted));
+    Ok(res)
+}
+
+/// Decrypts key material encrypted with `encrypt_key_material` or with legacy AES-256-CBC.
+/// `associated_data` is ignored for legacy format.
+pub fn decrypt_key_material(encrypted: &str, key: &[u8], associated_data: &[u8]) -> Result<Vec<u8>, FailureError> {
+    check_aes_key(key)?;
+    if encrypted.starts_with(AEAD_V1_PREFIX) {
+        let encrypted = decode_hex(&encrypted[AEAD_V1_PREFIX.len()..])?;
+        if encrypted.len() < AEAD_NONCE_LEN {
+            return Err(format_err!("Encrypted key material is too short"));
+        }
+        let (nonce, encrypted) = encrypted.split_at(AEAD_NONCE_LEN);
+        decrypt_aead(encrypted, key, nonce, associated_data).ok_or(format_err!("Failed to authenticate encrypted key material"))
+    } else {
+        let encrypted = decode_hex(encrypted)?;
+        if encrypted.len() < CBC_IV_LEN {
+            return Err(format_err!("Encrypted key material is too short"));
+        }
+        let (iv, encrypted) = encrypted.split_at(CBC_IV_LEN);
+        aes_dec(encrypted, key, iv).map_err(|e| format_err!("Failed to decrypt key material: {:?}", e))
+    }
+}
+
+/// Decodes hex encoded main key and checks its length
+pub fn decode_aes_key(aes_key: &str) -> Result<Vec<u8>, FailureError> {
+    let key = decode_hex(aes_key)?;
+    check_aes_key(&key)?;
+    Ok(key)
+}
+
+pub fn check_aes_key(key: &[u8]) -> Result<(), FailureError> {
+    if key.len() != AES_KEY_LEN {
+        return Err(format_err!("Encryption key must be {} bytes long, got {}", AES_KEY_LEN, key.len()));
+    }
+    Ok(())
+}
+
+/// Data bound to encrypted private key and data key. Moving encrypted keys to another row
+/// or changing the address of a key makes decryption fail.
+pub fn key_associated_data(id: &KeyId, currency: Currency, blockchain_address: &BlockchainAddress) -> Vec<u8> {
+    format!("{}:{}:{}", id.0, currency, blockchain_address.0).into_bytes()
+}
+
 /// Hex encoded blockchain address
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, FromSqlRow, AsExpression, Clone)]
 #[sql_type = "VarChar"]
@@ -96,42 +341,176 @@ impl BlockchainAddress {
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct Key {
-    pub id: KeyId,
-    pub private_key: PrivateKey,
-    pub blockchain_address: BlockchainAddress,
-    pub currency: Currency,
-    pub owner_id: UserId,
+impl FromStr for BlockchainAddress {
+    type Err = FailureError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(BlockchainAddress(s.to_string()))
+    }
+}
+
+/// Free-form label of a key, e.g. `hot`, `cold` or `fees`
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, FromSqlRow, AsExpression, Clone)]
+#[sql_type = "VarChar"]
+pub struct KeyLabel(String);
+derive_newtype_sql!(key_label, VarChar, KeyLabel, KeyLabel);
+
+impl KeyLabel {
+    pub fn new(data: String) -> Self {
+        KeyLabel(data)
+    }
+
+    pub fn raw(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Arbitrary JSON attached to a key, e.g. `{"merchant": "..."}`. Only objects are accepted from users,
+/// so that metadata of keys can be matched by fields, see `KeysFilter`.
+#[derive(Debug, Serialize, Deserialize, PartialEq, FromSqlRow, AsExpression, Clone)]
+#[sql_type = "Jsonb"]
+pub struct KeyMetadata(JsonValue);
+derive_newtype_sql!(key_metadata, Jsonb, KeyMetadata, KeyMetadata);
+
+impl KeyMetadata {
+    pub fn new(data: JsonValue) -> Self {
+        KeyMetadata(data)
+    }
+
+    pub fn is_object(&self) -> bool {
+        self.0.is_object()
+    }
+
+    /// Same as JSONB containment `@>`: all fields of `other` are present in this metadata with the same values.
+    /// Nested objects are matched recursively, non-object values must be equal.
+    pub fn contains(&self, other: &KeyMetadata) -> bool {
+        json_contains(&self.0, &other.0)
+    }
+}
+
+impl Default for KeyMetadata {
+    fn default() -> Self {
+        KeyMetadata(JsonValue::Object(Map::new()))
+    }
+}
+
+fn json_contains(value: &JsonValue, other: &JsonValue) -> bool {
+    match (value, other) {
+        (JsonValue::Object(value), JsonValue::Object(other)) => other
+            .iter()
+            .all(|(field, other_value)| value.get(field).map(|value| json_contains(value, other_value)).unwrap_or(false)),
+        _ => value == other,
+    }
+}
+
+/// Filter of listed keys, `None` fields match any key
+#[derive(Debug, Clone, Default)]
+pub struct KeysFilter {
+    pub currency: Option<Currency>,
+    pub status: Option<KeyStatus>,
+    pub label: Option<KeyLabel>,
+    /// Matches keys, which metadata contains all fields of this one
+    pub metadata: Option<KeyMetadata>,
+}
+
+/// Position in keys listing. Keys are listed in `(created_at, id)` order and the next page
+/// starts right after the cursor, so pages stay consistent while keys are added.
+/// Sent to clients as an opaque url-safe base64 string.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeysCursor {
     pub created_at: SystemTime,
-    pub updated_at: SystemTime,
+    pub id: KeyId,
 }
 
-impl Key {
-    pub fn from_encrypted(encrypted_key: EncryptedKey, aes_key: &str) -> Self {
-        Key {
-            id: encrypted_key.id,
-            private_key: PrivateKey::from_encrypted(encrypted_key.private_key, &aes_key),
-            blockchain_address: encrypted_key.blockchain_address,
-            currency: encrypted_key.currency,
-            owner_id: encrypted_key.owner_id,
-            created_at: encrypted_key.created_at,
-            updated_at: encrypted_key.updated_at,
+impl KeysCursor {
+    /// Cursor pointing at the key. Time is truncated to microseconds, that postgres timestamps are stored with.
+    pub fn new(key: &Key) -> Self {
+        KeysCursor {
+            created_at: from_micros(to_micros(key.created_at)),
+            id: key.id.clone(),
         }
     }
+
+    /// Whether the key is listed after the cursor
+    pub fn precedes(&self, key: &Key) -> bool {
+        let cursor = KeysCursor::new(key);
+        (cursor.created_at, &cursor.id) > (self.created_at, &self.id)
+    }
+}
+
+fn to_micros(time: SystemTime) -> u64 {
+    let duration = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    duration.as_secs() * 1_000_000 + u64::from(duration.subsec_micros())
+}
+
+fn from_micros(micros: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_micros(micros)
+}
+
+impl Display for KeysCursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let raw = format!("{}:{}", to_micros(self.created_at), self.id.0);
+        f.write_str(&base64::encode_config(&raw, base64::URL_SAFE_NO_PAD))
+    }
 }
 
+impl FromStr for KeysCursor {
+    type Err = FailureError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format_err!("Failed to parse keys cursor: {}", s);
+        let raw = base64::decode_config(s, base64::URL_SAFE_NO_PAD).map_err(|_| error())?;
+        let raw = String::from_utf8(raw).map_err(|_| error())?;
+        let mut parts = raw.splitn(2, ':');
+        let micros = parts.next().and_then(|micros| micros.parse().ok()).ok_or_else(error)?;
+        let id = parts.next().and_then(|id| id.parse().ok()).ok_or_else(error)?;
+        Ok(KeysCursor {
+            created_at: from_micros(micros),
+            id,
+        })
+    }
+}
+
+impl Serialize for KeysCursor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for KeysCursor {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        let s: String = Deserialize::deserialize(de)?;
+        s.parse().map_err(::serde::de::Error::custom)
+    }
+}
+
+/// Page of listed keys
 #[derive(Debug, Clone)]
-pub struct NewKey {
-    pub id: KeyId,
-    pub private_key: PrivateKey,
-    pub blockchain_address: BlockchainAddress,
-    pub currency: Currency,
-    pub owner_id: UserId,
+pub struct KeysPage {
+    pub keys: Vec<Key>,
+    /// Number of keys matching the filter on all pages
+    pub total: i64,
+    /// `None` on the last page
+    pub next_cursor: Option<KeysCursor>,
 }
 
+/// Changes of a key, `None` fields are left as is
+#[derive(Debug, Clone, Default, AsChangeset)]
+#[table_name = "keys"]
+pub struct KeyUpdate {
+    pub status: Option<KeyStatus>,
+    pub label: Option<KeyLabel>,
+    pub metadata: Option<KeyMetadata>,
+}
+
+impl KeyUpdate {
+    pub fn is_empty(&self) -> bool {
+        self.status.is_none() && self.label.is_none() && self.metadata.is_none()
+    }
+}
+
+/// Stored key. Private key stays encrypted until it's needed for signing,
+/// see `KeysRepo::decrypt_private_key`.
 #[derive(Debug, Queryable, Clone)]
-pub struct EncryptedKey {
+pub struct Key {
     pub id: KeyId,
     pub private_key: EncryptedPrivateKey,
     pub blockchain_address: BlockchainAddress,
@@ -139,6 +518,32 @@ pub struct EncryptedKey {
     pub owner_id: UserId,
     pub created_at: SystemTime,
     pub updated_at: SystemTime,
+    /// `None` for keys created before envelope encryption, which are encrypted with key encryption key directly
+    pub encrypted_data_key: Option<EncryptedDataKey>,
+    /// BIP32 path of the key derived from master seed, `None` for random keys
+    pub derivation_path: Option<DerivationPath>,
+    /// `None` for keys created before private keys were hashed, until `migrate_keys` is run
+    pub private_key_hash: Option<PrivateKeyHash>,
+    pub status: KeyStatus,
+    pub label: Option<KeyLabel>,
+    pub metadata: KeyMetadata,
+    /// Time of the last signed transaction, `None` if the key never signed
+    pub last_used_at: Option<SystemTime>,
+    pub signatures_count: i64,
+    /// `None` for keys of account based currencies
+    pub wallet_type: Option<WalletType>,
+}
+
+pub struct NewKey {
+    pub id: KeyId,
+    pub private_key: PrivateKey,
+    pub blockchain_address: BlockchainAddress,
+    pub currency: Currency,
+    pub owner_id: UserId,
+    pub derivation_path: Option<DerivationPath>,
+    pub label: Option<KeyLabel>,
+    pub metadata: KeyMetadata,
+    pub wallet_type: Option<WalletType>,
 }
 
 #[derive(Debug, Queryable, Insertable, Clone)]
@@ -149,16 +554,33 @@ pub struct NewEncryptedKey {
     pub blockchain_address: BlockchainAddress,
     pub currency: Currency,
     pub owner_id: UserId,
+    pub encrypted_data_key: Option<EncryptedDataKey>,
+    pub derivation_path: Option<DerivationPath>,
+    pub private_key_hash: Option<PrivateKeyHash>,
+    pub label: Option<KeyLabel>,
+    pub metadata: KeyMetadata,
+    pub wallet_type: Option<WalletType>,
 }
 
 impl NewEncryptedKey {
-    pub fn from_new_key(new_key: NewKey, aes_key: &str) -> Self {
+    pub fn from_new_key(
+        new_key: NewKey,
+        private_key: EncryptedPrivateKey,
+        encrypted_data_key: EncryptedDataKey,
+        private_key_hash: PrivateKeyHash,
+    ) -> Self {
         NewEncryptedKey {
             id: new_key.id,
-            private_key: EncryptedPrivateKey::from_private_key(new_key.private_key, &aes_key),
+            private_key,
             blockchain_address: new_key.blockchain_address,
             currency: new_key.currency,
             owner_id: new_key.owner_id,
+            encrypted_data_key: Some(encrypted_data_key),
+            derivation_path: new_key.derivation_path,
+            private_key_hash: Some(private_key_hash),
+            label: new_key.label,
+            metadata: new_key.metadata,
+            wallet_type: new_key.wallet_type,
         }
     }
 }
@@ -166,8 +588,15 @@ impl NewEncryptedKey {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ethkey::{Generator, Random};
     use rand::Rng;
-    use utils::encode_hex;
+    use serde_json;
+    use utils::{encode_hex, encrypt as aes_enc};
+
+    fn random_aes_key() -> Vec<u8> {
+        DataKey::generate().unwrap().as_bytes().to_vec()
+    }
+
     #[test]
     fn test_encrypt() {
         let mut random = rand::OsRng::new().unwrap();
@@ -176,14 +605,123 @@ mod tests {
             let mut pk = Vec::with_capacity(number_of_elems as usize);
             pk.resize(number_of_elems as usize, 0);
             random.fill_bytes(&mut pk);
-            let pk = PrivateKey(encode_hex(&pk));
-            let mut aes_key = [0u8; 32];
-            random.fill_bytes(&mut aes_key);
-            let aes_key = encode_hex(&aes_key);
-            assert_eq!(
-                pk.clone(),
-                PrivateKey::from_encrypted(EncryptedPrivateKey::from_private_key(pk, &aes_key), &aes_key)
-            );
+            let pk = PrivateKey::from_bytes(&pk);
+            let aes_key = random_aes_key();
+            let associated_data = key_associated_data(&KeyId::default(), Currency::Eth, &BlockchainAddress::new("address".to_string()));
+            let encrypted = EncryptedPrivateKey::from_private_key(&pk, &aes_key, &associated_data).unwrap();
+            assert!(!encrypted.is_legacy());
+            let decrypted = PrivateKey::from_encrypted(&encrypted, &aes_key, &associated_data).unwrap();
+            assert_eq!(pk.raw(), decrypted.raw());
         }
     }
+
+    #[test]
+    fn test_decrypt_legacy() {
+        let mut random = rand::OsRng::new().unwrap();
+        let pk = "b3c0e85a511cc6d21423a386de29dcf2cda6b2f2fa5ebb47948401bbb90458db".to_string();
+        let aes_key = random_aes_key();
+        let mut iv = [0u8; 16];
+        random.fill_bytes(&mut iv);
+        let encrypted = aes_enc(&decode_hex(&pk).unwrap(), &aes_key, &iv).unwrap();
+        let mut legacy = encode_hex(&iv);
+        legacy.push_str(&encode_hex(&encrypted));
+        let legacy = EncryptedPrivateKey::new(legacy);
+        assert!(legacy.is_legacy());
+        let decrypted = PrivateKey::from_encrypted(&legacy, &aes_key, b"ignored").unwrap();
+        assert_eq!(decrypted.raw(), pk);
+    }
+
+    #[test]
+    fn test_decrypt_tampered() {
+        let pk = PrivateKey::new("b3c0e85a511cc6d21423a386de29dcf2cda6b2f2fa5ebb47948401bbb90458db".to_string());
+        let aes_key = random_aes_key();
+        let key_id = KeyId::default();
+        let address = BlockchainAddress::new("b3683b4de1fc502807464b55d151e8e2d2c19cb5".to_string());
+        let associated_data = key_associated_data(&key_id, Currency::Eth, &address);
+        let encrypted = EncryptedPrivateKey::from_private_key(&pk, &aes_key, &associated_data).unwrap();
+
+        // other key id, currency or address
+        let other_data = key_associated_data(&KeyId::default(), Currency::Eth, &address);
+        assert!(PrivateKey::from_encrypted(&encrypted, &aes_key, &other_data).is_err());
+        let other_data = key_associated_data(&key_id, Currency::Stq, &address);
+        assert!(PrivateKey::from_encrypted(&encrypted, &aes_key, &other_data).is_err());
+        let other_data = key_associated_data(&key_id, Currency::Eth, &BlockchainAddress::new("other".to_string()));
+        assert!(PrivateKey::from_encrypted(&encrypted, &aes_key, &other_data).is_err());
+
+        // other encryption key
+        assert!(PrivateKey::from_encrypted(&encrypted, &random_aes_key(), &associated_data).is_err());
+
+        // flipped ciphertext byte, malformed and truncated data
+        let mut tampered = encrypted.clone().into_inner();
+        let last = if tampered.ends_with('0') { "1" } else { "0" };
+        tampered.pop();
+        tampered.push_str(last);
+        assert!(PrivateKey::from_encrypted(&EncryptedPrivateKey::new(tampered), &aes_key, &associated_data).is_err());
+        let malformed = EncryptedPrivateKey::new("v1:zz".to_string());
+        assert!(PrivateKey::from_encrypted(&malformed, &aes_key, &associated_data).is_err());
+        let truncated = EncryptedPrivateKey::new("v1:00".to_string());
+        assert!(PrivateKey::from_encrypted(&truncated, &aes_key, &associated_data).is_err());
+    }
+
+    #[test]
+    fn test_export() {
+        let pk = PrivateKey::new("b3c0e85a511cc6d21423a386de29dcf2cda6b2f2fa5ebb47948401bbb90458db".to_string());
+        let recipient = Random.generate().unwrap();
+        let public = RecipientPublicKey::new(format!("04{:x}", recipient.public()));
+        let exported = pk.export(&public).unwrap();
+        let decrypted = ecies::decrypt(recipient.secret(), &[], &decode_hex(exported.raw()).unwrap()).unwrap();
+        assert_eq!(encode_hex(&decrypted), pk.raw());
+
+        // without prefix
+        let public = RecipientPublicKey::new(format!("0x{:x}", recipient.public()));
+        assert!(pk.export(&public).is_ok());
+        // wrong length, not a curve point and malformed hex
+        assert!(pk.export(&RecipientPublicKey::new("04b3c0e85a".to_string())).is_err());
+        assert!(pk.export(&RecipientPublicKey::new(format!("{:0128x}", 1))).is_err());
+        assert!(pk.export(&RecipientPublicKey::new("zz".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_with_bytes() {
+        let pk = PrivateKey::from_bytes(&[0xb3, 0xc0, 0x0e, 0x85]);
+        assert_eq!(pk.raw(), "b3c00e85");
+        assert_eq!(pk.with_bytes(|bytes| bytes.to_vec()).unwrap(), vec![0xb3, 0xc0, 0x0e, 0x85]);
+        assert!(PrivateKey::new("zz".to_string()).with_bytes(|_| ()).is_err());
+    }
+
+    #[test]
+    fn test_wrong_key_length() {
+        let pk = PrivateKey::new("b3c0e85a".to_string());
+        assert!(EncryptedPrivateKey::from_private_key(&pk, &[0u8; 16], b"").is_err());
+        assert!(decode_aes_key("b3c0e85a").is_err());
+    }
+
+    #[test]
+    fn test_metadata_contains() {
+        let metadata = |json: &str| KeyMetadata::new(serde_json::from_str(json).unwrap());
+        let key_metadata = metadata(r#"{"merchant": "shop", "limits": {"daily": 10, "monthly": 100}, "tags": ["hot"]}"#);
+        assert!(key_metadata.contains(&KeyMetadata::default()));
+        assert!(key_metadata.contains(&metadata(r#"{"merchant": "shop"}"#)));
+        assert!(key_metadata.contains(&metadata(r#"{"limits": {"daily": 10}}"#)));
+        assert!(!key_metadata.contains(&metadata(r#"{"merchant": "market"}"#)));
+        assert!(!key_metadata.contains(&metadata(r#"{"limits": {"weekly": 50}}"#)));
+        assert!(!key_metadata.contains(&metadata(r#"{"merchant": "shop", "region": "eu"}"#)));
+    }
+
+    #[test]
+    fn test_keys_cursor() {
+        let cursor = KeysCursor {
+            created_at: UNIX_EPOCH + Duration::from_micros(1_556_100_000_123_456),
+            id: KeyId::default(),
+        };
+        let encoded = cursor.to_string();
+        assert!(!encoded.contains('='));
+        assert_eq!(encoded.parse::<KeysCursor>().unwrap(), cursor);
+        let json = serde_json::to_string(&cursor).unwrap();
+        assert_eq!(serde_json::from_str::<KeysCursor>(&json).unwrap(), cursor);
+        assert!("".parse::<KeysCursor>().is_err());
+        assert!("not a cursor".parse::<KeysCursor>().is_err());
+        let missing_id = base64::encode_config("1556100000123456", base64::URL_SAFE_NO_PAD);
+        assert!(missing_id.parse::<KeysCursor>().is_err());
+    }
 }
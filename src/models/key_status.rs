@@ -0,0 +1,66 @@
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::Pg;
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::VarChar;
+use std::fmt;
+use std::io::Write;
+
+/// Lifecycle status of a key. Only active keys can sign transactions,
+/// archived keys are retired for good and can't be reactivated.
+/// Keys that failed integrity audit are quarantined and can only be archived.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, FromSqlRow, AsExpression, Clone, Copy)]
+#[sql_type = "VarChar"]
+#[serde(rename_all = "lowercase")]
+pub enum KeyStatus {
+    Active,
+    Disabled,
+    Archived,
+    Quarantined,
+}
+
+impl Default for KeyStatus {
+    fn default() -> Self {
+        KeyStatus::Active
+    }
+}
+
+impl fmt::Display for KeyStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyStatus::Active => f.write_str("active"),
+            KeyStatus::Disabled => f.write_str("disabled"),
+            KeyStatus::Archived => f.write_str("archived"),
+            KeyStatus::Quarantined => f.write_str("quarantined"),
+        }
+    }
+}
+
+impl FromSql<VarChar, Pg> for KeyStatus {
+    fn from_sql(data: Option<&[u8]>) -> deserialize::Result<Self> {
+        match data {
+            Some(b"active") => Ok(KeyStatus::Active),
+            Some(b"disabled") => Ok(KeyStatus::Disabled),
+            Some(b"archived") => Ok(KeyStatus::Archived),
+            Some(b"quarantined") => Ok(KeyStatus::Quarantined),
+            Some(v) => Err(format!(
+                "Unrecognized enum variant: {:?}",
+                String::from_utf8(v.to_vec()).unwrap_or("Non - UTF8 value".to_string())
+            )
+            .to_string()
+            .into()),
+            None => Err("Unexpected null for non-null column".into()),
+        }
+    }
+}
+
+impl ToSql<VarChar, Pg> for KeyStatus {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        match self {
+            KeyStatus::Active => out.write_all(b"active")?,
+            KeyStatus::Disabled => out.write_all(b"disabled")?,
+            KeyStatus::Archived => out.write_all(b"archived")?,
+            KeyStatus::Quarantined => out.write_all(b"quarantined")?,
+        };
+        Ok(IsNull::No)
+    }
+}
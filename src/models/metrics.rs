@@ -1,6 +1,40 @@
+use super::currency::Currency;
+use super::key::{BlockchainAddress, KeyId};
+
 #[derive(Debug, Clone, Serialize, Default)]
 #[serde(rename = "camelCase")]
 pub struct Metrics {
     pub total_keys: u64,
     pub failed_derivations_count: u64,
 }
+
+/// Result of key integrity audit
+#[derive(Debug, Clone, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Audit {
+    pub total_keys: u64,
+    /// Keys that failed the audit, including the ones quarantined before
+    pub failed_keys: Vec<AuditFailure>,
+    /// Number of keys quarantined by this audit
+    pub quarantined_keys: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditFailure {
+    pub key_id: KeyId,
+    pub currency: Currency,
+    pub blockchain_address: BlockchainAddress,
+    pub reason: AuditFailureReason,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditFailureReason {
+    /// Private key can't be decrypted
+    Decryption,
+    /// Address can't be derived from private key, e.g. private key is malformed
+    Derivation,
+    /// Address derived from private key doesn't match the stored one
+    AddressMismatch,
+}
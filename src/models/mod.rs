@@ -1,13 +1,21 @@
 mod amount;
+mod coin_selection;
 mod currency;
 mod key;
+mod key_status;
 mod metrics;
+mod seed;
 mod transaction;
 mod user;
+mod wallet_type;
 
 pub use self::amount::*;
+pub use self::coin_selection::*;
 pub use self::currency::*;
 pub use self::key::*;
+pub use self::key_status::*;
 pub use self::metrics::*;
+pub use self::seed::*;
 pub use self::transaction::*;
 pub use self::user::*;
+pub use self::wallet_type::*;
@@ -0,0 +1,297 @@
+use std::fmt::{self, Debug, Display};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use bip39::{Language, Mnemonic as Bip39Mnemonic, MnemonicType, Seed as Bip39Seed};
+use diesel::sql_types::VarChar;
+use failure::Error as FailureError;
+use rand::RngCore;
+
+use super::currency::Currency;
+use super::key::EncryptedDataKey;
+use super::user::UserId;
+use super::wallet_type::BIP44_PURPOSE;
+use schema::{derivation_indexes, seeds};
+
+/// Length of generated master seeds, 512 bits as recommended by BIP32
+const MASTER_SEED_LEN: usize = 64;
+/// Child indexes starting from this one are hardened
+pub const HARDENED_INDEX: u32 = 0x8000_0000;
+
+/// Master seed of BIP32 hierarchical deterministic keys
+#[derive(PartialEq, Eq)]
+pub struct MasterSeed(Vec<u8>);
+mask_logs!(MasterSeed);
+
+#[derive(FromSqlRow, AsExpression, Clone, PartialEq, Eq)]
+#[sql_type = "VarChar"]
+pub struct EncryptedMasterSeed(String);
+derive_newtype_sql!(encrypted_master_seed, VarChar, EncryptedMasterSeed, EncryptedMasterSeed);
+mask_logs!(EncryptedMasterSeed);
+
+/// BIP39 mnemonic phrase
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Mnemonic(String);
+mask_logs!(Mnemonic);
+
+/// Optional BIP39 passphrase, empty string means no passphrase
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct Passphrase(String);
+mask_logs!(Passphrase);
+
+/// BIP32 serialized extended public key, e.g. `xpub...` or `tpub...`
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct ExtendedPublicKey(String);
+
+/// BIP32 derivation path, e.g. `m/44'/60'/0'/0/1`
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, FromSqlRow, AsExpression, Clone)]
+#[sql_type = "VarChar"]
+pub struct DerivationPath(String);
+derive_newtype_sql!(derivation_path, VarChar, DerivationPath, DerivationPath);
+
+impl MasterSeed {
+    pub fn new(data: Vec<u8>) -> Self {
+        MasterSeed(data)
+    }
+
+    /// Generates new random master seed
+    pub fn generate() -> Result<Self, FailureError> {
+        let mut random = rand::OsRng::new()?;
+        let mut data = vec![0u8; MASTER_SEED_LEN];
+        random.fill_bytes(&mut data);
+        Ok(MasterSeed(data))
+    }
+
+    /// Derives seed from BIP39 mnemonic with PBKDF2, empty passphrase means no passphrase
+    pub fn from_mnemonic(mnemonic: &Mnemonic, passphrase: &Passphrase) -> Result<Self, FailureError> {
+        let mnemonic = Bip39Mnemonic::from_phrase(mnemonic.0.as_str(), Language::English)?;
+        let seed = Bip39Seed::new(&mnemonic, &passphrase.0);
+        Ok(MasterSeed(seed.as_bytes().to_vec()))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Mnemonic {
+    pub fn new(phrase: String) -> Self {
+        Mnemonic(phrase)
+    }
+
+    /// Generates new random english mnemonic of 12 or 24 words
+    pub fn generate(words: usize) -> Result<Self, FailureError> {
+        let mnemonic_type = match words {
+            12 => MnemonicType::Words12,
+            24 => MnemonicType::Words24,
+            _ => return Err(format_err!("Mnemonic must have 12 or 24 words, got {}", words)),
+        };
+        let mnemonic = Bip39Mnemonic::new(mnemonic_type, Language::English);
+        Ok(Mnemonic(mnemonic.phrase().to_string()))
+    }
+
+    /// Checks that mnemonic consists of english BIP39 words and has valid checksum
+    pub fn validate(&self) -> Result<(), FailureError> {
+        Bip39Mnemonic::from_phrase(self.0.as_str(), Language::English)?;
+        Ok(())
+    }
+
+    pub fn raw(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Passphrase {
+    pub fn new(passphrase: String) -> Self {
+        Passphrase(passphrase)
+    }
+}
+
+impl ExtendedPublicKey {
+    pub fn new(data: String) -> Self {
+        ExtendedPublicKey(data)
+    }
+
+    pub fn raw(&self) -> &str {
+        &self.0
+    }
+}
+
+impl EncryptedMasterSeed {
+    pub fn new(data: String) -> Self {
+        EncryptedMasterSeed(data)
+    }
+
+    pub fn raw(&self) -> &str {
+        &self.0
+    }
+}
+
+impl DerivationPath {
+    /// BIP44 account path `m/44'/coin_type'/account'`
+    pub fn bip44_account(coin_type: u32, account: u32) -> Self {
+        DerivationPath::account(BIP44_PURPOSE, coin_type, account)
+    }
+
+    /// Account path `m/purpose'/coin_type'/account'` of BIP44 and derived schemes, e.g. BIP84
+    pub fn account(purpose: u32, coin_type: u32, account: u32) -> Self {
+        DerivationPath(format!("m/{}'/{}'/{}'", purpose, coin_type, account))
+    }
+
+    /// Path of the external chain address with `index` under this account path
+    pub fn address(&self, index: u32) -> Self {
+        DerivationPath(format!("{}/0/{}", self.0, index))
+    }
+
+    /// Child indexes of the path, hardened indexes have `HARDENED_INDEX` bit set
+    pub fn indexes(&self) -> Result<Vec<u32>, FailureError> {
+        let mut parts = self.0.split('/');
+        if parts.next() != Some("m") {
+            return Err(format_err!("Derivation path must start with m: {}", self.0));
+        }
+        parts
+            .map(|part| {
+                let (number, hardened) = if part.ends_with('\'') {
+                    (&part[..part.len() - 1], true)
+                } else {
+                    (part, false)
+                };
+                let index: u32 = number
+                    .parse()
+                    .map_err(|_| format_err!("Malformed derivation path index {}: {}", part, self.0))?;
+                if index >= HARDENED_INDEX {
+                    return Err(format_err!("Derivation path index is too big {}: {}", part, self.0));
+                }
+                Ok(if hardened { index | HARDENED_INDEX } else { index })
+            })
+            .collect()
+    }
+}
+
+impl FromStr for DerivationPath {
+    type Err = FailureError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let path = DerivationPath(s.to_string());
+        path.indexes()?;
+        Ok(path)
+    }
+}
+
+impl Display for DerivationPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct Seed {
+    pub owner_id: UserId,
+    pub master_seed: MasterSeed,
+    pub created_at: SystemTime,
+    pub updated_at: SystemTime,
+}
+
+impl Seed {
+    pub fn from_encrypted(encrypted_seed: EncryptedSeed, master_seed: MasterSeed) -> Self {
+        Seed {
+            owner_id: encrypted_seed.owner_id,
+            master_seed,
+            created_at: encrypted_seed.created_at,
+            updated_at: encrypted_seed.updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Queryable, Clone)]
+pub struct EncryptedSeed {
+    pub owner_id: UserId,
+    pub master_seed: EncryptedMasterSeed,
+    pub encrypted_data_key: EncryptedDataKey,
+    pub created_at: SystemTime,
+    pub updated_at: SystemTime,
+}
+
+#[derive(Debug, Insertable, Clone)]
+#[table_name = "seeds"]
+pub struct NewEncryptedSeed {
+    pub owner_id: UserId,
+    pub master_seed: EncryptedMasterSeed,
+    pub encrypted_data_key: EncryptedDataKey,
+}
+
+/// Next address index of keys of the currency and BIP43 purpose, derived from the seed of the owner
+#[derive(Debug, Queryable, Insertable, Clone, PartialEq, Eq)]
+#[table_name = "derivation_indexes"]
+pub struct DerivationIndex {
+    pub owner_id: UserId,
+    pub currency: Currency,
+    pub next_index: i32,
+    pub purpose: i32,
+}
+
+/// Data bound to encrypted master seed and its data key
+pub fn seed_associated_data(owner_id: &UserId) -> Vec<u8> {
+    format!("seed:{}", owner_id.0).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::encode_hex;
+
+    const MNEMONIC: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+    // https://github.com/trezor/python-mnemonic/blob/master/vectors.json
+    #[test]
+    fn test_seed_from_mnemonic() {
+        let mnemonic = Mnemonic::new(MNEMONIC.to_string());
+        assert_eq!(
+            encode_hex(MasterSeed::from_mnemonic(&mnemonic, &Passphrase::default()).unwrap().as_bytes()),
+            "5eb00bbddcf069084889a8ab9155568165f5c453ccb85e70811aaed6f6da5fc19a5ac40b389cd370d086206dec8aa6c43daea6690f20ad3d8d48b2d2ce9e38e4"
+        );
+        assert_eq!(
+            encode_hex(MasterSeed::from_mnemonic(&mnemonic, &Passphrase::new("TREZOR".to_string())).unwrap().as_bytes()),
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04"
+        );
+        let bad_checksum = Mnemonic::new(MNEMONIC.replace("about", "abandon"));
+        assert!(bad_checksum.validate().is_err());
+        assert!(MasterSeed::from_mnemonic(&bad_checksum, &Passphrase::default()).is_err());
+    }
+
+    #[test]
+    fn test_generate_mnemonic() {
+        for words in &[12, 24] {
+            let mnemonic = Mnemonic::generate(*words).unwrap();
+            assert_eq!(mnemonic.raw().split(' ').count(), *words);
+            assert!(mnemonic.validate().is_ok());
+        }
+        assert!(Mnemonic::generate(13).is_err());
+    }
+
+    #[test]
+    fn test_derivation_path() {
+        let account = DerivationPath::bip44_account(60, 1);
+        let path = account.address(5);
+        assert_eq!(path.to_string(), "m/44'/60'/1'/0/5");
+        assert_eq!(
+            path.indexes().unwrap(),
+            vec![44 | HARDENED_INDEX, 60 | HARDENED_INDEX, 1 | HARDENED_INDEX, 0, 5]
+        );
+        assert_eq!(DerivationPath::account(84, 0, 0).address(1).to_string(), "m/84'/0'/0'/0/1");
+        assert_eq!("m/44'/0'/0'/0/0".parse::<DerivationPath>().unwrap().indexes().unwrap().len(), 5);
+        assert_eq!("m".parse::<DerivationPath>().unwrap().indexes().unwrap(), Vec::<u32>::new());
+        assert!("44'/0'".parse::<DerivationPath>().is_err());
+        assert!("m/44'/x".parse::<DerivationPath>().is_err());
+        assert!("m/2147483648".parse::<DerivationPath>().is_err());
+    }
+
+    #[test]
+    fn test_seed_associated_data() {
+        // Existing seeds are encrypted with owner id in hyphenated format
+        let owner_id = "67e55044-10b1-426f-9247-bb680e5fe0c8".parse::<UserId>().unwrap();
+        assert_eq!(
+            seed_associated_data(&owner_id),
+            b"seed:67e55044-10b1-426f-9247-bb680e5fe0c8".to_vec()
+        );
+    }
+}
@@ -3,6 +3,7 @@ use std::fmt::{self, Debug, Display};
 use uuid::Uuid;
 
 use super::amount::Amount;
+use super::coin_selection::CoinSelection;
 use super::currency::Currency;
 use super::key::BlockchainAddress;
 
@@ -48,6 +49,8 @@ pub struct UnsignedTransaction {
     pub fee_price: f64,
     pub nonce: Option<u64>,
     pub utxos: Option<Vec<Utxo>>,
+    /// Overrides coin selection strategy of bitcoin service
+    pub coin_selection: Option<CoinSelection>,
 }
 
 #[derive(Debug, Serialize, Clone)]
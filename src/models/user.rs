@@ -12,9 +12,9 @@ use uuid::Uuid;
 
 use prelude::*;
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, FromSqlRow, AsExpression, Clone)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, FromSqlRow, AsExpression, Clone)]
 #[sql_type = "SqlUuid"]
-pub struct UserId(Uuid);
+pub struct UserId(pub(super) Uuid);
 derive_newtype_sql!(user_id, SqlUuid, UserId, UserId);
 
 impl Debug for UserId {
@@ -71,6 +71,10 @@ pub struct User {
     pub authentication_token: AuthenticationToken,
     pub created_at: SystemTime,
     pub updated_at: SystemTime,
+    /// User is allowed to export private keys of all users, see `KeysService::export`
+    pub can_export_keys: bool,
+    /// User is allowed to audit keys of all users, see `MetricsService::audit`
+    pub is_admin: bool,
 }
 
 impl Default for User {
@@ -81,6 +85,8 @@ impl Default for User {
             authentication_token: Default::default(),
             created_at: SystemTime::now(),
             updated_at: SystemTime::now(),
+            can_export_keys: false,
+            is_admin: false,
         }
     }
 }
@@ -92,6 +98,10 @@ pub struct NewUser {
     pub id: UserId,
     pub name: String,
     pub authentication_token: AuthenticationToken,
+    #[serde(default)]
+    pub can_export_keys: bool,
+    #[serde(default)]
+    pub is_admin: bool,
 }
 
 impl Default for NewUser {
@@ -100,6 +110,8 @@ impl Default for NewUser {
             id: UserId(Uuid::new_v4()),
             name: "Anonymous".to_string(),
             authentication_token: Default::default(),
+            can_export_keys: false,
+            is_admin: false,
         }
     }
 }
@@ -0,0 +1,121 @@
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::Pg;
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::VarChar;
+use failure::Error as FailureError;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+use super::currency::Currency;
+
+/// BIP43 purpose of BIP44 derivation paths, used by keys without wallet type and by P2PKH keys
+pub const BIP44_PURPOSE: u32 = 44;
+/// BIP43 purpose of BIP49 derivation paths of P2SH-P2WPKH keys
+pub const BIP49_PURPOSE: u32 = 49;
+/// BIP43 purpose of BIP84 derivation paths of P2WPKH keys
+pub const BIP84_PURPOSE: u32 = 84;
+
+/// Script that locks funds of a bitcoin key. It decides the address format of the key
+/// and how transaction inputs are signed. Keys of account based currencies don't have it.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, FromSqlRow, AsExpression, Clone, Copy)]
+#[sql_type = "VarChar"]
+#[serde(rename_all = "lowercase")]
+pub enum WalletType {
+    /// Legacy pay to public key hash, base58 address
+    P2pkh,
+    /// Native SegWit pay to witness public key hash, bech32 address
+    P2wpkh,
+    /// P2WPKH nested in pay to script hash, base58 address for senders that can't pay to bech32
+    #[serde(rename = "p2sh-p2wpkh")]
+    P2shP2wpkh,
+}
+
+impl WalletType {
+    /// Wallet type of keys created without one, `None` for currencies that don't have wallet types
+    pub fn default_for(currency: Currency) -> Option<Self> {
+        match currency {
+            Currency::Btc => Some(WalletType::P2pkh),
+            Currency::Eth | Currency::Stq => None,
+        }
+    }
+
+    /// SegWit inputs are signed in witness and require compressed public keys
+    pub fn is_segwit(self) -> bool {
+        match self {
+            WalletType::P2pkh => false,
+            WalletType::P2wpkh | WalletType::P2shP2wpkh => true,
+        }
+    }
+
+    /// BIP43 purpose of derivation paths of HD keys, so that wallets recover them with the same address format
+    pub fn purpose(self) -> u32 {
+        match self {
+            WalletType::P2pkh => BIP44_PURPOSE,
+            WalletType::P2wpkh => BIP84_PURPOSE,
+            WalletType::P2shP2wpkh => BIP49_PURPOSE,
+        }
+    }
+}
+
+/// BIP43 purpose of derivation paths of HD keys with `wallet_type`
+pub fn derivation_purpose(wallet_type: Option<WalletType>) -> u32 {
+    wallet_type.map(WalletType::purpose).unwrap_or(BIP44_PURPOSE)
+}
+
+/// Bitcoin keys stored before wallet types were added are P2PKH
+impl Default for WalletType {
+    fn default() -> Self {
+        WalletType::P2pkh
+    }
+}
+
+impl fmt::Display for WalletType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WalletType::P2pkh => f.write_str("p2pkh"),
+            WalletType::P2wpkh => f.write_str("p2wpkh"),
+            WalletType::P2shP2wpkh => f.write_str("p2sh-p2wpkh"),
+        }
+    }
+}
+
+impl FromStr for WalletType {
+    type Err = FailureError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "p2pkh" => Ok(WalletType::P2pkh),
+            "p2wpkh" => Ok(WalletType::P2wpkh),
+            "p2sh-p2wpkh" => Ok(WalletType::P2shP2wpkh),
+            _ => Err(format_err!("Unknown wallet type: {}", s)),
+        }
+    }
+}
+
+impl FromSql<VarChar, Pg> for WalletType {
+    fn from_sql(data: Option<&[u8]>) -> deserialize::Result<Self> {
+        match data {
+            Some(b"p2pkh") => Ok(WalletType::P2pkh),
+            Some(b"p2wpkh") => Ok(WalletType::P2wpkh),
+            Some(b"p2sh-p2wpkh") => Ok(WalletType::P2shP2wpkh),
+            Some(v) => Err(format!(
+                "Unrecognized enum variant: {:?}",
+                String::from_utf8(v.to_vec()).unwrap_or("Non - UTF8 value".to_string())
+            )
+            .to_string()
+            .into()),
+            None => Err("Unexpected null for non-null column".into()),
+        }
+    }
+}
+
+impl ToSql<VarChar, Pg> for WalletType {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        match self {
+            WalletType::P2pkh => out.write_all(b"p2pkh")?,
+            WalletType::P2wpkh => out.write_all(b"p2wpkh")?,
+            WalletType::P2shP2wpkh => out.write_all(b"p2sh-p2wpkh")?,
+        };
+        Ok(IsNull::No)
+    }
+}
@@ -35,6 +35,14 @@ pub enum ErrorSource {
 pub enum ErrorContext {
     #[fail(display = "database context - error getting connection")]
     Connection,
+    #[fail(display = "database context - error encrypting private key")]
+    Encryption,
+    #[fail(display = "database context - error decrypting private key")]
+    Decryption,
+    #[fail(display = "database context - private key verification failed")]
+    Verification,
+    #[fail(display = "database context - error loading master key")]
+    MasterKey,
 }
 
 derive_error_impls!();
@@ -1,78 +1,466 @@
+use std::sync::Arc;
+use std::time::SystemTime;
+
 use diesel;
+use diesel::dsl::now;
+use diesel::pg::{Pg, PgConnection};
+use diesel::sql_types::Jsonb;
 
 use super::error::*;
 use super::executor::with_tls_connection;
+use master_key::MasterKeyProvider;
 use models::*;
 use prelude::*;
 use schema::keys::dsl::*;
+use schema::keys::BoxedQuery;
+use utils::{encode_hex, hmac_sha256, wipe};
+
+diesel_infix_operator!(JsonbContains, " @> ", backend: Pg);
 
 pub trait KeysRepo: Send + Sync + 'static {
-    fn list(&self, current_user_id: UserId, offset: i64, limit: i64) -> Result<Vec<Key>, Error>;
+    /// Lists keys in `(created_at, id)` order, starting after the cursor
+    fn list(&self, current_user_id: UserId, filter: KeysFilter, cursor: Option<KeysCursor>, limit: i64) -> Result<Vec<Key>, Error>;
+    fn count(&self, current_user_id: UserId, filter: KeysFilter) -> Result<i64, Error>;
     fn all(&self) -> Result<Vec<Key>, Error>;
     fn create(&self, payload: NewKey) -> Result<Key, Error>;
-    // We don't check currency, since there's case when you want to transfer
+    // Currency is optional, since there's case when you want to transfer
     // ether to stq account (to be able to make withdrawal)
-    fn find_by_address(&self, current_user_id: UserId, address: BlockchainAddress) -> Result<Option<Key>, Error>;
+    fn find_by_address(
+        &self,
+        current_user_id: UserId,
+        address: BlockchainAddress,
+        key_currency: Option<Currency>,
+    ) -> Result<Option<Key>, Error>;
+    fn find(&self, key_id: KeyId) -> Result<Option<Key>, Error>;
+    /// Finds key of `key_currency` with the same private key by its keyed hash
+    fn find_by_private_key(&self, private_key: &PrivateKey, key_currency: Currency) -> Result<Option<Key>, Error>;
+    fn update_status(&self, key_id: KeyId, key_status: KeyStatus) -> Result<Key, Error>;
+    /// Updates status, label and metadata of the key, `None` fields are left as is
+    fn update(&self, key_id: KeyId, changes: KeyUpdate) -> Result<Key, Error>;
+    /// Sets `last_used_at` to now and increments `signatures_count` after the key signed a transaction
+    fn record_signature(&self, key_id: KeyId) -> Result<Key, Error>;
+    /// Sets creation time and usage of the key restored from backup
+    fn restore_usage(
+        &self,
+        key_id: KeyId,
+        key_created_at: SystemTime,
+        key_last_used_at: Option<SystemTime>,
+        key_signatures_count: i64,
+    ) -> Result<Key, Error>;
+    /// Decrypts private key of the key. Should be called right before the private key
+    /// is used, it's wiped from memory when dropped.
+    fn decrypt_private_key(&self, key: &Key) -> Result<PrivateKey, Error>;
+}
+
+/// Key encryption key (KEK) used for envelope encryption of private keys.
+///
+/// Every private key is encrypted with its own random data key (DEK) and only
+/// the data key is encrypted (wrapped) with KEK. So rotating KEK only re-wraps
+/// data keys and a leaked data key exposes exactly one private key.
+pub trait KeyEncryptionProvider: Send + Sync + 'static {
+    /// Encrypts key material with KEK, `associated_data` is authenticated, but not encrypted
+    fn wrap_key(&self, key: &[u8], associated_data: &[u8]) -> Result<String, Error>;
+    /// Decrypts key material encrypted with `wrap_key`
+    fn unwrap_key(&self, wrapped_key: &str, associated_data: &[u8]) -> Result<Vec<u8>, Error>;
+    /// Hex encoded HMAC of key material under a key derived from KEK. The same key
+    /// material always has the same hash, until KEK is rotated.
+    fn hash_key(&self, key: &[u8]) -> Result<String, Error>;
+}
+
+/// Info, that the key for hashes of private keys is derived from KEK with
+pub const KEY_HASH_INFO: &[u8] = b"keystore private key hash";
+
+/// Uses master key as key encryption key
+pub struct MasterKeyEncryptionProvider {
+    master_key: Vec<u8>,
+    hash_key: Vec<u8>,
+}
+
+impl MasterKeyEncryptionProvider {
+    pub fn new(master_key: Vec<u8>) -> Self {
+        let hash_key = hmac_sha256(&master_key, KEY_HASH_INFO);
+        MasterKeyEncryptionProvider { master_key, hash_key }
+    }
+}
+
+impl Drop for MasterKeyEncryptionProvider {
+    fn drop(&mut self) {
+        wipe(&mut self.master_key);
+        wipe(&mut self.hash_key);
+    }
+}
+
+impl KeyEncryptionProvider for MasterKeyEncryptionProvider {
+    fn wrap_key(&self, key: &[u8], associated_data: &[u8]) -> Result<String, Error> {
+        encrypt_key_material(key, &self.master_key, associated_data).map_err(ectx!(ErrorContext::Encryption, ErrorKind::Internal))
+    }
+
+    fn unwrap_key(&self, wrapped_key: &str, associated_data: &[u8]) -> Result<Vec<u8>, Error> {
+        decrypt_key_material(wrapped_key, &self.master_key, associated_data).map_err(ectx!(ErrorContext::Decryption, ErrorKind::Internal))
+    }
+
+    fn hash_key(&self, key: &[u8]) -> Result<String, Error> {
+        Ok(encode_hex(&hmac_sha256(&self.hash_key, key)))
+    }
 }
 
 pub struct KeysRepoImpl {
-    aes_key: String,
+    key_encryption_provider: Arc<KeyEncryptionProvider>,
+}
+
+/// Result of key encryption key rotation
+#[derive(Debug, Clone, Default)]
+pub struct KeyRotationReport {
+    /// Number of keys re-wrapped with the new key encryption key
+    pub rotated: u64,
+    /// Number of keys that were already wrapped with the new key encryption key
+    pub skipped: u64,
 }
 
 impl KeysRepoImpl {
-    pub fn new(aes_key: String) -> Self {
-        KeysRepoImpl { aes_key }
+    /// Wraps data keys with the master key from `master_key_provider`, selected in config
+    pub fn new(master_key_provider: &MasterKeyProvider) -> Result<Self, Error> {
+        let key_encryption_provider = master_key_provider
+            .key_encryption_provider()
+            .map_err(ectx!(try ErrorContext::MasterKey, ErrorKind::Internal))?;
+        Ok(Self::with_key_encryption_provider(key_encryption_provider))
+    }
+
+    /// Uses already created key encryption provider, e.g. shared by repos of all requests
+    pub fn with_key_encryption_provider(key_encryption_provider: Arc<KeyEncryptionProvider>) -> Self {
+        KeysRepoImpl { key_encryption_provider }
+    }
+
+    pub fn key_encryption_provider(&self) -> Arc<KeyEncryptionProvider> {
+        self.key_encryption_provider.clone()
+    }
+
+    /// Moves all private keys encrypted with key encryption key directly (including
+    /// legacy AES-CBC format) to envelope encryption. Returns the number of migrated keys.
+    /// Should be run inside a transaction.
+    pub fn migrate_to_envelope_encryption(&self) -> Result<u64, Error> {
+        with_tls_connection(|conn| {
+            let encrypted_keys = keys
+                .filter(encrypted_data_key.is_null())
+                .get_results::<Key>(conn)
+                .map_err(ectx!(try ErrorKind::Internal))?;
+            let mut migrated: u64 = 0;
+            for encrypted_key in encrypted_keys {
+                let associated_data = associated_data(&encrypted_key);
+                let private_key_ = decrypt_private_key(&encrypted_key, &*self.key_encryption_provider)?;
+                let (encrypted_pk, encrypted_dk) = self.encrypt_private_key(&private_key_, &associated_data)?;
+                let key_hash = self.hash_private_key(&private_key_)?;
+                update_encrypted_keys(conn, encrypted_key.id, encrypted_pk, encrypted_dk, key_hash)?;
+                migrated += 1;
+            }
+            Ok(migrated)
+        })
+    }
+
+    /// Sets keyed hashes of private keys created before they were hashed, so that they are
+    /// found by `find_by_private_key`. Returns the number of hashed keys. Should be run inside a transaction.
+    pub fn hash_private_keys(&self) -> Result<u64, Error> {
+        with_tls_connection(|conn| {
+            let encrypted_keys = keys
+                .filter(private_key_hash.is_null())
+                .get_results::<Key>(conn)
+                .map_err(ectx!(try ErrorKind::Internal))?;
+            let mut hashed: u64 = 0;
+            for encrypted_key in encrypted_keys {
+                let private_key_ = decrypt_private_key(&encrypted_key, &*self.key_encryption_provider)?;
+                let key_hash = self.hash_private_key(&private_key_)?;
+                let key_id = encrypted_key.id.clone();
+                diesel::update(keys.filter(id.eq(key_id.clone())))
+                    .set(private_key_hash.eq(key_hash))
+                    .execute(conn)
+                    .map_err(move |e| {
+                        let kind = ErrorKind::from_diesel(&e);
+                        ectx!(try err e, kind => key_id)
+                    })?;
+                hashed += 1;
+            }
+            Ok(hashed)
+        })
+    }
+
+    /// Re-wraps data keys of all private keys, wrapped with `old_provider`, with the key encryption
+    /// provider of this repo. Keys without data keys are moved to envelope encryption.
+    /// Every rotated key is decrypted back and passed to `verify` with its private key before it's stored,
+    /// rotation is aborted if `verify` returns false.
+    ///
+    /// Keys that are already wrapped with the new key encryption key are skipped, so an interrupted
+    /// rotation can be run again. Should be run inside a serializable transaction.
+    pub fn rotate_key_encryption_key<F>(&self, old_provider: &KeyEncryptionProvider, verify: F) -> Result<KeyRotationReport, Error>
+    where
+        F: Fn(&Key, &PrivateKey) -> bool,
+    {
+        with_tls_connection(|conn| {
+            let encrypted_keys = keys.get_results::<Key>(conn).map_err(ectx!(try ErrorKind::Internal))?;
+            let mut report = KeyRotationReport::default();
+            for encrypted_key in encrypted_keys {
+                let associated_data = associated_data(&encrypted_key);
+                let (encrypted_pk, encrypted_dk) = match encrypted_key.encrypted_data_key.clone() {
+                    Some(encrypted_dk) => {
+                        // Authenticated encryption guarantees that successful unwrapping is done with the right key
+                        if self
+                            .key_encryption_provider
+                            .unwrap_key(encrypted_dk.raw(), &associated_data)
+                            .is_ok()
+                        {
+                            report.skipped += 1;
+                            continue;
+                        }
+                        let data_key = DataKey::new(old_provider.unwrap_key(encrypted_dk.raw(), &associated_data)?);
+                        let encrypted_dk = self.key_encryption_provider.wrap_key(data_key.as_bytes(), &associated_data)?;
+                        (encrypted_key.private_key.clone(), EncryptedDataKey::new(encrypted_dk))
+                    }
+                    None => {
+                        let private_key_ = decrypt_private_key(&encrypted_key, &*self.key_encryption_provider)
+                            .or_else(|_| decrypt_private_key(&encrypted_key, old_provider))?;
+                        self.encrypt_private_key(&private_key_, &associated_data)?
+                    }
+                };
+                let key_id = encrypted_key.id.clone();
+                let rotated = Key {
+                    private_key: encrypted_pk.clone(),
+                    encrypted_data_key: Some(encrypted_dk.clone()),
+                    ..encrypted_key
+                };
+                let rotated_private_key = decrypt_private_key(&rotated, &*self.key_encryption_provider)?;
+                if !verify(&rotated, &rotated_private_key) {
+                    return Err(ectx!(err ErrorContext::Verification, ErrorKind::Internal => key_id));
+                }
+                // Hash key is derived from key encryption key, so hashes change with it
+                let key_hash = self.hash_private_key(&rotated_private_key)?;
+                update_encrypted_keys(conn, key_id, encrypted_pk, encrypted_dk, key_hash)?;
+                report.rotated += 1;
+            }
+            Ok(report)
+        })
+    }
+
+    /// Encrypts private key with a new data key and wraps the data key with key encryption key
+    fn encrypt_private_key(
+        &self,
+        private_key_: &PrivateKey,
+        associated_data: &[u8],
+    ) -> Result<(EncryptedPrivateKey, EncryptedDataKey), Error> {
+        let data_key = DataKey::generate().map_err(ectx!(try ErrorContext::Encryption, ErrorKind::Internal))?;
+        let encrypted_pk = EncryptedPrivateKey::from_private_key(private_key_, data_key.as_bytes(), associated_data)
+            .map_err(ectx!(try ErrorContext::Encryption, ErrorKind::Internal))?;
+        let encrypted_dk = self.key_encryption_provider.wrap_key(data_key.as_bytes(), associated_data)?;
+        Ok((encrypted_pk, EncryptedDataKey::new(encrypted_dk)))
+    }
+
+    fn hash_private_key(&self, private_key_: &PrivateKey) -> Result<PrivateKeyHash, Error> {
+        let key_hash = private_key_
+            .with_bytes(|bytes| self.key_encryption_provider.hash_key(bytes))
+            .map_err(ectx!(try ErrorContext::Encryption, ErrorKind::Internal))??;
+        Ok(PrivateKeyHash::new(key_hash))
     }
 }
 
+fn associated_data(encrypted_key: &Key) -> Vec<u8> {
+    key_associated_data(&encrypted_key.id, encrypted_key.currency, &encrypted_key.blockchain_address)
+}
+
+fn decrypt_private_key(encrypted_key: &Key, provider: &KeyEncryptionProvider) -> Result<PrivateKey, Error> {
+    let key_id = encrypted_key.id.clone();
+    let associated_data = associated_data(encrypted_key);
+    match encrypted_key.encrypted_data_key {
+        Some(ref encrypted_dk) => {
+            let data_key = DataKey::new(provider.unwrap_key(encrypted_dk.raw(), &associated_data)?);
+            PrivateKey::from_encrypted(&encrypted_key.private_key, data_key.as_bytes(), &associated_data)
+                .map_err(ectx!(ErrorContext::Decryption, ErrorKind::Internal => key_id))
+        }
+        // Keys created before envelope encryption are encrypted with key encryption key directly
+        None => provider
+            .unwrap_key(encrypted_key.private_key.raw(), &associated_data)
+            .map(|mut decrypted| {
+                let private_key_ = PrivateKey::from_bytes(&decrypted);
+                wipe(&mut decrypted);
+                private_key_
+            }),
+    }
+}
+
+fn filtered_keys(current_user_id: UserId, filter: &KeysFilter) -> BoxedQuery<'static, Pg> {
+    let mut query = keys.filter(owner_id.eq(current_user_id)).into_boxed();
+    if let Some(key_currency) = filter.currency {
+        query = query.filter(currency.eq(key_currency));
+    }
+    if let Some(key_status) = filter.status {
+        query = query.filter(status.eq(key_status));
+    }
+    if let Some(key_label) = filter.label.clone() {
+        query = query.filter(label.eq(key_label));
+    }
+    if let Some(key_metadata) = filter.metadata.clone() {
+        query = query.filter(JsonbContains::new(metadata, key_metadata.into_sql::<Jsonb>()));
+    }
+    query
+}
+
+fn update_encrypted_keys(
+    conn: &PgConnection,
+    key_id: KeyId,
+    encrypted_pk: EncryptedPrivateKey,
+    encrypted_dk: EncryptedDataKey,
+    key_hash: PrivateKeyHash,
+) -> Result<(), Error> {
+    diesel::update(keys.filter(id.eq(key_id.clone())))
+        .set((
+            private_key.eq(encrypted_pk),
+            encrypted_data_key.eq(encrypted_dk),
+            private_key_hash.eq(key_hash),
+        ))
+        .execute(conn)
+        .map(|_| ())
+        .map_err(ectx!(ErrorKind::Internal => key_id))
+}
+
 impl KeysRepo for KeysRepoImpl {
     fn all(&self) -> Result<Vec<Key>, Error> {
+        with_tls_connection(|conn| keys.get_results::<Key>(conn).map_err(ectx!(ErrorKind::Internal)))
+    }
+
+    fn list(&self, current_user_id: UserId, filter: KeysFilter, cursor: Option<KeysCursor>, limit: i64) -> Result<Vec<Key>, Error> {
         with_tls_connection(|conn| {
-            let encrypted_keys = keys.get_results::<EncryptedKey>(conn).map_err(ectx!(try ErrorKind::Internal))?;
-            Ok(encrypted_keys
-                .into_iter()
-                .map(|encrypted_key| Key::from_encrypted(encrypted_key, &self.aes_key))
-                .collect())
+            let mut query = filtered_keys(current_user_id, &filter);
+            if let Some(cursor) = cursor.clone() {
+                query = query.filter(
+                    created_at
+                        .gt(cursor.created_at)
+                        .or(created_at.eq(cursor.created_at).and(id.gt(cursor.id))),
+                );
+            }
+            query
+                .order((created_at.asc(), id.asc()))
+                .limit(limit)
+                .get_results::<Key>(conn)
+                .map_err(ectx!(ErrorKind::Internal => filter, cursor, limit))
         })
     }
 
-    fn list(&self, current_user_id: UserId, offset: i64, limit: i64) -> Result<Vec<Key>, Error> {
+    fn count(&self, current_user_id: UserId, filter: KeysFilter) -> Result<i64, Error> {
         with_tls_connection(|conn| {
-            let encrypted_keys = keys
-                .filter(owner_id.eq(current_user_id))
-                .offset(offset)
-                .limit(limit)
-                .get_results::<EncryptedKey>(conn)
-                .map_err(ectx!(try ErrorKind::Internal))?;
-            Ok(encrypted_keys
-                .into_iter()
-                .map(|encrypted_key| Key::from_encrypted(encrypted_key, &self.aes_key))
-                .collect())
+            filtered_keys(current_user_id, &filter)
+                .count()
+                .get_result::<i64>(conn)
+                .map_err(ectx!(ErrorKind::Internal => filter))
         })
     }
 
-    fn find_by_address(&self, _current_user_id: UserId, address: BlockchainAddress) -> Result<Option<Key>, Error> {
+    fn find_by_address(
+        &self,
+        _current_user_id: UserId,
+        address: BlockchainAddress,
+        key_currency: Option<Currency>,
+    ) -> Result<Option<Key>, Error> {
         // no need for current user check, because essentially all dr accounts are system
         // e.g. you put in ether, change btc and withdraw btc. Or transfer your eth to another workplace
         with_tls_connection(|conn| {
-            let maybe_enc_key = keys
-                .filter(blockchain_address.eq(address))
+            let mut query = keys.filter(blockchain_address.eq(address)).into_boxed();
+            if let Some(key_currency) = key_currency {
+                query = query.filter(currency.eq(key_currency));
+            }
+            query
                 .limit(1)
-                .get_results(conn)
+                .get_results::<Key>(conn)
                 .map(|ks| ks.get(0).cloned())
-                .map_err(ectx!(try ErrorKind::Internal))?;
-            Ok(maybe_enc_key.map(|enc_key| Key::from_encrypted(enc_key, &self.aes_key)))
+                .map_err(ectx!(ErrorKind::Internal))
+        })
+    }
+
+    fn find(&self, key_id: KeyId) -> Result<Option<Key>, Error> {
+        with_tls_connection(|conn| {
+            keys.filter(id.eq(key_id.clone()))
+                .get_result::<Key>(conn)
+                .optional()
+                .map_err(ectx!(ErrorKind::Internal => key_id))
+        })
+    }
+
+    fn find_by_private_key(&self, private_key_: &PrivateKey, key_currency: Currency) -> Result<Option<Key>, Error> {
+        let key_hash = self.hash_private_key(private_key_)?;
+        with_tls_connection(|conn| {
+            keys.filter(private_key_hash.eq(key_hash))
+                .filter(currency.eq(key_currency))
+                .get_result::<Key>(conn)
+                .optional()
+                .map_err(ectx!(ErrorKind::Internal => key_currency))
+        })
+    }
+
+    fn update_status(&self, key_id: KeyId, key_status: KeyStatus) -> Result<Key, Error> {
+        with_tls_connection(|conn| {
+            diesel::update(keys.filter(id.eq(key_id.clone())))
+                .set(status.eq(key_status))
+                .get_result::<Key>(conn)
+                .map_err(ectx!(ErrorKind::Internal => key_id, key_status))
         })
     }
 
+    fn record_signature(&self, key_id: KeyId) -> Result<Key, Error> {
+        with_tls_connection(|conn| {
+            // increment is done in sql, so that concurrent signatures are all counted
+            diesel::update(keys.filter(id.eq(key_id.clone())))
+                .set((last_used_at.eq(now.nullable()), signatures_count.eq(signatures_count + 1)))
+                .get_result::<Key>(conn)
+                .map_err(ectx!(ErrorKind::Internal => key_id))
+        })
+    }
+
+    fn restore_usage(
+        &self,
+        key_id: KeyId,
+        key_created_at: SystemTime,
+        key_last_used_at: Option<SystemTime>,
+        key_signatures_count: i64,
+    ) -> Result<Key, Error> {
+        with_tls_connection(|conn| {
+            diesel::update(keys.filter(id.eq(key_id.clone())))
+                .set((
+                    created_at.eq(key_created_at),
+                    last_used_at.eq(key_last_used_at),
+                    signatures_count.eq(key_signatures_count),
+                ))
+                .get_result::<Key>(conn)
+                .map_err(ectx!(ErrorKind::Internal => key_id, key_signatures_count))
+        })
+    }
+
+    fn update(&self, key_id: KeyId, changes: KeyUpdate) -> Result<Key, Error> {
+        with_tls_connection(|conn| {
+            // Diesel refuses to build update without changes
+            if changes.is_empty() {
+                return keys
+                    .filter(id.eq(key_id.clone()))
+                    .get_result::<Key>(conn)
+                    .map_err(ectx!(ErrorKind::Internal => key_id));
+            }
+            let changes_clone = changes.clone();
+            diesel::update(keys.filter(id.eq(key_id.clone())))
+                .set(&changes)
+                .get_result::<Key>(conn)
+                .map_err(ectx!(ErrorKind::Internal => key_id, changes_clone))
+        })
+    }
+
+    fn decrypt_private_key(&self, key: &Key) -> Result<PrivateKey, Error> {
+        decrypt_private_key(key, &*self.key_encryption_provider)
+    }
+
     fn create(&self, payload: NewKey) -> Result<Key, Error> {
-        let payload = NewEncryptedKey::from_new_key(payload, &self.aes_key);
+        let associated_data = key_associated_data(&payload.id, payload.currency, &payload.blockchain_address);
+        let (encrypted_pk, encrypted_dk) = self.encrypt_private_key(&payload.private_key, &associated_data)?;
+        let key_hash = self.hash_private_key(&payload.private_key)?;
+        let payload = NewEncryptedKey::from_new_key(payload, encrypted_pk, encrypted_dk, key_hash);
         let payload_clone = payload.clone();
         with_tls_connection(move |conn| {
             diesel::insert_into(keys)
                 .values(payload.clone())
-                .get_result::<EncryptedKey>(conn)
-                .map(|encrypted_key| Key::from_encrypted(encrypted_key, &self.aes_key))
+                .get_result::<Key>(conn)
                 .map_err(move |e| {
                     let kind = ErrorKind::from_diesel(&e);
                     ectx!(err e, kind => payload_clone)
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
 
@@ -5,9 +6,11 @@ use super::error::*;
 use super::executor::DbExecutor;
 use super::executor::Isolation;
 use super::keys::*;
+use super::seeds::*;
 use super::users::*;
 use models::*;
 use prelude::*;
+use validator::{ValidationError, ValidationErrors};
 
 #[derive(Clone)]
 pub struct KeysRepoMock {
@@ -22,48 +25,249 @@ impl KeysRepoMock {
     }
 }
 
+fn matches_filter(key: &Key, current_user_id: &UserId, filter: &KeysFilter) -> bool {
+    key.owner_id == *current_user_id
+        && filter.currency.map(|currency| key.currency == currency).unwrap_or(true)
+        && filter.status.map(|status| key.status == status).unwrap_or(true)
+        && (filter.label.is_none() || key.label == filter.label)
+        && filter
+            .metadata
+            .as_ref()
+            .map(|metadata| key.metadata.contains(metadata))
+            .unwrap_or(true)
+}
+
 impl KeysRepo for KeysRepoMock {
     fn all(&self) -> Result<Vec<Key>, Error> {
         let data = self.data.lock().unwrap();
         Ok(data.iter().cloned().collect())
     }
-    fn list(&self, current_user_id: UserId, offset: i64, limit: i64) -> Result<Vec<Key>, Error> {
+    fn list(&self, current_user_id: UserId, filter: KeysFilter, cursor: Option<KeysCursor>, limit: i64) -> Result<Vec<Key>, Error> {
         let data = self.data.lock().unwrap();
-        Ok(data
+        let mut keys: Vec<Key> = data
             .iter()
-            .filter(|x| x.owner_id == current_user_id)
-            .skip(offset as usize)
-            .take(limit as usize)
+            .filter(|x| matches_filter(x, &current_user_id, &filter))
+            .filter(|x| cursor.as_ref().map(|cursor| cursor.precedes(x)).unwrap_or(true))
             .cloned()
-            .collect())
+            .collect();
+        keys.sort_by_key(|x| {
+            let cursor = KeysCursor::new(x);
+            (cursor.created_at, cursor.id)
+        });
+        keys.truncate(limit as usize);
+        Ok(keys)
     }
 
-    fn find_by_address(&self, current_user_id: UserId, address: BlockchainAddress) -> Result<Option<Key>, Error> {
+    fn count(&self, current_user_id: UserId, filter: KeysFilter) -> Result<i64, Error> {
+        let data = self.data.lock().unwrap();
+        Ok(data.iter().filter(|x| matches_filter(x, &current_user_id, &filter)).count() as i64)
+    }
+
+    fn find_by_address(
+        &self,
+        current_user_id: UserId,
+        address: BlockchainAddress,
+        currency: Option<Currency>,
+    ) -> Result<Option<Key>, Error> {
         let data = self.data.lock().unwrap();
         let keys: Vec<Key> = data
             .iter()
             .filter(|x| x.owner_id == current_user_id)
             .filter(|x| x.blockchain_address == address)
+            .filter(|x| currency.is_none() || Some(x.currency) == currency)
             .take(1)
             .cloned()
             .collect();
         Ok(keys.get(0).cloned())
     }
 
+    fn find(&self, key_id: KeyId) -> Result<Option<Key>, Error> {
+        let data = self.data.lock().unwrap();
+        Ok(data.iter().find(|x| x.id == key_id).cloned())
+    }
+
+    fn update_status(&self, key_id: KeyId, key_status: KeyStatus) -> Result<Key, Error> {
+        let mut data = self.data.lock().unwrap();
+        let key = match data.iter_mut().find(|x| x.id == key_id) {
+            Some(key) => key,
+            None => return Err(ErrorKind::Internal.into()),
+        };
+        key.status = key_status;
+        key.updated_at = SystemTime::now();
+        Ok(key.clone())
+    }
+
+    fn record_signature(&self, key_id: KeyId) -> Result<Key, Error> {
+        let mut data = self.data.lock().unwrap();
+        let key = match data.iter_mut().find(|x| x.id == key_id) {
+            Some(key) => key,
+            None => return Err(ErrorKind::Internal.into()),
+        };
+        key.last_used_at = Some(SystemTime::now());
+        key.signatures_count += 1;
+        Ok(key.clone())
+    }
+
+    fn restore_usage(
+        &self,
+        key_id: KeyId,
+        key_created_at: SystemTime,
+        key_last_used_at: Option<SystemTime>,
+        key_signatures_count: i64,
+    ) -> Result<Key, Error> {
+        let mut data = self.data.lock().unwrap();
+        let key = match data.iter_mut().find(|x| x.id == key_id) {
+            Some(key) => key,
+            None => return Err(ErrorKind::Internal.into()),
+        };
+        key.created_at = key_created_at;
+        key.last_used_at = key_last_used_at;
+        key.signatures_count = key_signatures_count;
+        Ok(key.clone())
+    }
+
+    fn update(&self, key_id: KeyId, changes: KeyUpdate) -> Result<Key, Error> {
+        let mut data = self.data.lock().unwrap();
+        let key = match data.iter_mut().find(|x| x.id == key_id) {
+            Some(key) => key,
+            None => return Err(ErrorKind::Internal.into()),
+        };
+        if let Some(key_status) = changes.status {
+            key.status = key_status;
+        }
+        if let Some(label) = changes.label {
+            key.label = Some(label);
+        }
+        if let Some(metadata) = changes.metadata {
+            key.metadata = metadata;
+        }
+        key.updated_at = SystemTime::now();
+        Ok(key.clone())
+    }
+
     fn create(&self, payload: NewKey) -> Result<Key, Error> {
         let mut data = self.data.lock().unwrap();
+        if data.iter().any(|x| {
+            x.currency == payload.currency
+                && (x.blockchain_address == payload.blockchain_address || x.private_key.raw() == payload.private_key.raw())
+        }) {
+            let mut errors = ValidationErrors::new();
+            errors.add("database", ValidationError::new("not unique"));
+            return Err(ErrorKind::Constraints(errors).into());
+        }
         let key = Key {
             id: payload.id,
             currency: payload.currency,
             blockchain_address: payload.blockchain_address,
             owner_id: payload.owner_id,
-            private_key: payload.private_key,
+            // keys are not encrypted in mock
+            private_key: EncryptedPrivateKey::new(payload.private_key.raw().to_string()),
             created_at: SystemTime::now(),
             updated_at: SystemTime::now(),
+            encrypted_data_key: None,
+            derivation_path: payload.derivation_path,
+            private_key_hash: None,
+            status: KeyStatus::Active,
+            label: payload.label,
+            metadata: payload.metadata,
+            last_used_at: None,
+            signatures_count: 0,
+            wallet_type: payload.wallet_type,
         };
         data.push(key.clone());
         Ok(key)
     }
+
+    fn find_by_private_key(&self, private_key: &PrivateKey, key_currency: Currency) -> Result<Option<Key>, Error> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .iter()
+            .find(|x| x.currency == key_currency && x.private_key.raw() == private_key.raw())
+            .cloned())
+    }
+
+    fn decrypt_private_key(&self, key: &Key) -> Result<PrivateKey, Error> {
+        Ok(PrivateKey::new(key.private_key.raw().to_string()))
+    }
+}
+
+#[derive(Clone)]
+pub struct SeedsRepoMock {
+    data: Arc<Mutex<HashMap<UserId, Vec<u8>>>>,
+    indexes: Arc<Mutex<HashMap<(UserId, Currency, u32), u32>>>,
+}
+
+impl SeedsRepoMock {
+    pub fn new() -> Self {
+        Self {
+            data: Arc::new(Mutex::new(HashMap::new())),
+            indexes: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl SeedsRepo for SeedsRepoMock {
+    fn find(&self, user_id: UserId) -> Result<Option<Seed>, Error> {
+        let data = self.data.lock().unwrap();
+        Ok(data.get(&user_id).map(|seed| Seed {
+            owner_id: user_id.clone(),
+            master_seed: MasterSeed::new(seed.clone()),
+            created_at: SystemTime::now(),
+            updated_at: SystemTime::now(),
+        }))
+    }
+
+    fn all(&self) -> Result<Vec<Seed>, Error> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .iter()
+            .map(|(user_id, seed)| Seed {
+                owner_id: user_id.clone(),
+                master_seed: MasterSeed::new(seed.clone()),
+                created_at: SystemTime::now(),
+                updated_at: SystemTime::now(),
+            })
+            .collect())
+    }
+
+    fn create(&self, user_id: UserId, seed: MasterSeed) -> Result<Seed, Error> {
+        self.data
+            .lock()
+            .unwrap()
+            .entry(user_id.clone())
+            .or_insert_with(|| seed.as_bytes().to_vec());
+        self.find(user_id).map(|seed| seed.unwrap())
+    }
+
+    fn next_derivation_index(&self, user_id: UserId, currency: Currency, wallet_type: Option<WalletType>) -> Result<u32, Error> {
+        let mut indexes = self.indexes.lock().unwrap();
+        let next = indexes.entry((user_id, currency, derivation_purpose(wallet_type))).or_insert(0);
+        *next += 1;
+        Ok(*next - 1)
+    }
+
+    fn derivation_indexes(&self) -> Result<Vec<DerivationIndex>, Error> {
+        let indexes = self.indexes.lock().unwrap();
+        Ok(indexes
+            .iter()
+            .map(|((user_id, currency, purpose), next)| DerivationIndex {
+                owner_id: user_id.clone(),
+                currency: *currency,
+                next_index: *next as i32,
+                purpose: *purpose as i32,
+            })
+            .collect())
+    }
+
+    fn create_derivation_index(&self, index: DerivationIndex) -> Result<bool, Error> {
+        let mut indexes = self.indexes.lock().unwrap();
+        let key = (index.owner_id, index.currency, index.purpose as u32);
+        if indexes.contains_key(&key) {
+            return Ok(false);
+        }
+        indexes.insert(key, index.next_index as u32);
+        Ok(true)
+    }
 }
 
 #[derive(Clone)]
@@ -99,10 +303,22 @@ impl UsersRepo for UsersRepoMock {
             authentication_token: payload.authentication_token,
             created_at: SystemTime::now(),
             updated_at: SystemTime::now(),
+            can_export_keys: payload.can_export_keys,
+            is_admin: payload.is_admin,
         };
         data.push(res.clone());
         Ok(res)
     }
+
+    fn find(&self, user_id: UserId) -> Result<Option<User>, Error> {
+        let data = self.data.lock().unwrap();
+        Ok(data.iter().find(|x| x.id == user_id).cloned())
+    }
+
+    fn all(&self) -> Result<Vec<User>, Error> {
+        let data = self.data.lock().unwrap();
+        Ok(data.iter().cloned().collect())
+    }
 }
 
 #[derive(Clone)]
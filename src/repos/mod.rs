@@ -3,6 +3,7 @@ mod executor;
 mod keys;
 #[cfg(test)]
 mod mocks;
+mod seeds;
 mod users;
 
 pub use self::error::*;
@@ -10,4 +11,5 @@ pub use self::executor::{DbExecutor, DbExecutorImpl, Isolation};
 pub use self::keys::*;
 #[cfg(test)]
 pub use self::mocks::*;
+pub use self::seeds::*;
 pub use self::users::*;
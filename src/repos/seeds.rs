@@ -0,0 +1,169 @@
+use std::sync::Arc;
+
+use diesel;
+
+use super::error::*;
+use super::executor::with_tls_connection;
+use super::keys::{KeyEncryptionProvider, KeyRotationReport};
+use models::*;
+use prelude::*;
+use schema::derivation_indexes;
+use schema::seeds::dsl::*;
+
+pub trait SeedsRepo: Send + Sync + 'static {
+    fn find(&self, user_id: UserId) -> Result<Option<Seed>, Error>;
+    /// Returns all master seeds, decrypted
+    fn all(&self) -> Result<Vec<Seed>, Error>;
+    /// Stores master seed of the user. If the user already has a seed, the existing one is returned.
+    fn create(&self, user_id: UserId, seed: MasterSeed) -> Result<Seed, Error>;
+    /// Allocates next BIP44 address index for keys of `currency`, derived from the seed of the user.
+    /// Wallet types with different derivation purpose have separate indexes.
+    fn next_derivation_index(&self, user_id: UserId, currency: Currency, wallet_type: Option<WalletType>) -> Result<u32, Error>;
+    fn derivation_indexes(&self) -> Result<Vec<DerivationIndex>, Error>;
+    /// Stores derivation index, returns `false` if the owner already has index of the currency and purpose
+    fn create_derivation_index(&self, index: DerivationIndex) -> Result<bool, Error>;
+}
+
+/// Master seeds are stored with the same envelope encryption as private keys
+pub struct SeedsRepoImpl {
+    key_encryption_provider: Arc<KeyEncryptionProvider>,
+}
+
+impl SeedsRepoImpl {
+    pub fn new(key_encryption_provider: Arc<KeyEncryptionProvider>) -> Self {
+        SeedsRepoImpl { key_encryption_provider }
+    }
+
+    /// Re-wraps data keys of all master seeds, wrapped with `old_provider`, with the key encryption
+    /// provider of this repo. Seeds that are already wrapped with the new key are skipped.
+    /// Should be run inside a serializable transaction.
+    pub fn rotate_key_encryption_key(&self, old_provider: &KeyEncryptionProvider) -> Result<KeyRotationReport, Error> {
+        with_tls_connection(|conn| {
+            let encrypted_seeds = seeds.get_results::<EncryptedSeed>(conn).map_err(ectx!(try ErrorKind::Internal))?;
+            let mut report = KeyRotationReport::default();
+            for encrypted_seed in encrypted_seeds {
+                let associated_data = seed_associated_data(&encrypted_seed.owner_id);
+                let wrapped_key = encrypted_seed.encrypted_data_key.raw();
+                if self.key_encryption_provider.unwrap_key(wrapped_key, &associated_data).is_ok() {
+                    report.skipped += 1;
+                    continue;
+                }
+                let data_key = DataKey::new(old_provider.unwrap_key(wrapped_key, &associated_data)?);
+                let encrypted_dk = EncryptedDataKey::new(self.key_encryption_provider.wrap_key(data_key.as_bytes(), &associated_data)?);
+                let seed_owner_id = encrypted_seed.owner_id.clone();
+                diesel::update(seeds.filter(owner_id.eq(encrypted_seed.owner_id)))
+                    .set(encrypted_data_key.eq(encrypted_dk))
+                    .execute(conn)
+                    .map_err(ectx!(try ErrorKind::Internal => seed_owner_id))?;
+                report.rotated += 1;
+            }
+            Ok(report)
+        })
+    }
+
+    fn decrypt(&self, encrypted_seed: EncryptedSeed) -> Result<Seed, Error> {
+        let seed_owner_id = encrypted_seed.owner_id.clone();
+        let associated_data = seed_associated_data(&encrypted_seed.owner_id);
+        let data_key = DataKey::new(
+            self.key_encryption_provider
+                .unwrap_key(encrypted_seed.encrypted_data_key.raw(), &associated_data)?,
+        );
+        let decrypted = decrypt_key_material(encrypted_seed.master_seed.raw(), data_key.as_bytes(), &associated_data)
+            .map_err(ectx!(try ErrorContext::Decryption, ErrorKind::Internal => seed_owner_id))?;
+        Ok(Seed::from_encrypted(encrypted_seed, MasterSeed::new(decrypted)))
+    }
+}
+
+impl SeedsRepo for SeedsRepoImpl {
+    fn find(&self, user_id: UserId) -> Result<Option<Seed>, Error> {
+        let user_id_clone = user_id.clone();
+        with_tls_connection(|conn| {
+            seeds
+                .filter(owner_id.eq(user_id))
+                .get_result::<EncryptedSeed>(conn)
+                .optional()
+                .map_err(ectx!(ErrorKind::Internal => user_id_clone))
+        })
+        .and_then(|maybe_seed| match maybe_seed {
+            Some(encrypted_seed) => self.decrypt(encrypted_seed).map(Some),
+            None => Ok(None),
+        })
+    }
+
+    fn all(&self) -> Result<Vec<Seed>, Error> {
+        with_tls_connection(|conn| seeds.get_results::<EncryptedSeed>(conn).map_err(ectx!(ErrorKind::Internal)))?
+            .into_iter()
+            .map(|encrypted_seed| self.decrypt(encrypted_seed))
+            .collect()
+    }
+
+    fn create(&self, user_id: UserId, seed: MasterSeed) -> Result<Seed, Error> {
+        let associated_data = seed_associated_data(&user_id);
+        let data_key = DataKey::generate().map_err(ectx!(try ErrorContext::Encryption, ErrorKind::Internal))?;
+        let encrypted_seed = encrypt_key_material(seed.as_bytes(), data_key.as_bytes(), &associated_data)
+            .map_err(ectx!(try ErrorContext::Encryption, ErrorKind::Internal))?;
+        let encrypted_dk = self.key_encryption_provider.wrap_key(data_key.as_bytes(), &associated_data)?;
+        let payload = NewEncryptedSeed {
+            owner_id: user_id.clone(),
+            master_seed: EncryptedMasterSeed::new(encrypted_seed),
+            encrypted_data_key: EncryptedDataKey::new(encrypted_dk),
+        };
+        let user_id_clone = user_id.clone();
+        with_tls_connection(|conn| {
+            // Concurrent requests may both try to create a seed, the first one wins
+            diesel::insert_into(seeds)
+                .values(payload)
+                .on_conflict_do_nothing()
+                .execute(conn)
+                .map_err(ectx!(ErrorKind::Internal => user_id_clone))
+        })?;
+        let user_id_clone = user_id.clone();
+        self.find(user_id)?
+            .ok_or(ectx!(err ErrorContext::Encryption, ErrorKind::Internal => user_id_clone))
+    }
+
+    fn next_derivation_index(&self, user_id: UserId, currency: Currency, wallet_type: Option<WalletType>) -> Result<u32, Error> {
+        let user_id_clone = user_id.clone();
+        let purpose = derivation_purpose(wallet_type);
+        with_tls_connection(|conn| {
+            diesel::insert_into(derivation_indexes::table)
+                .values((
+                    derivation_indexes::owner_id.eq(user_id),
+                    derivation_indexes::currency.eq(currency),
+                    derivation_indexes::next_index.eq(1),
+                    derivation_indexes::purpose.eq(purpose as i32),
+                ))
+                .on_conflict((
+                    derivation_indexes::owner_id,
+                    derivation_indexes::currency,
+                    derivation_indexes::purpose,
+                ))
+                .do_update()
+                .set(derivation_indexes::next_index.eq(derivation_indexes::next_index + 1))
+                .returning(derivation_indexes::next_index)
+                .get_result::<i32>(conn)
+                .map(|next| (next - 1) as u32)
+                .map_err(ectx!(ErrorKind::Internal => user_id_clone, currency, purpose))
+        })
+    }
+
+    fn derivation_indexes(&self) -> Result<Vec<DerivationIndex>, Error> {
+        with_tls_connection(|conn| {
+            derivation_indexes::table
+                .get_results::<DerivationIndex>(conn)
+                .map_err(ectx!(ErrorKind::Internal))
+        })
+    }
+
+    fn create_derivation_index(&self, index: DerivationIndex) -> Result<bool, Error> {
+        let index_clone = index.clone();
+        with_tls_connection(|conn| {
+            diesel::insert_into(derivation_indexes::table)
+                .values(index)
+                .on_conflict_do_nothing()
+                .execute(conn)
+                .map(|inserted| inserted > 0)
+                .map_err(ectx!(ErrorKind::Internal => index_clone))
+        })
+    }
+}
@@ -10,6 +10,8 @@ pub trait UsersRepo: Send + Sync + 'static {
     fn find_user_by_authentication_token(&self, token: AuthenticationToken) -> Result<Option<User>, Error>;
     fn find_system_user(&self) -> Result<Option<User>, Error>;
     fn create(&self, payload: NewUser) -> Result<User, Error>;
+    fn find(&self, user_id: UserId) -> Result<Option<User>, Error>;
+    fn all(&self) -> Result<Vec<User>, Error>;
 }
 
 #[derive(Clone)]
@@ -38,6 +40,20 @@ impl<'a> UsersRepo for UsersRepoImpl {
         })
     }
 
+    fn find(&self, user_id: UserId) -> Result<Option<User>, Error> {
+        with_tls_connection(|conn| {
+            users
+                .filter(id.eq(user_id.clone()))
+                .get_result(conn)
+                .optional()
+                .map_err(ectx!(ErrorKind::Internal => user_id))
+        })
+    }
+
+    fn all(&self) -> Result<Vec<User>, Error> {
+        with_tls_connection(|conn| users.get_results::<User>(conn).map_err(ectx!(ErrorKind::Internal)))
+    }
+
     fn create(&self, payload: NewUser) -> Result<User, Error> {
         let payload_clone = payload.clone();
         with_tls_connection(|conn| {
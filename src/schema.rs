@@ -7,6 +7,34 @@ table! {
         owner_id -> Uuid,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        encrypted_data_key -> Nullable<Varchar>,
+        derivation_path -> Nullable<Varchar>,
+        private_key_hash -> Nullable<Varchar>,
+        status -> Varchar,
+        label -> Nullable<Varchar>,
+        metadata -> Jsonb,
+        last_used_at -> Nullable<Timestamp>,
+        signatures_count -> Int8,
+        wallet_type -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    derivation_indexes (owner_id, currency, purpose) {
+        owner_id -> Uuid,
+        currency -> Varchar,
+        next_index -> Int4,
+        purpose -> Int4,
+    }
+}
+
+table! {
+    seeds (owner_id) {
+        owner_id -> Uuid,
+        master_seed -> Varchar,
+        encrypted_data_key -> Varchar,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
     }
 }
 
@@ -17,9 +45,13 @@ table! {
         authentication_token -> Varchar,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        can_export_keys -> Bool,
+        is_admin -> Bool,
     }
 }
 
+joinable!(derivation_indexes -> users (owner_id));
 joinable!(keys -> users (owner_id));
+joinable!(seeds -> users (owner_id));
 
-allow_tables_to_appear_in_same_query!(keys, users,);
+allow_tables_to_appear_in_same_query!(derivation_indexes, keys, seeds, users,);
@@ -0,0 +1,228 @@
+use std::io::{Read, Write};
+
+use super::error::*;
+use backup::{BackupReader, BackupWriter, DerivationIndexRecord, KeyRecord, Record, SeedRecord, UserRecord};
+use blockchain::BlockchainService;
+use models::*;
+use prelude::*;
+use repos::{KeysRepo, SeedsRepo, UsersRepo};
+
+/// Number of records written to or restored from backup
+#[derive(Debug, Clone, Default)]
+pub struct BackupReport {
+    pub users: u64,
+    pub keys: u64,
+    pub seeds: u64,
+    pub derivation_indexes: u64,
+    /// Number of records, that already existed and were not restored
+    pub skipped: u64,
+}
+
+/// Writes all users, master seeds, derivation indexes and keys to backup. Private keys and seeds are decrypted,
+/// so that backup doesn't depend on the master key. Should be run inside a transaction to get a consistent snapshot.
+pub fn write_backup<W: Write>(
+    users_repo: &UsersRepo,
+    keys_repo: &KeysRepo,
+    seeds_repo: &SeedsRepo,
+    backup: &mut BackupWriter<W>,
+) -> Result<BackupReport, Error> {
+    let mut report = BackupReport::default();
+    for user in users_repo.all()? {
+        let user_id = user.id.clone();
+        backup
+            .write(Record::User(UserRecord::from_user(&user)))
+            .map_err(ectx!(try convert => user_id))?;
+        report.users += 1;
+    }
+    for seed in seeds_repo.all()? {
+        let owner_id = seed.owner_id.clone();
+        backup
+            .write(Record::Seed(SeedRecord::new(&seed)))
+            .map_err(ectx!(try convert => owner_id))?;
+        report.seeds += 1;
+    }
+    // Without indexes, keys derived after restore would reuse paths of restored keys
+    for index in seeds_repo.derivation_indexes()? {
+        let owner_id = index.owner_id.clone();
+        backup
+            .write(Record::DerivationIndex(DerivationIndexRecord::new(&index)))
+            .map_err(ectx!(try convert => owner_id))?;
+        report.derivation_indexes += 1;
+    }
+    for key in keys_repo.all()? {
+        let key_id = key.id.clone();
+        let private_key = keys_repo.decrypt_private_key(&key)?;
+        backup
+            .write(Record::Key(KeyRecord::new(&key, &private_key)))
+            .map_err(ectx!(try convert => key_id))?;
+        report.keys += 1;
+    }
+    Ok(report)
+}
+
+/// Restores users, master seeds, derivation indexes and keys from backup, existing ones are skipped.
+/// Every key is verified by deriving its address from the private key before it's stored. Should be run
+/// inside a transaction, so that a failed restore doesn't leave part of the records.
+pub fn restore_backup<R: Read>(
+    blockchain_service: &BlockchainService,
+    users_repo: &UsersRepo,
+    keys_repo: &KeysRepo,
+    seeds_repo: &SeedsRepo,
+    backup: &mut BackupReader<R>,
+) -> Result<BackupReport, Error> {
+    let mut report = BackupReport::default();
+    while let Some(record) = backup.read().map_err(ectx!(try convert))? {
+        match record {
+            Record::User(user) => {
+                if users_repo.find(user.id.clone())?.is_some() {
+                    report.skipped += 1;
+                    continue;
+                }
+                users_repo.create(user.into_new_user())?;
+                report.users += 1;
+            }
+            Record::Seed(mut seed) => {
+                if seeds_repo.find(seed.owner_id.clone())?.is_some() {
+                    report.skipped += 1;
+                    continue;
+                }
+                let owner_id = seed.owner_id.clone();
+                let master_seed = seed
+                    .take_master_seed()
+                    .ok_or(ectx!(try err ErrorContext::BackupSeed, ErrorKind::Internal => owner_id))?;
+                seeds_repo.create(seed.owner_id.clone(), master_seed)?;
+                report.seeds += 1;
+            }
+            Record::DerivationIndex(index) => {
+                if seeds_repo.create_derivation_index(index.into_derivation_index())? {
+                    report.derivation_indexes += 1;
+                } else {
+                    report.skipped += 1;
+                }
+            }
+            Record::Key(mut key) => {
+                if keys_repo.find(key.id.clone())?.is_some() {
+                    report.skipped += 1;
+                    continue;
+                }
+                let private_key = key.take_private_key();
+                let key_id = key.id.clone();
+                let currency = key.currency;
+                let wallet_type = key.wallet_type.or_else(|| WalletType::default_for(currency));
+                let address = blockchain_service
+                    .derive_address(currency, wallet_type, &private_key)
+                    .map_err(ectx!(try convert => key_id, currency))?;
+                if address != key.blockchain_address {
+                    return Err(ectx!(err ErrorContext::BackupVerification, ErrorKind::Internal => key.id, key.blockchain_address));
+                }
+                let new_key = NewKey {
+                    id: key.id.clone(),
+                    private_key,
+                    blockchain_address: address,
+                    currency,
+                    owner_id: key.owner_id.clone(),
+                    derivation_path: key.derivation_path.clone(),
+                    label: key.label.clone(),
+                    metadata: key.metadata.clone(),
+                    wallet_type,
+                };
+                keys_repo.create(new_key)?;
+                if key.status != KeyStatus::Active {
+                    keys_repo.update_status(key.id.clone(), key.status)?;
+                }
+                if let Some(created_at) = key.created_at {
+                    keys_repo.restore_usage(key.id.clone(), created_at, key.last_used_at, key.signatures_count)?;
+                }
+                report.keys += 1;
+            }
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain::*;
+    use repos::*;
+    use serde_json;
+    use std::io::Cursor;
+
+    const PASSPHRASE: &str = "correct horse battery staple";
+
+    #[test]
+    fn test_backup_restore() {
+        let blockchain_service = BlockchainServiceMock;
+        let users_repo = UsersRepoMock::new();
+        let keys_repo = KeysRepoMock::new();
+        let seeds_repo = SeedsRepoMock::new();
+        let user = users_repo.create(NewUser::default()).unwrap();
+        let master_seed = MasterSeed::generate().unwrap();
+        seeds_repo
+            .create(user.id.clone(), MasterSeed::new(master_seed.as_bytes().to_vec()))
+            .unwrap();
+        seeds_repo
+            .next_derivation_index(user.id.clone(), Currency::Btc, Some(WalletType::P2wpkh))
+            .unwrap();
+        let private_key = PrivateKey::new("e1b8c31f5ea3d7a0b5c0e8a9d8f1c5b7a2e4d6f8091a2b3c4d5e6f708192a3b4".to_string());
+        let blockchain_address = blockchain_service.derive_address(Currency::Eth, None, &private_key).unwrap();
+        let key = keys_repo
+            .create(NewKey {
+                id: KeyId::default(),
+                private_key,
+                blockchain_address,
+                currency: Currency::Eth,
+                owner_id: user.id.clone(),
+                derivation_path: None,
+                label: Some(KeyLabel::new("hot".to_string())),
+                metadata: KeyMetadata::new(serde_json::from_str(r#"{"merchant": "shop"}"#).unwrap()),
+                wallet_type: None,
+            })
+            .unwrap();
+        keys_repo.record_signature(key.id.clone()).unwrap();
+        let key = keys_repo.record_signature(key.id.clone()).unwrap();
+        keys_repo.update_status(key.id.clone(), KeyStatus::Disabled).unwrap();
+
+        let mut data = Vec::new();
+        {
+            let mut backup = BackupWriter::with_scrypt_log_n(&mut data, PASSPHRASE, 1).unwrap();
+            let report = write_backup(&users_repo, &keys_repo, &seeds_repo, &mut backup).unwrap();
+            assert_eq!((report.users, report.keys, report.seeds, report.derivation_indexes), (1, 1, 1, 1));
+            backup.finish().unwrap();
+        }
+
+        let new_users_repo = UsersRepoMock::new();
+        let new_keys_repo = KeysRepoMock::new();
+        let new_seeds_repo = SeedsRepoMock::new();
+        let mut backup = BackupReader::new(Cursor::new(&data), PASSPHRASE).unwrap();
+        let report = restore_backup(&blockchain_service, &new_users_repo, &new_keys_repo, &new_seeds_repo, &mut backup).unwrap();
+        assert_eq!((report.users, report.keys, report.skipped), (1, 1, 0));
+        assert_eq!((report.seeds, report.derivation_indexes), (1, 1));
+        let restored = new_keys_repo.find(key.id.clone()).unwrap().unwrap();
+        assert_eq!(restored.blockchain_address, key.blockchain_address);
+        assert_eq!(restored.status, KeyStatus::Disabled);
+        assert_eq!(restored.label, key.label);
+        assert_eq!(restored.metadata, key.metadata);
+        assert_eq!(restored.created_at, key.created_at);
+        assert_eq!(restored.last_used_at, key.last_used_at);
+        assert_eq!(restored.signatures_count, 2);
+        assert_eq!(
+            new_keys_repo.decrypt_private_key(&restored).unwrap().raw(),
+            keys_repo.decrypt_private_key(&key).unwrap().raw()
+        );
+        assert_eq!(new_users_repo.find(user.id.clone()).unwrap().unwrap().name, user.name);
+        let restored_seed = new_seeds_repo.find(user.id.clone()).unwrap().unwrap();
+        assert_eq!(restored_seed.master_seed, master_seed);
+        // the next key continues after keys derived before backup
+        let index = new_seeds_repo
+            .next_derivation_index(user.id.clone(), Currency::Btc, Some(WalletType::P2wpkh))
+            .unwrap();
+        assert_eq!(index, 1);
+
+        // existing records are skipped
+        let mut backup = BackupReader::new(Cursor::new(&data), PASSPHRASE).unwrap();
+        let report = restore_backup(&blockchain_service, &new_users_repo, &new_keys_repo, &new_seeds_repo, &mut backup).unwrap();
+        assert_eq!((report.users, report.keys, report.seeds, report.derivation_indexes), (0, 0, 0, 0));
+        assert_eq!(report.skipped, 4);
+    }
+}
@@ -2,8 +2,9 @@ use std::fmt;
 use std::fmt::Display;
 
 use failure::{Backtrace, Context, Fail};
-use validator::ValidationErrors;
+use validator::{ValidationError as ValidatorError, ValidationErrors};
 
+use backup::ErrorKind as BackupErrorKind;
 use blockchain::{ErrorKind as BlockchainErrorKind, ValidationError as BlockchainValidationError};
 use repos::{Error as ReposError, ErrorKind as ReposErrorKind};
 
@@ -55,12 +56,59 @@ pub enum ErrorContext {
     NoSystemUser,
     #[fail(display = "service error context - signing transaction")]
     SigningTransaction,
+    #[fail(display = "service error context - generating master seed")]
+    MasterSeed,
+    #[fail(display = "service error context - generating mnemonic")]
+    Mnemonic,
+    #[fail(display = "service error context - invalid private key")]
+    InvalidPrivateKey,
+    #[fail(display = "service error context - private key is already imported")]
+    DuplicateKey,
     #[fail(display = "service error context - currency is not supported")]
     NotSupportedCurrency,
+    #[fail(display = "service error context - user is not allowed to export keys")]
+    ExportNotAllowed,
+    #[fail(display = "service error context - user is not an admin")]
+    AdminOnly,
+    #[fail(display = "service error context - no key with this id found")]
+    NoKey,
+    #[fail(display = "service error context - exporting private key")]
+    ExportingKey,
+    #[fail(display = "service error context - archived key can't change status")]
+    KeyArchived,
+    #[fail(display = "service error context - key is not active")]
+    KeyNotActive,
+    #[fail(display = "service error context - quarantined key can only be archived")]
+    KeyQuarantined,
+    #[fail(display = "service error context - invalid key label or metadata")]
+    InvalidKeyInfo,
+    #[fail(display = "service error context - address derived from private key in backup doesn't match")]
+    BackupVerification,
+    #[fail(display = "service error context - master seed in backup is not valid hex")]
+    BackupSeed,
+    #[fail(display = "service error context - page size of keys listing is out of range")]
+    InvalidKeysLimit,
+    #[fail(display = "service error context - number of keys to recover from mnemonic is out of range")]
+    InvalidRecoverCount,
+    #[fail(display = "service error context - wallet type is not supported by currency")]
+    InvalidWalletType,
+    #[fail(display = "service error context - no key with address prefix found in allowed iterations")]
+    VanityIterations,
+    #[fail(display = "service error context - no key with address prefix found before timeout")]
+    VanityTimeout,
 }
 
 derive_error_impls!();
 
+impl ErrorKind {
+    /// Validation error of the input `field` with `code`, rendered as 422 with the same body as validator errors
+    pub fn invalid_field(field: &'static str, code: &'static str) -> Self {
+        let mut errors = ValidationErrors::new();
+        errors.add(field, ValidatorError::new(code));
+        ErrorKind::Validation(ValidationError::Validator(errors))
+    }
+}
+
 impl From<ReposError> for Error {
     fn from(e: ReposError) -> Error {
         let kind: ErrorKind = e.kind().into();
@@ -87,3 +135,12 @@ impl From<BlockchainErrorKind> for ErrorKind {
         }
     }
 }
+
+impl From<BackupErrorKind> for ErrorKind {
+    fn from(e: BackupErrorKind) -> ErrorKind {
+        match e {
+            BackupErrorKind::Internal | BackupErrorKind::Io => ErrorKind::Internal,
+            BackupErrorKind::Malformed | BackupErrorKind::Decryption => ErrorKind::MalformedInput,
+        }
+    }
+}
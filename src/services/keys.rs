@@ -2,21 +2,82 @@ use std::sync::Arc;
 
 use super::auth::AuthService;
 use super::error::*;
+use super::seeds::find_or_create_seed;
 use super::ServiceFuture;
 use blockchain::BlockchainService;
+use config::KeyGeneration;
 use models::*;
 use prelude::*;
-use repos::{DbExecutor, KeysRepo};
+use repos::{DbExecutor, KeysRepo, SeedsRepo, UsersRepo};
+
+const MAX_LABEL_LENGTH: usize = 64;
+/// Page size of keys listing, if not set in request
+const DEFAULT_KEYS_LIMIT: i64 = 50;
+pub const MAX_KEYS_LIMIT: i64 = 1000;
 
 pub trait KeysService: Send + Sync + 'static {
-    fn list(&self, maybe_token: Option<AuthenticationToken>, user_id: UserId, offset: i64, limit: i64) -> ServiceFuture<Vec<Key>>;
-    fn create(&self, maybe_token: Option<AuthenticationToken>, user_id: UserId, currency: Currency, id: KeyId) -> ServiceFuture<Key>;
+    /// Lists keys in `(created_at, id)` order, starting after the cursor
+    fn list(
+        &self,
+        maybe_token: Option<AuthenticationToken>,
+        user_id: UserId,
+        filter: KeysFilter,
+        cursor: Option<KeysCursor>,
+        limit: Option<i64>,
+    ) -> ServiceFuture<KeysPage>;
+    fn get(&self, maybe_token: Option<AuthenticationToken>, user_id: UserId, key_id: KeyId) -> ServiceFuture<Key>;
+    /// Returns key of the authenticated user by its address, e.g. to resolve deposit address to a wallet.
+    /// Ethereum addresses are matched regardless of case and `0x` prefix.
+    fn find_by_address(
+        &self,
+        maybe_token: Option<AuthenticationToken>,
+        currency: Currency,
+        address: BlockchainAddress,
+    ) -> ServiceFuture<Key>;
+    /// `wallet_type` can be set only for bitcoin keys, it's P2PKH if not set
+    fn create(
+        &self,
+        maybe_token: Option<AuthenticationToken>,
+        user_id: UserId,
+        currency: Currency,
+        wallet_type: Option<WalletType>,
+        id: KeyId,
+        label: Option<KeyLabel>,
+        metadata: KeyMetadata,
+    ) -> ServiceFuture<Key>;
+    /// Stores existing private key, the same key can't be imported twice
+    fn import(
+        &self,
+        maybe_token: Option<AuthenticationToken>,
+        user_id: UserId,
+        currency: Currency,
+        wallet_type: Option<WalletType>,
+        id: KeyId,
+        private_key: RawPrivateKey,
+        label: Option<KeyLabel>,
+        metadata: KeyMetadata,
+    ) -> ServiceFuture<Key>;
+    /// Returns private key of any user encrypted with ECIES to `recipient` public key.
+    /// Allowed only for users with `can_export_keys` flag, every export is logged.
+    fn export(
+        &self,
+        maybe_token: Option<AuthenticationToken>,
+        key_id: KeyId,
+        recipient: RecipientPublicKey,
+    ) -> ServiceFuture<(Key, ExportedPrivateKey)>;
+    /// Changes status, label and metadata of the key, archived keys can't be reactivated
+    fn update(&self, maybe_token: Option<AuthenticationToken>, user_id: UserId, key_id: KeyId, changes: KeyUpdate) -> ServiceFuture<Key>;
+    /// Archives the key, the row is kept so that the address can't be reused
+    fn delete(&self, maybe_token: Option<AuthenticationToken>, user_id: UserId, key_id: KeyId) -> ServiceFuture<Key>;
 }
 
 pub struct KeysServiceImpl<E: DbExecutor> {
     auth_service: Arc<AuthService>,
     blockchain_service: Arc<BlockchainService>,
     keys_repo: Arc<KeysRepo>,
+    seeds_repo: Arc<SeedsRepo>,
+    users_repo: Arc<UsersRepo>,
+    key_generation: KeyGeneration,
     db_executor: E,
 }
 
@@ -25,46 +86,139 @@ impl<E: DbExecutor> KeysServiceImpl<E> {
         auth_service: Arc<AuthService>,
         blockchain_service: Arc<BlockchainService>,
         keys_repo: Arc<KeysRepo>,
+        seeds_repo: Arc<SeedsRepo>,
+        users_repo: Arc<UsersRepo>,
+        key_generation: KeyGeneration,
         db_executor: E,
     ) -> Self {
         Self {
             auth_service,
             blockchain_service,
             keys_repo,
+            seeds_repo,
+            users_repo,
+            key_generation,
             db_executor,
         }
     }
 }
 
 impl<E: DbExecutor> KeysService for KeysServiceImpl<E> {
-    fn list(&self, maybe_token: Option<AuthenticationToken>, user_id: UserId, offset: i64, limit: i64) -> ServiceFuture<Vec<Key>> {
+    fn list(
+        &self,
+        maybe_token: Option<AuthenticationToken>,
+        user_id: UserId,
+        filter: KeysFilter,
+        cursor: Option<KeysCursor>,
+        limit: Option<i64>,
+    ) -> ServiceFuture<KeysPage> {
         let db_executor = self.db_executor.clone();
         let keys_repo = self.keys_repo.clone();
+        let limit = limit.unwrap_or(DEFAULT_KEYS_LIMIT);
         Box::new(
             self.auth_service
                 .authenticate(maybe_token)
                 .and_then(move |user| {
                     if user.id != user_id {
                         Err(ectx!(err ErrorContext::NotOwnResources, ErrorKind::Unauthorized => user.id, user_id))
+                    } else if limit < 1 || limit > MAX_KEYS_LIMIT {
+                        Err(ectx!(err ErrorContext::InvalidKeysLimit, ErrorKind::invalid_field("limit", "range") => limit))
                     } else {
                         Ok(user)
                     }
                 })
                 .and_then(move |user| {
                     let user_id = user.id.clone();
-                    let user_id_clone = user_id.clone();
                     db_executor.execute_transaction(move || {
+                        let user_id_clone = user_id.clone();
+                        let filter_clone = filter.clone();
+                        let cursor_clone = cursor.clone();
+                        // one more key tells if there's the next page
+                        let mut keys = keys_repo
+                            .list(user_id.clone(), filter.clone(), cursor, limit + 1)
+                            .map_err(ectx!(try ErrorKind::Internal => user_id_clone, filter_clone, cursor_clone, limit))?;
+                        let next_cursor = if keys.len() as i64 > limit {
+                            keys.truncate(limit as usize);
+                            keys.last().map(KeysCursor::new)
+                        } else {
+                            None
+                        };
+                        let user_id_clone = user_id.clone();
+                        let filter_clone = filter.clone();
+                        let total = keys_repo
+                            .count(user_id, filter)
+                            .map_err(ectx!(try ErrorKind::Internal => user_id_clone, filter_clone))?;
+                        Ok(KeysPage { keys, total, next_cursor })
+                    })
+                }),
+        )
+    }
+
+    fn get(&self, maybe_token: Option<AuthenticationToken>, user_id: UserId, key_id: KeyId) -> ServiceFuture<Key> {
+        let db_executor = self.db_executor.clone();
+        let keys_repo = self.keys_repo.clone();
+        Box::new(
+            self.auth_service
+                .authenticate(maybe_token)
+                .and_then(move |user| {
+                    if user.id != user_id {
+                        Err(ectx!(err ErrorContext::NotOwnResources, ErrorKind::Unauthorized => user.id, user_id))
+                    } else {
+                        Ok(user)
+                    }
+                })
+                .and_then(move |user| {
+                    let owner_id = user.id;
+                    db_executor.execute(move || {
+                        let key_id_clone = key_id.clone();
                         keys_repo
-                            .list(user_id, offset, limit)
-                            .map_err(ectx!(ErrorKind::Internal => user_id_clone, offset, limit))
+                            .find(key_id.clone())
+                            .map_err(ectx!(try convert => key_id_clone))?
+                            .filter(|key| key.owner_id == owner_id)
+                            .ok_or(ectx!(err ErrorContext::NoKey, ErrorKind::NotFound => owner_id, key_id))
                     })
                 }),
         )
     }
 
-    fn create(&self, maybe_token: Option<AuthenticationToken>, user_id: UserId, currency: Currency, id: KeyId) -> ServiceFuture<Key> {
+    fn find_by_address(
+        &self,
+        maybe_token: Option<AuthenticationToken>,
+        currency: Currency,
+        address: BlockchainAddress,
+    ) -> ServiceFuture<Key> {
         let db_executor = self.db_executor.clone();
         let keys_repo = self.keys_repo.clone();
+        let address = normalize_address(currency, address);
+        Box::new(self.auth_service.authenticate(maybe_token).and_then(move |user| {
+            let owner_id = user.id;
+            db_executor.execute(move || {
+                let address_clone = address.clone();
+                // Keys of other users are reported as missing, so that their addresses can't be probed
+                keys_repo
+                    .find_by_address(owner_id.clone(), address.clone(), Some(currency))
+                    .map_err(ectx!(try convert => address_clone, currency))?
+                    .filter(|key| key.owner_id == owner_id)
+                    .ok_or(ectx!(err ErrorContext::NoWallet, ErrorKind::NotFound => owner_id, address, currency))
+            })
+        }))
+    }
+
+    fn create(
+        &self,
+        maybe_token: Option<AuthenticationToken>,
+        user_id: UserId,
+        currency: Currency,
+        wallet_type: Option<WalletType>,
+        id: KeyId,
+        label: Option<KeyLabel>,
+        metadata: KeyMetadata,
+    ) -> ServiceFuture<Key> {
+        let db_executor = self.db_executor.clone();
+        let keys_repo = self.keys_repo.clone();
+        let seeds_repo = self.seeds_repo.clone();
+        let users_repo = self.users_repo.clone();
+        let key_generation = self.key_generation;
         let id_clone = id.clone();
         let blockchain_service = self.blockchain_service.clone();
         Box::new(
@@ -78,18 +232,106 @@ impl<E: DbExecutor> KeysService for KeysServiceImpl<E> {
                     }
                 })
                 .and_then(move |user| {
+                    let owner_id = user.id;
+                    let owner_id_clone = owner_id.clone();
+                    db_executor.execute_transaction(move || {
+                        validate_key_info(label.as_ref(), Some(&metadata))?;
+                        let wallet_type = key_wallet_type(currency, wallet_type)?;
+                        let (private_key, blockchain_address, derivation_path) = generate_key(
+                            &*blockchain_service,
+                            &*seeds_repo,
+                            &*users_repo,
+                            key_generation,
+                            owner_id.clone(),
+                            currency,
+                            wallet_type,
+                        )?;
+                        let new_key = NewKey {
+                            id,
+                            currency,
+                            owner_id,
+                            private_key,
+                            blockchain_address,
+                            derivation_path,
+                            label,
+                            metadata,
+                            wallet_type,
+                        };
+                        keys_repo
+                            .create(new_key)
+                            .map_err(ectx!(convert => owner_id_clone, currency, id_clone))
+                    })
+                }),
+        )
+    }
+
+    fn import(
+        &self,
+        maybe_token: Option<AuthenticationToken>,
+        user_id: UserId,
+        currency: Currency,
+        wallet_type: Option<WalletType>,
+        id: KeyId,
+        private_key: RawPrivateKey,
+        label: Option<KeyLabel>,
+        metadata: KeyMetadata,
+    ) -> ServiceFuture<Key> {
+        let db_executor = self.db_executor.clone();
+        let keys_repo = self.keys_repo.clone();
+        let blockchain_service = self.blockchain_service.clone();
+        let id_clone = id.clone();
+        Box::new(
+            self.auth_service
+                .authenticate(maybe_token)
+                .and_then(move |user| {
+                    if user.id != user_id {
+                        Err(ectx!(err ErrorContext::NotOwnResources, ErrorKind::Unauthorized => user.id, user_id))
+                    } else {
+                        Ok(user)
+                    }
+                })
+                .and_then(move |user| {
+                    let wallet_type = key_wallet_type(currency, wallet_type)?;
+                    // Blockchain errors may contain the key, so they are not kept as the cause
+                    let private_key = blockchain_service.parse_private_key(currency, private_key).map_err(
+                        |_| ectx!(try err ErrorContext::InvalidPrivateKey, ErrorKind::invalid_field("privateKey", "invalid") => currency),
+                    )?;
+                    let blockchain_address = blockchain_service.derive_address(currency, wallet_type, &private_key).map_err(
+                        |_| ectx!(try err ErrorContext::InvalidPrivateKey, ErrorKind::invalid_field("privateKey", "invalid") => currency),
+                    )?;
+                    Ok((user, wallet_type, private_key, blockchain_address))
+                })
+                .and_then(move |(user, wallet_type, private_key, blockchain_address)| {
                     let owner_id = user.id;
                     let owner_id_clone = owner_id.clone();
                     db_executor.execute(move || {
-                        let (private_key, blockchain_address) =
-                            blockchain_service.generate_key(currency).map_err(ectx!(try convert => currency))?;
+                        validate_key_info(label.as_ref(), Some(&metadata))?;
+                        // Address is not enough to find the same key, e.g. if it was imported with another wallet type
+                        let owner_id_clone2 = owner_id_clone.clone();
+                        let existing_key = keys_repo
+                            .find_by_private_key(&private_key, currency)
+                            .map_err(ectx!(try convert => owner_id_clone2, currency))?;
+                        if existing_key.is_some() {
+                            let owner_id_clone = owner_id_clone.clone();
+                            return Err(ectx!(
+                                err ErrorContext::DuplicateKey,
+                                ErrorKind::invalid_field("privateKey", "exists") =>
+                                owner_id_clone,
+                                currency
+                            ));
+                        }
                         let new_key = NewKey {
                             id,
                             currency,
                             owner_id,
                             private_key,
                             blockchain_address,
+                            derivation_path: None,
+                            label,
+                            metadata,
+                            wallet_type,
                         };
+                        // Concurrent imports are rejected by unique index on private key hash and currency
                         keys_repo
                             .create(new_key)
                             .map_err(ectx!(convert => owner_id_clone, currency, id_clone))
@@ -97,15 +339,222 @@ impl<E: DbExecutor> KeysService for KeysServiceImpl<E> {
                 }),
         )
     }
+
+    fn export(
+        &self,
+        maybe_token: Option<AuthenticationToken>,
+        key_id: KeyId,
+        recipient: RecipientPublicKey,
+    ) -> ServiceFuture<(Key, ExportedPrivateKey)> {
+        let db_executor = self.db_executor.clone();
+        let keys_repo = self.keys_repo.clone();
+        let blockchain_service = self.blockchain_service.clone();
+        Box::new(
+            self.auth_service
+                .authenticate(maybe_token)
+                .and_then(move |user| {
+                    if !user.can_export_keys {
+                        warn!("User {:?} is not allowed to export keys, tried to export key {:?}", user.id, key_id);
+                        return Err(ectx!(err ErrorContext::ExportNotAllowed, ErrorKind::Unauthorized => user.id, key_id));
+                    }
+                    let recipient_clone = recipient.clone();
+                    recipient.to_public().map_err(
+                        ectx!(try ErrorContext::ExportingKey, ErrorKind::invalid_field("publicKey", "invalid") => recipient_clone),
+                    )?;
+                    Ok((user, key_id, recipient))
+                })
+                .and_then(move |(user, key_id, recipient)| {
+                    db_executor.execute(move || {
+                        let key_id_clone = key_id.clone();
+                        let key = keys_repo
+                            .find(key_id.clone())
+                            .map_err(ectx!(try convert => key_id_clone))?
+                            .ok_or(ectx!(try err ErrorContext::NoKey, ErrorKind::NotFound => key_id))?;
+                        let key_id = key.id.clone();
+                        let private_key = keys_repo.decrypt_private_key(&key).map_err(ectx!(try convert => key_id))?;
+                        let key_id = key.id.clone();
+                        let private_key = blockchain_service
+                            .export_private_key(key.currency, &private_key)
+                            .map_err(ectx!(try convert => key_id))?;
+                        let key_id = key.id.clone();
+                        // Encryption errors may contain the key, so they are not kept as the cause
+                        let exported = private_key
+                            .export(&recipient)
+                            .map_err(|_| ectx!(try err ErrorContext::ExportingKey, ErrorKind::Internal => key_id))?;
+                        info!(
+                            "User {:?} exported key {:?} of user {:?} ({:?}, {:?}) to public key {}",
+                            user.id,
+                            key.id,
+                            key.owner_id,
+                            key.currency,
+                            key.blockchain_address,
+                            recipient.raw()
+                        );
+                        Ok((key, exported))
+                    })
+                }),
+        )
+    }
+
+    fn update(&self, maybe_token: Option<AuthenticationToken>, user_id: UserId, key_id: KeyId, changes: KeyUpdate) -> ServiceFuture<Key> {
+        let db_executor = self.db_executor.clone();
+        let keys_repo = self.keys_repo.clone();
+        Box::new(
+            self.auth_service
+                .authenticate(maybe_token)
+                .and_then(move |user| {
+                    if user.id != user_id {
+                        Err(ectx!(err ErrorContext::NotOwnResources, ErrorKind::Unauthorized => user.id, user_id))
+                    } else {
+                        Ok(user)
+                    }
+                })
+                .and_then(move |user| {
+                    let owner_id = user.id;
+                    db_executor.execute_transaction(move || {
+                        let key_id_clone = key_id.clone();
+                        let key = keys_repo
+                            .find(key_id.clone())
+                            .map_err(ectx!(try convert => key_id_clone))?
+                            .filter(|key| key.owner_id == owner_id)
+                            .ok_or(ectx!(try err ErrorContext::NoKey, ErrorKind::NotFound => owner_id, key_id))?;
+                        validate_key_info(changes.label.as_ref(), changes.metadata.as_ref())?;
+                        if let Some(status) = changes.status {
+                            if key.status == KeyStatus::Archived && status != KeyStatus::Archived {
+                                return Err(
+                                    ectx!(err ErrorContext::KeyArchived, ErrorKind::invalid_field("status", "archived") => key_id, status),
+                                );
+                            }
+                            // Keys are quarantined only by audit, and stay quarantined until they are archived
+                            if (key.status == KeyStatus::Quarantined) != (status == KeyStatus::Quarantined) && status != KeyStatus::Archived {
+                                return Err(
+                                    ectx!(err ErrorContext::KeyQuarantined, ErrorKind::invalid_field("status", "quarantined") => key_id, status),
+                                );
+                            }
+                        }
+                        let changes_clone = changes.clone();
+                        keys_repo
+                            .update(key_id.clone(), changes)
+                            .map_err(ectx!(convert => key_id, changes_clone))
+                    })
+                }),
+        )
+    }
+
+    fn delete(&self, maybe_token: Option<AuthenticationToken>, user_id: UserId, key_id: KeyId) -> ServiceFuture<Key> {
+        let changes = KeyUpdate {
+            status: Some(KeyStatus::Archived),
+            ..Default::default()
+        };
+        self.update(maybe_token, user_id, key_id, changes)
+    }
+}
+
+/// Ethereum addresses are stored as lowercase hex without `0x`, but usually come checksummed or prefixed
+fn normalize_address(currency: Currency, address: BlockchainAddress) -> BlockchainAddress {
+    match currency {
+        Currency::Eth | Currency::Stq => {
+            let address = address.into_inner().to_lowercase();
+            let address = address.trim_start_matches("0x").to_string();
+            BlockchainAddress::new(address)
+        }
+        Currency::Btc => address,
+    }
+}
+
+/// Checks label and metadata supplied by user, `None` is valid
+fn validate_key_info(label: Option<&KeyLabel>, metadata: Option<&KeyMetadata>) -> Result<(), Error> {
+    if let Some(label) = label {
+        let length = label.raw().chars().count();
+        if length == 0 || length > MAX_LABEL_LENGTH {
+            return Err(ectx!(err ErrorContext::InvalidKeyInfo, ErrorKind::invalid_field("label", "length") => length));
+        }
+    }
+    if let Some(metadata) = metadata {
+        if !metadata.is_object() {
+            return Err(ectx!(err ErrorContext::InvalidKeyInfo, ErrorKind::invalid_field("metadata", "not_object")));
+        }
+    }
+    Ok(())
+}
+
+/// Wallet type of a new key, only bitcoin keys have it
+pub fn key_wallet_type(currency: Currency, wallet_type: Option<WalletType>) -> Result<Option<WalletType>, Error> {
+    match (WalletType::default_for(currency), wallet_type) {
+        (None, Some(wallet_type)) => Err(
+            ectx!(err ErrorContext::InvalidWalletType, ErrorKind::invalid_field("walletType", "not_supported") => currency, wallet_type),
+        ),
+        (default, wallet_type) => Ok(wallet_type.or(default)),
+    }
+}
+
+/// Generates random key or derives the next key of the currency from master seed, depending on `key_generation`
+fn generate_key(
+    blockchain_service: &BlockchainService,
+    seeds_repo: &SeedsRepo,
+    users_repo: &UsersRepo,
+    key_generation: KeyGeneration,
+    owner_id: UserId,
+    currency: Currency,
+    wallet_type: Option<WalletType>,
+) -> Result<(PrivateKey, BlockchainAddress, Option<DerivationPath>), Error> {
+    let seed_owner_id = match key_generation {
+        KeyGeneration::Random => {
+            let (private_key, blockchain_address) = blockchain_service
+                .generate_key(currency, wallet_type)
+                .map_err(ectx!(try convert => currency))?;
+            return Ok((private_key, blockchain_address, None));
+        }
+        KeyGeneration::UserSeed => owner_id,
+        KeyGeneration::SystemSeed => {
+            users_repo
+                .find_system_user()?
+                .ok_or(ectx!(try err ErrorContext::NoSystemUser, ErrorKind::Internal))?
+                .id
+        }
+    };
+    let seed = find_or_create_seed(seeds_repo, seed_owner_id.clone())?;
+    let index = seeds_repo.next_derivation_index(seed_owner_id, currency, wallet_type)?;
+    let path = blockchain_service.account_derivation_path(currency, wallet_type).address(index);
+    let path_clone = path.clone();
+    let (private_key, blockchain_address) = blockchain_service
+        .derive_key(currency, wallet_type, &seed.master_seed, &path)
+        .map_err(ectx!(try convert => currency, path_clone))?;
+    Ok((private_key, blockchain_address, Some(path)))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use blockchain::*;
+    use ethkey::crypto::ecies;
+    use ethkey::{Generator, Random};
     use repos::*;
+    use serde_json;
     use services::*;
+    // blockchain and repos errors have the same names
+    use services::ErrorKind;
     use tokio_core::reactor::Core;
+    use utils::{decode_hex, encode_hex};
+
+    fn create_service(auth_service: Arc<AuthServiceMock>) -> KeysServiceImpl<DbExecutorMock> {
+        KeysServiceImpl::new(
+            auth_service,
+            Arc::new(BlockchainServiceMock),
+            Arc::new(KeysRepoMock::new()),
+            Arc::new(SeedsRepoMock::new()),
+            Arc::new(UsersRepoMock::new()),
+            KeyGeneration::Random,
+            DbExecutorMock::new(),
+        )
+    }
+
+    fn status_update(status: KeyStatus) -> KeyUpdate {
+        KeyUpdate {
+            status: Some(status),
+            ..Default::default()
+        }
+    }
 
     #[test]
     fn test_create() {
@@ -116,19 +565,39 @@ mod tests {
         let blockchain_service = Arc::new(BlockchainServiceMock);
         let keys_repo = Arc::new(KeysRepoMock::new());
         let db_executor = DbExecutorMock::new();
-        let keys_service = KeysServiceImpl::new(auth_service, blockchain_service, keys_repo, db_executor);
+        let keys_service = KeysServiceImpl::new(
+            auth_service,
+            blockchain_service,
+            keys_repo,
+            Arc::new(SeedsRepoMock::new()),
+            Arc::new(UsersRepoMock::new()),
+            KeyGeneration::Random,
+            db_executor,
+        );
         let mut core = Core::new().unwrap();
 
         // creates with right token
         let keys_count = core
-            .run(keys_service.list(Some(token.clone()), user_id.clone(), 0, 100))
+            .run(keys_service.list(Some(token.clone()), user_id.clone(), KeysFilter::default(), None, None))
             .unwrap()
+            .keys
             .len();
         assert_eq!(keys_count, 0);
         let key_id = KeyId::default();
-        let res = core.run(keys_service.create(Some(token.clone()), user_id.clone(), Currency::Eth, key_id.clone()));
+        let res = core.run(keys_service.create(
+            Some(token.clone()),
+            user_id.clone(),
+            Currency::Eth,
+            None,
+            key_id.clone(),
+            None,
+            KeyMetadata::default(),
+        ));
         assert_eq!(res.is_ok(), true);
-        let keys = core.run(keys_service.list(Some(token.clone()), user_id.clone(), 0, 100)).unwrap();
+        let keys = core
+            .run(keys_service.list(Some(token.clone()), user_id.clone(), KeysFilter::default(), None, None))
+            .unwrap()
+            .keys;
         assert_eq!(keys[0].id, key_id.clone());
         assert_eq!(keys.len(), 1);
 
@@ -137,33 +606,112 @@ mod tests {
         let auth_token2 = new_user2.authentication_token.clone();
         let user_id2 = new_user2.id.clone();
         let key_id = KeyId::default();
-        let res = core.run(keys_service.create(Some(auth_token2.clone()), user_id2.clone(), Currency::Eth, key_id.clone()));
+        let res = core.run(keys_service.create(
+            Some(auth_token2.clone()),
+            user_id2.clone(),
+            Currency::Eth,
+            None,
+            key_id.clone(),
+            None,
+            KeyMetadata::default(),
+        ));
         assert_eq!(res.is_err(), true);
         let keys_count = core
-            .run(keys_service.list(Some(token.clone()), user_id.clone(), 0, 100))
+            .run(keys_service.list(Some(token.clone()), user_id.clone(), KeysFilter::default(), None, None))
             .unwrap()
+            .keys
             .len();
         assert_eq!(keys_count, 1);
 
         // doesn't create with no token
-        let res = core.run(keys_service.create(None, user_id.clone(), Currency::Eth, key_id.clone()));
+        let res = core.run(keys_service.create(
+            None,
+            user_id.clone(),
+            Currency::Eth,
+            None,
+            key_id.clone(),
+            None,
+            KeyMetadata::default(),
+        ));
         assert_eq!(res.is_err(), true);
         let keys_count = core
-            .run(keys_service.list(Some(token.clone()), user_id.clone(), 0, 100))
+            .run(keys_service.list(Some(token.clone()), user_id.clone(), KeysFilter::default(), None, None))
             .unwrap()
+            .keys
             .len();
         assert_eq!(keys_count, 1);
 
         // doesn't create with token user id not equal to user id
-        let res = core.run(keys_service.create(Some(token.clone()), UserId::default(), Currency::Eth, key_id.clone()));
+        let res = core.run(keys_service.create(
+            Some(token.clone()),
+            UserId::default(),
+            Currency::Eth,
+            None,
+            key_id.clone(),
+            None,
+            KeyMetadata::default(),
+        ));
         assert_eq!(res.is_err(), true);
         let keys_count = core
-            .run(keys_service.list(Some(token.clone()), user_id.clone(), 0, 100))
+            .run(keys_service.list(Some(token.clone()), user_id.clone(), KeysFilter::default(), None, None))
             .unwrap()
+            .keys
             .len();
         assert_eq!(keys_count, 1);
     }
 
+    #[test]
+    fn test_create_wallet_types() {
+        let new_user = NewUser::default();
+        let user_id = new_user.id.clone();
+        let token = new_user.authentication_token.clone();
+        let auth_service = Arc::new(AuthServiceMock::new(vec![(token.clone(), user_id.clone())]));
+        let keys_service = create_service(auth_service);
+        let mut core = Core::new().unwrap();
+
+        // bitcoin keys are P2PKH unless wallet type is set, other currencies don't have it
+        let btc_key = |wallet_type| {
+            keys_service.create(
+                Some(token.clone()),
+                user_id.clone(),
+                Currency::Btc,
+                wallet_type,
+                KeyId::default(),
+                None,
+                KeyMetadata::default(),
+            )
+        };
+        let key = core.run(btc_key(None)).unwrap();
+        assert_eq!(key.wallet_type, Some(WalletType::P2pkh));
+        let key = core.run(btc_key(Some(WalletType::P2wpkh))).unwrap();
+        assert_eq!(key.wallet_type, Some(WalletType::P2wpkh));
+        let key = core
+            .run(keys_service.create(
+                Some(token.clone()),
+                user_id.clone(),
+                Currency::Eth,
+                None,
+                KeyId::default(),
+                None,
+                KeyMetadata::default(),
+            ))
+            .unwrap();
+        assert_eq!(key.wallet_type, None);
+        let res = core.run(keys_service.create(
+            Some(token.clone()),
+            user_id.clone(),
+            Currency::Eth,
+            Some(WalletType::P2wpkh),
+            KeyId::default(),
+            None,
+            KeyMetadata::default(),
+        ));
+        match res.unwrap_err().kind() {
+            ErrorKind::Validation(_) => (),
+            kind => panic!("Unexpected error {:?}", kind),
+        }
+    }
+
     #[test]
     fn test_list() {
         let new_user = NewUser::default();
@@ -173,19 +721,39 @@ mod tests {
         let blockchain_service = Arc::new(BlockchainServiceMock);
         let keys_repo = Arc::new(KeysRepoMock::new());
         let db_executor = DbExecutorMock::new();
-        let keys_service = KeysServiceImpl::new(auth_service, blockchain_service, keys_repo, db_executor);
+        let keys_service = KeysServiceImpl::new(
+            auth_service,
+            blockchain_service,
+            keys_repo,
+            Arc::new(SeedsRepoMock::new()),
+            Arc::new(UsersRepoMock::new()),
+            KeyGeneration::Random,
+            db_executor,
+        );
         let mut core = Core::new().unwrap();
 
         // lists with right token
         let keys_count = core
-            .run(keys_service.list(Some(token.clone()), user_id.clone(), 0, 100))
+            .run(keys_service.list(Some(token.clone()), user_id.clone(), KeysFilter::default(), None, None))
             .unwrap()
+            .keys
             .len();
         assert_eq!(keys_count, 0);
         let key_id = KeyId::default();
-        let res = core.run(keys_service.create(Some(token.clone()), user_id.clone(), Currency::Eth, key_id.clone()));
+        let res = core.run(keys_service.create(
+            Some(token.clone()),
+            user_id.clone(),
+            Currency::Eth,
+            None,
+            key_id.clone(),
+            None,
+            KeyMetadata::default(),
+        ));
         assert_eq!(res.is_ok(), true);
-        let keys = core.run(keys_service.list(Some(token.clone()), user_id.clone(), 0, 100)).unwrap();
+        let keys = core
+            .run(keys_service.list(Some(token.clone()), user_id.clone(), KeysFilter::default(), None, None))
+            .unwrap()
+            .keys;
         assert_eq!(keys[0].id, key_id.clone());
         assert_eq!(keys.len(), 1);
 
@@ -193,11 +761,630 @@ mod tests {
         let new_user2 = NewUser::default();
         let auth_token2 = new_user2.authentication_token.clone();
         let user_id2 = new_user2.id.clone();
-        let res = core.run(keys_service.list(Some(auth_token2.clone()), user_id2.clone(), 0, 100));
+        let res = core.run(keys_service.list(Some(auth_token2.clone()), user_id2.clone(), KeysFilter::default(), None, None));
         assert_eq!(res.is_err(), true);
 
         // doesn't list with no token
-        let res = core.run(keys_service.list(None, user_id.clone(), 0, 100));
+        let res = core.run(keys_service.list(None, user_id.clone(), KeysFilter::default(), None, None));
         assert_eq!(res.is_err(), true);
     }
+
+    #[test]
+    fn test_import() {
+        let new_user = NewUser::default();
+        let user_id = new_user.id.clone();
+        let token = new_user.authentication_token.clone();
+        let auth_service = Arc::new(AuthServiceMock::new(vec![(token.clone(), user_id.clone())]));
+        let keys_repo = Arc::new(KeysRepoMock::new());
+        let keys_service = KeysServiceImpl::new(
+            auth_service,
+            Arc::new(BlockchainServiceMock),
+            keys_repo.clone(),
+            Arc::new(SeedsRepoMock::new()),
+            Arc::new(UsersRepoMock::new()),
+            KeyGeneration::Random,
+            DbExecutorMock::new(),
+        );
+        let mut core = Core::new().unwrap();
+        let private_key = RawPrivateKey::new("b3c0e85a511cc6d21423a386de29dcf2cda6b2f2fa5ebb47948401bbb90458db".to_string());
+
+        let key = core
+            .run(keys_service.import(
+                Some(token.clone()),
+                user_id.clone(),
+                Currency::Eth,
+                None,
+                KeyId::default(),
+                private_key.clone(),
+                None,
+                KeyMetadata::default(),
+            ))
+            .unwrap();
+        assert_eq!(keys_repo.decrypt_private_key(&key).unwrap().raw(), private_key.raw());
+        assert_eq!(key.derivation_path, None);
+
+        // doesn't import the same key twice
+        let res = core.run(keys_service.import(
+            Some(token.clone()),
+            user_id.clone(),
+            Currency::Eth,
+            None,
+            KeyId::default(),
+            private_key.clone(),
+            None,
+            KeyMetadata::default(),
+        ));
+        match res.unwrap_err().kind() {
+            ErrorKind::Validation(_) => (),
+            kind => panic!("Unexpected error {:?}", kind),
+        }
+
+        // doesn't import invalid key
+        let res = core.run(keys_service.import(
+            Some(token.clone()),
+            user_id.clone(),
+            Currency::Eth,
+            None,
+            KeyId::default(),
+            RawPrivateKey::new(String::new()),
+            None,
+            KeyMetadata::default(),
+        ));
+        match res.unwrap_err().kind() {
+            ErrorKind::Validation(_) => (),
+            kind => panic!("Unexpected error {:?}", kind),
+        }
+
+        // doesn't import for other users
+        let res = core.run(keys_service.import(
+            Some(token.clone()),
+            UserId::default(),
+            Currency::Eth,
+            None,
+            KeyId::default(),
+            private_key,
+            None,
+            KeyMetadata::default(),
+        ));
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_export() {
+        let owner = User::default();
+        let mut exporter = User::default();
+        exporter.can_export_keys = true;
+        let auth_service = Arc::new(AuthServiceMock::from_users(vec![owner.clone(), exporter.clone()]));
+        let keys_service = create_service(auth_service);
+        let mut core = Core::new().unwrap();
+        let private_key = "b3c0e85a511cc6d21423a386de29dcf2cda6b2f2fa5ebb47948401bbb90458db".to_string();
+        let key = core
+            .run(keys_service.import(
+                Some(owner.authentication_token.clone()),
+                owner.id.clone(),
+                Currency::Eth,
+                None,
+                KeyId::default(),
+                RawPrivateKey::new(private_key.clone()),
+                None,
+                KeyMetadata::default(),
+            ))
+            .unwrap();
+        let recipient = Random.generate().unwrap();
+        let public_key = RecipientPublicKey::new(format!("{:x}", recipient.public()));
+
+        let (exported_key, exported) = core
+            .run(keys_service.export(Some(exporter.authentication_token.clone()), key.id.clone(), public_key.clone()))
+            .unwrap();
+        assert_eq!(exported_key.id, key.id);
+        let decrypted = ecies::decrypt(recipient.secret(), &[], &decode_hex(exported.raw()).unwrap()).unwrap();
+        assert_eq!(encode_hex(&decrypted), private_key);
+
+        // only flagged users can export
+        let res = core.run(keys_service.export(Some(owner.authentication_token.clone()), key.id.clone(), public_key.clone()));
+        match res.unwrap_err().kind() {
+            ErrorKind::Unauthorized => (),
+            kind => panic!("Unexpected error {:?}", kind),
+        }
+
+        // invalid public key
+        let res = core.run(keys_service.export(
+            Some(exporter.authentication_token.clone()),
+            key.id.clone(),
+            RecipientPublicKey::new("04b3c0e85a".to_string()),
+        ));
+        match res.unwrap_err().kind() {
+            ErrorKind::Validation(_) => (),
+            kind => panic!("Unexpected error {:?}", kind),
+        }
+
+        // unknown key
+        let res = core.run(keys_service.export(Some(exporter.authentication_token.clone()), KeyId::default(), public_key));
+        match res.unwrap_err().kind() {
+            ErrorKind::NotFound => (),
+            kind => panic!("Unexpected error {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_update_status() {
+        let new_user = NewUser::default();
+        let user_id = new_user.id.clone();
+        let token = new_user.authentication_token.clone();
+        let new_user2 = NewUser::default();
+        let user_id2 = new_user2.id.clone();
+        let token2 = new_user2.authentication_token.clone();
+        let auth_service = Arc::new(AuthServiceMock::new(vec![
+            (token.clone(), user_id.clone()),
+            (token2.clone(), user_id2.clone()),
+        ]));
+        let keys_service = create_service(auth_service);
+        let mut core = Core::new().unwrap();
+        let key = core
+            .run(keys_service.create(
+                Some(token.clone()),
+                user_id.clone(),
+                Currency::Eth,
+                None,
+                KeyId::default(),
+                None,
+                KeyMetadata::default(),
+            ))
+            .unwrap();
+        assert_eq!(key.status, KeyStatus::Active);
+
+        let key = core
+            .run(keys_service.update(
+                Some(token.clone()),
+                user_id.clone(),
+                key.id.clone(),
+                status_update(KeyStatus::Disabled),
+            ))
+            .unwrap();
+        assert_eq!(key.status, KeyStatus::Disabled);
+        let key = core
+            .run(keys_service.update(
+                Some(token.clone()),
+                user_id.clone(),
+                key.id.clone(),
+                status_update(KeyStatus::Active),
+            ))
+            .unwrap();
+        assert_eq!(key.status, KeyStatus::Active);
+
+        // doesn't change keys of other users
+        let res = core.run(keys_service.update(
+            Some(token2.clone()),
+            user_id2.clone(),
+            key.id.clone(),
+            status_update(KeyStatus::Disabled),
+        ));
+        match res.unwrap_err().kind() {
+            ErrorKind::NotFound => (),
+            kind => panic!("Unexpected error {:?}", kind),
+        }
+        let res = core.run(keys_service.delete(Some(token2.clone()), user_id.clone(), key.id.clone()));
+        assert!(res.is_err());
+
+        // archived keys are kept, but can't be reactivated
+        let key = core
+            .run(keys_service.delete(Some(token.clone()), user_id.clone(), key.id.clone()))
+            .unwrap();
+        assert_eq!(key.status, KeyStatus::Archived);
+        let keys = core
+            .run(keys_service.list(Some(token.clone()), user_id.clone(), KeysFilter::default(), None, None))
+            .unwrap()
+            .keys;
+        assert_eq!(keys.len(), 1);
+        let res = core.run(keys_service.update(
+            Some(token.clone()),
+            user_id.clone(),
+            key.id.clone(),
+            status_update(KeyStatus::Active),
+        ));
+        match res.unwrap_err().kind() {
+            ErrorKind::Validation(_) => (),
+            kind => panic!("Unexpected error {:?}", kind),
+        }
+
+        // keys can't be quarantined by users
+        let key = core
+            .run(keys_service.create(
+                Some(token.clone()),
+                user_id.clone(),
+                Currency::Eth,
+                None,
+                KeyId::default(),
+                None,
+                KeyMetadata::default(),
+            ))
+            .unwrap();
+        let res = core.run(keys_service.update(
+            Some(token.clone()),
+            user_id.clone(),
+            key.id.clone(),
+            status_update(KeyStatus::Quarantined),
+        ));
+        match res.unwrap_err().kind() {
+            ErrorKind::Validation(_) => (),
+            kind => panic!("Unexpected error {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_get_and_find_by_address() {
+        let new_user = NewUser::default();
+        let user_id = new_user.id.clone();
+        let token = new_user.authentication_token.clone();
+        let new_user2 = NewUser::default();
+        let user_id2 = new_user2.id.clone();
+        let token2 = new_user2.authentication_token.clone();
+        let auth_service = Arc::new(AuthServiceMock::new(vec![
+            (token.clone(), user_id.clone()),
+            (token2.clone(), user_id2.clone()),
+        ]));
+        let keys_service = create_service(auth_service);
+        let mut core = Core::new().unwrap();
+        let private_key = RawPrivateKey::new("b3c0e85a511cc6d21423a386de29dcf2cda6b2f2fa5ebb47948401bbb90458db".to_string());
+        let eth_key = core
+            .run(keys_service.import(
+                Some(token.clone()),
+                user_id.clone(),
+                Currency::Eth,
+                None,
+                KeyId::default(),
+                private_key.clone(),
+                None,
+                KeyMetadata::default(),
+            ))
+            .unwrap();
+        // the same address is used for STQ
+        let stq_key = core
+            .run(keys_service.import(
+                Some(token.clone()),
+                user_id.clone(),
+                Currency::Stq,
+                None,
+                KeyId::default(),
+                private_key,
+                None,
+                KeyMetadata::default(),
+            ))
+            .unwrap();
+
+        let key = core
+            .run(keys_service.get(Some(token.clone()), user_id.clone(), eth_key.id.clone()))
+            .unwrap();
+        assert_eq!(key.blockchain_address, eth_key.blockchain_address);
+        let res = core.run(keys_service.get(Some(token2.clone()), user_id2.clone(), eth_key.id.clone()));
+        match res.unwrap_err().kind() {
+            ErrorKind::NotFound => (),
+            kind => panic!("Unexpected error {:?}", kind),
+        }
+        let res = core.run(keys_service.get(Some(token2.clone()), user_id.clone(), eth_key.id.clone()));
+        match res.unwrap_err().kind() {
+            ErrorKind::Unauthorized => (),
+            kind => panic!("Unexpected error {:?}", kind),
+        }
+
+        let key = core
+            .run(keys_service.find_by_address(Some(token.clone()), Currency::Stq, stq_key.blockchain_address.clone()))
+            .unwrap();
+        assert_eq!(key.id, stq_key.id);
+        let prefixed = BlockchainAddress::new(format!("0x{}", eth_key.blockchain_address.clone().into_inner().to_uppercase()));
+        let key = core
+            .run(keys_service.find_by_address(Some(token.clone()), Currency::Eth, prefixed))
+            .unwrap();
+        assert_eq!(key.id, eth_key.id);
+        let res = core.run(keys_service.find_by_address(Some(token2.clone()), Currency::Eth, eth_key.blockchain_address.clone()));
+        match res.unwrap_err().kind() {
+            ErrorKind::NotFound => (),
+            kind => panic!("Unexpected error {:?}", kind),
+        }
+        let res = core.run(keys_service.find_by_address(Some(token.clone()), Currency::Btc, eth_key.blockchain_address.clone()));
+        assert!(res.is_err());
+        let res = core.run(keys_service.find_by_address(None, Currency::Eth, eth_key.blockchain_address.clone()));
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_list_pages() {
+        let new_user = NewUser::default();
+        let user_id = new_user.id.clone();
+        let token = new_user.authentication_token.clone();
+        let auth_service = Arc::new(AuthServiceMock::new(vec![(token.clone(), user_id.clone())]));
+        let keys_service = create_service(auth_service);
+        let mut core = Core::new().unwrap();
+        let mut created = Vec::new();
+        for currency in &[Currency::Eth, Currency::Btc, Currency::Eth, Currency::Stq, Currency::Eth] {
+            let key = core
+                .run(keys_service.create(
+                    Some(token.clone()),
+                    user_id.clone(),
+                    *currency,
+                    None,
+                    KeyId::default(),
+                    None,
+                    KeyMetadata::default(),
+                ))
+                .unwrap();
+            created.push(key.id);
+        }
+        core.run(keys_service.delete(Some(token.clone()), user_id.clone(), created[4].clone()))
+            .unwrap();
+
+        let mut listed = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = core
+                .run(keys_service.list(Some(token.clone()), user_id.clone(), KeysFilter::default(), cursor, Some(2)))
+                .unwrap();
+            assert_eq!(page.total, 5);
+            assert!(page.keys.len() <= 2);
+            listed.extend(page.keys.into_iter().map(|key| key.id));
+            cursor = page.next_cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        let mut expected = created.clone();
+        expected.sort();
+        listed.sort();
+        assert_eq!(listed, expected);
+        // cursor survives the round trip through clients
+        let page = core
+            .run(keys_service.list(Some(token.clone()), user_id.clone(), KeysFilter::default(), None, Some(3)))
+            .unwrap();
+        let cursor: KeysCursor = page.next_cursor.unwrap().to_string().parse().unwrap();
+        let page = core
+            .run(keys_service.list(Some(token.clone()), user_id.clone(), KeysFilter::default(), Some(cursor), None))
+            .unwrap();
+        assert_eq!(page.keys.len(), 2);
+        assert!(page.next_cursor.is_none());
+
+        let filter = KeysFilter {
+            currency: Some(Currency::Eth),
+            status: Some(KeyStatus::Active),
+            ..Default::default()
+        };
+        let page = core
+            .run(keys_service.list(Some(token.clone()), user_id.clone(), filter, None, Some(1)))
+            .unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(page.keys.len(), 1);
+        assert!(page.next_cursor.is_some());
+
+        for limit in &[0, MAX_KEYS_LIMIT + 1] {
+            let res = core.run(keys_service.list(Some(token.clone()), user_id.clone(), KeysFilter::default(), None, Some(*limit)));
+            match res.unwrap_err().kind() {
+                ErrorKind::Validation(_) => (),
+                kind => panic!("Unexpected error {:?}", kind),
+            }
+        }
+    }
+
+    #[test]
+    fn test_labels_and_metadata() {
+        let new_user = NewUser::default();
+        let user_id = new_user.id.clone();
+        let token = new_user.authentication_token.clone();
+        let auth_service = Arc::new(AuthServiceMock::new(vec![(token.clone(), user_id.clone())]));
+        let keys_service = create_service(auth_service);
+        let mut core = Core::new().unwrap();
+        let metadata = |json: &str| KeyMetadata::new(serde_json::from_str(json).unwrap());
+        let hot = core
+            .run(keys_service.create(
+                Some(token.clone()),
+                user_id.clone(),
+                Currency::Eth,
+                None,
+                KeyId::default(),
+                Some(KeyLabel::new("hot".to_string())),
+                metadata(r#"{"merchant": "shop", "region": "eu"}"#),
+            ))
+            .unwrap();
+        assert_eq!(hot.label, Some(KeyLabel::new("hot".to_string())));
+        let fees = core
+            .run(keys_service.create(
+                Some(token.clone()),
+                user_id.clone(),
+                Currency::Eth,
+                None,
+                KeyId::default(),
+                Some(KeyLabel::new("fees".to_string())),
+                metadata(r#"{"merchant": "market"}"#),
+            ))
+            .unwrap();
+
+        let filter = KeysFilter {
+            label: Some(KeyLabel::new("hot".to_string())),
+            ..Default::default()
+        };
+        let keys = core
+            .run(keys_service.list(Some(token.clone()), user_id.clone(), filter, None, None))
+            .unwrap()
+            .keys;
+        assert_eq!(keys.iter().map(|key| key.id.clone()).collect::<Vec<_>>(), vec![hot.id.clone()]);
+        let filter = KeysFilter {
+            metadata: Some(metadata(r#"{"merchant": "market"}"#)),
+            ..Default::default()
+        };
+        let keys = core
+            .run(keys_service.list(Some(token.clone()), user_id.clone(), filter, None, None))
+            .unwrap()
+            .keys;
+        assert_eq!(keys.iter().map(|key| key.id.clone()).collect::<Vec<_>>(), vec![fees.id.clone()]);
+
+        // fields that are not set are left as is
+        let changes = KeyUpdate {
+            metadata: Some(metadata(r#"{"merchant": "market", "payer": true}"#)),
+            ..Default::default()
+        };
+        let updated = core
+            .run(keys_service.update(Some(token.clone()), user_id.clone(), fees.id.clone(), changes))
+            .unwrap();
+        assert_eq!(updated.label, fees.label);
+        assert_eq!(updated.status, KeyStatus::Active);
+        assert_eq!(updated.metadata, metadata(r#"{"payer": true, "merchant": "market"}"#));
+
+        let invalid = vec![
+            (Some(KeyLabel::new(String::new())), None),
+            (Some(KeyLabel::new("x".repeat(65))), None),
+            (None, Some(metadata(r#"["hot"]"#))),
+        ];
+        for (label, metadata) in invalid {
+            let changes = KeyUpdate {
+                status: None,
+                label,
+                metadata,
+            };
+            let res = core.run(keys_service.update(Some(token.clone()), user_id.clone(), fees.id.clone(), changes));
+            match res.unwrap_err().kind() {
+                ErrorKind::Validation(_) => (),
+                kind => panic!("Unexpected error {:?}", kind),
+            }
+        }
+        let res = core.run(keys_service.create(
+            Some(token.clone()),
+            user_id.clone(),
+            Currency::Eth,
+            None,
+            KeyId::default(),
+            None,
+            metadata("42"),
+        ));
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_create_derived() {
+        let new_user = NewUser::default();
+        let user_id = new_user.id.clone();
+        let token = new_user.authentication_token.clone();
+        let new_user2 = NewUser::default();
+        let user_id2 = new_user2.id.clone();
+        let token2 = new_user2.authentication_token.clone();
+        let auth_service = Arc::new(AuthServiceMock::new(vec![
+            (token.clone(), user_id.clone()),
+            (token2.clone(), user_id2.clone()),
+        ]));
+        let blockchain_service = Arc::new(BlockchainServiceMock);
+        let users_repo = Arc::new(UsersRepoMock::new());
+        let mut system_user = NewUser::default();
+        system_user.name = "system".to_string();
+        users_repo.create(system_user).unwrap();
+        let mut core = Core::new().unwrap();
+
+        // derives keys of each user from their own seed
+        let keys_service = KeysServiceImpl::new(
+            auth_service.clone(),
+            blockchain_service.clone(),
+            Arc::new(KeysRepoMock::new()),
+            Arc::new(SeedsRepoMock::new()),
+            users_repo.clone(),
+            KeyGeneration::UserSeed,
+            DbExecutorMock::new(),
+        );
+        let key = core
+            .run(keys_service.create(
+                Some(token.clone()),
+                user_id.clone(),
+                Currency::Eth,
+                None,
+                KeyId::default(),
+                None,
+                KeyMetadata::default(),
+            ))
+            .unwrap();
+        assert_eq!(key.derivation_path.unwrap().to_string(), "m/44'/0'/0'/0/0");
+        let key = core
+            .run(keys_service.create(
+                Some(token.clone()),
+                user_id.clone(),
+                Currency::Eth,
+                None,
+                KeyId::default(),
+                None,
+                KeyMetadata::default(),
+            ))
+            .unwrap();
+        assert_eq!(key.derivation_path.unwrap().to_string(), "m/44'/0'/0'/0/1");
+        let key = core
+            .run(keys_service.create(
+                Some(token2.clone()),
+                user_id2.clone(),
+                Currency::Eth,
+                None,
+                KeyId::default(),
+                None,
+                KeyMetadata::default(),
+            ))
+            .unwrap();
+        assert_eq!(key.derivation_path.unwrap().to_string(), "m/44'/0'/0'/0/0");
+
+        // derives keys of all users from system seed
+        let keys_service = KeysServiceImpl::new(
+            auth_service,
+            blockchain_service,
+            Arc::new(KeysRepoMock::new()),
+            Arc::new(SeedsRepoMock::new()),
+            users_repo,
+            KeyGeneration::SystemSeed,
+            DbExecutorMock::new(),
+        );
+        let key1 = core
+            .run(keys_service.create(
+                Some(token.clone()),
+                user_id.clone(),
+                Currency::Btc,
+                None,
+                KeyId::default(),
+                None,
+                KeyMetadata::default(),
+            ))
+            .unwrap();
+        let key2 = core
+            .run(keys_service.create(
+                Some(token2.clone()),
+                user_id2.clone(),
+                Currency::Btc,
+                None,
+                KeyId::default(),
+                None,
+                KeyMetadata::default(),
+            ))
+            .unwrap();
+        assert_eq!(key1.owner_id, user_id);
+        assert_eq!(key2.derivation_path.unwrap().to_string(), "m/44'/0'/0'/0/1");
+        assert_ne!(key1.blockchain_address, key2.blockchain_address);
+
+        // derives P2WPKH keys under BIP84 purpose with separate indexes
+        let key = core
+            .run(keys_service.create(
+                Some(token.clone()),
+                user_id.clone(),
+                Currency::Btc,
+                Some(WalletType::P2wpkh),
+                KeyId::default(),
+                None,
+                KeyMetadata::default(),
+            ))
+            .unwrap();
+        assert_eq!(key.derivation_path.unwrap().to_string(), "m/84'/0'/0'/0/0");
+        assert_eq!(key.wallet_type, Some(WalletType::P2wpkh));
+
+        // derives P2SH-P2WPKH keys under BIP49 purpose
+        let key = core
+            .run(keys_service.create(
+                Some(token.clone()),
+                user_id.clone(),
+                Currency::Btc,
+                Some(WalletType::P2shP2wpkh),
+                KeyId::default(),
+                None,
+                KeyMetadata::default(),
+            ))
+            .unwrap();
+        assert_eq!(key.derivation_path.unwrap().to_string(), "m/49'/0'/0'/0/0");
+    }
 }
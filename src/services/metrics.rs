@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use super::auth::AuthService;
 use super::error::*;
 use blockchain::BlockchainService;
 use models::*;
@@ -8,23 +9,98 @@ use repos::{DbExecutor, Isolation, KeysRepo};
 
 pub trait MetricsService: Send + Sync + 'static {
     fn get_metrics(&self) -> Box<Future<Item = Metrics, Error = Error> + Send>;
+    /// Checks that every key can be decrypted and its address can be derived from it.
+    /// Read-only report, allowed only for admin users.
+    fn audit(&self, maybe_token: Option<AuthenticationToken>) -> Box<Future<Item = Audit, Error = Error> + Send>;
+    /// Same check as `audit`, but failed keys are quarantined, so that they can't sign transactions.
+    /// Allowed only for admin users.
+    fn quarantine(&self, maybe_token: Option<AuthenticationToken>) -> Box<Future<Item = Audit, Error = Error> + Send>;
 }
 
 #[derive(Clone)]
 pub struct MetricsServiceImpl<E: DbExecutor> {
+    auth_service: Arc<AuthService>,
     keys_repo: Arc<KeysRepo>,
     blockchain_service: Arc<BlockchainService>,
     db_executor: E,
 }
 
 impl<E: DbExecutor> MetricsServiceImpl<E> {
-    pub fn new(keys_repo: Arc<KeysRepo>, blockchain_service: Arc<BlockchainService>, db_executor: E) -> Self {
+    pub fn new(
+        auth_service: Arc<AuthService>,
+        keys_repo: Arc<KeysRepo>,
+        blockchain_service: Arc<BlockchainService>,
+        db_executor: E,
+    ) -> Self {
         MetricsServiceImpl {
+            auth_service,
             keys_repo,
             db_executor,
             blockchain_service,
         }
     }
+
+    /// Returns the reason if the key fails the audit
+    fn audit_key(&self, key: &Key) -> Option<AuditFailureReason> {
+        let private_key = match self.keys_repo.decrypt_private_key(key) {
+            Ok(private_key) => private_key,
+            Err(_) => return Some(AuditFailureReason::Decryption),
+        };
+        match self.blockchain_service.derive_address(key.currency, key.wallet_type, &private_key) {
+            Ok(ref derived) if *derived == key.blockchain_address => None,
+            Ok(_) => Some(AuditFailureReason::AddressMismatch),
+            Err(_) => Some(AuditFailureReason::Derivation),
+        }
+    }
+
+    fn audit_keys(&self, maybe_token: Option<AuthenticationToken>, quarantine: bool) -> Box<Future<Item = Audit, Error = Error> + Send> {
+        let self_ = self.clone();
+        Box::new(
+            self.auth_service
+                .authenticate(maybe_token)
+                .and_then(|user| {
+                    if !user.is_admin {
+                        warn!("User {:?} is not allowed to audit keys", user.id);
+                        return Err(ectx!(err ErrorContext::AdminOnly, ErrorKind::Unauthorized => user.id));
+                    }
+                    Ok(user)
+                })
+                .and_then(move |user| {
+                    let db_executor = self_.db_executor.clone();
+                    db_executor.execute_transaction_with_isolation(Isolation::RepeatableRead, move || {
+                        let keys = self_.keys_repo.all().map_err(ectx!(try ErrorKind::Internal))?;
+                        let mut audit = Audit {
+                            total_keys: keys.len() as u64,
+                            ..Default::default()
+                        };
+                        for key in keys {
+                            let reason = match self_.audit_key(&key) {
+                                Some(reason) => reason,
+                                None => continue,
+                            };
+                            warn!("Key {:?} failed audit: {:?}", key.id, reason);
+                            // Archived keys are already retired for good
+                            if quarantine && key.status != KeyStatus::Quarantined && key.status != KeyStatus::Archived {
+                                let key_id = key.id.clone();
+                                self_
+                                    .keys_repo
+                                    .update_status(key.id.clone(), KeyStatus::Quarantined)
+                                    .map_err(ectx!(try ErrorKind::Internal => key_id))?;
+                                info!("User {:?} quarantined key {:?}", user.id, key.id);
+                                audit.quarantined_keys += 1;
+                            }
+                            audit.failed_keys.push(AuditFailure {
+                                key_id: key.id,
+                                currency: key.currency,
+                                blockchain_address: key.blockchain_address,
+                                reason,
+                            });
+                        }
+                        Ok(audit)
+                    })
+                }),
+        )
+    }
 }
 
 impl<E: DbExecutor> MetricsService for MetricsServiceImpl<E> {
@@ -33,31 +109,104 @@ impl<E: DbExecutor> MetricsService for MetricsServiceImpl<E> {
         self.db_executor
             .execute_transaction_with_isolation(Isolation::RepeatableRead, move || {
                 let keys = self_.keys_repo.all().map_err(ectx!(try ErrorKind::Internal))?;
-                let mut total_keys: u64 = 0;
-                let mut failed_derivations_count: u64 = 0;
-                for key in keys {
-                    total_keys += 1;
-
-                    let Key {
-                        currency,
-                        private_key,
-                        blockchain_address,
-                        ..
-                    } = key;
-
-                    let derived = self_
-                        .blockchain_service
-                        .derive_address(currency.clone(), private_key.clone())
-                        .map_err(ectx!(try ErrorKind::Internal => currency, private_key))?;
-
-                    if blockchain_address != derived {
-                        failed_derivations_count += 1;
-                    }
-                }
+                let total_keys = keys.len() as u64;
+                let failed_derivations_count = keys.iter().filter(|key| self_.audit_key(key).is_some()).count() as u64;
                 Ok(Metrics {
                     total_keys,
                     failed_derivations_count,
                 })
             })
     }
+
+    fn audit(&self, maybe_token: Option<AuthenticationToken>) -> Box<Future<Item = Audit, Error = Error> + Send> {
+        self.audit_keys(maybe_token, false)
+    }
+
+    fn quarantine(&self, maybe_token: Option<AuthenticationToken>) -> Box<Future<Item = Audit, Error = Error> + Send> {
+        self.audit_keys(maybe_token, true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain::*;
+    use repos::*;
+    use services::{AuthServiceMock, ErrorKind};
+    use tokio_core::reactor::Core;
+
+    fn create_key(keys_repo: &KeysRepoMock, private_key: &str, blockchain_address: &str) -> Key {
+        keys_repo
+            .create(NewKey {
+                id: KeyId::default(),
+                private_key: PrivateKey::new(private_key.to_string()),
+                blockchain_address: BlockchainAddress::new(blockchain_address.to_string()),
+                currency: Currency::Eth,
+                owner_id: UserId::default(),
+                derivation_path: None,
+                label: None,
+                metadata: KeyMetadata::default(),
+                wallet_type: None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_audit() {
+        let admin = User {
+            is_admin: true,
+            ..User::default()
+        };
+        let user = User::default();
+        let auth_service = Arc::new(AuthServiceMock::from_users(vec![admin.clone(), user.clone()]));
+        let keys_repo = Arc::new(KeysRepoMock::new());
+        let metrics_service = MetricsServiceImpl::new(
+            auth_service,
+            keys_repo.clone(),
+            Arc::new(BlockchainServiceMock),
+            DbExecutorMock::new(),
+        );
+        let valid = create_key(&keys_repo, "valid", "address:valid");
+        let mismatched = create_key(&keys_repo, "mismatched", "address:other");
+        let archived = create_key(&keys_repo, "archived", "address:another");
+        keys_repo.update_status(archived.id.clone(), KeyStatus::Archived).unwrap();
+        let mut core = Core::new().unwrap();
+        let admin_token = Some(admin.authentication_token.clone());
+
+        let metrics = core.run(metrics_service.get_metrics()).unwrap();
+        assert_eq!((metrics.total_keys, metrics.failed_derivations_count), (3, 2));
+
+        for maybe_token in vec![None, Some(user.authentication_token.clone())] {
+            for res in vec![
+                core.run(metrics_service.audit(maybe_token.clone())),
+                core.run(metrics_service.quarantine(maybe_token.clone())),
+            ] {
+                match res.unwrap_err().kind() {
+                    ErrorKind::Unauthorized => (),
+                    kind => panic!("Unexpected error {:?}", kind),
+                }
+            }
+        }
+        let status = |key: &Key| keys_repo.find(key.id.clone()).unwrap().unwrap().status;
+        assert_eq!(status(&mismatched), KeyStatus::Active);
+
+        // report doesn't change statuses
+        let audit = core.run(metrics_service.audit(admin_token.clone())).unwrap();
+        assert_eq!(audit.total_keys, 3);
+        assert_eq!(audit.quarantined_keys, 0);
+        let failed: Vec<KeyId> = audit.failed_keys.iter().map(|failure| failure.key_id.clone()).collect();
+        assert_eq!(failed, vec![mismatched.id.clone(), archived.id.clone()]);
+        assert_eq!(audit.failed_keys[0].reason, AuditFailureReason::AddressMismatch);
+        assert_eq!(status(&mismatched), KeyStatus::Active);
+
+        let audit = core.run(metrics_service.quarantine(admin_token.clone())).unwrap();
+        assert_eq!((audit.failed_keys.len(), audit.quarantined_keys), (2, 1));
+        assert_eq!(status(&valid), KeyStatus::Active);
+        assert_eq!(status(&mismatched), KeyStatus::Quarantined);
+        assert_eq!(status(&archived), KeyStatus::Archived);
+
+        // already quarantined keys are still reported
+        let audit = core.run(metrics_service.quarantine(admin_token)).unwrap();
+        assert_eq!((audit.failed_keys.len(), audit.quarantined_keys), (2, 0));
+    }
 }
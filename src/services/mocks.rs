@@ -21,6 +21,14 @@ impl AuthServiceMock {
         }
         AuthServiceMock { users }
     }
+
+    pub fn from_users(allowed_users: Vec<User>) -> Self {
+        let users = allowed_users
+            .into_iter()
+            .map(|user| (user.authentication_token.clone(), user))
+            .collect();
+        AuthServiceMock { users }
+    }
 }
 
 impl AuthService for AuthServiceMock {
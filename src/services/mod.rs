@@ -1,18 +1,24 @@
 mod auth;
+mod backup;
 mod error;
 mod keys;
 mod metrics;
 #[cfg(test)]
 mod mocks;
+mod seeds;
 mod transactions;
+mod vanity;
 
 pub use self::auth::*;
+pub use self::backup::*;
 pub use self::error::*;
 pub use self::keys::*;
 pub use self::metrics::*;
 #[cfg(test)]
 pub use self::mocks::*;
+pub use self::seeds::*;
 pub use self::transactions::*;
+pub use self::vanity::*;
 
 use prelude::*;
 
@@ -0,0 +1,540 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::auth::AuthService;
+use super::error::*;
+use super::keys::{key_wallet_type, MAX_KEYS_LIMIT};
+use super::ServiceFuture;
+use blockchain::BlockchainService;
+use config::KeyGeneration;
+use models::*;
+use prelude::*;
+use repos::{DbExecutor, KeysRepo, SeedsRepo, UsersRepo};
+
+pub trait SeedsService: Send + Sync + 'static {
+    /// Generates new BIP39 mnemonic and stores master seed derived from it. The mnemonic itself
+    /// is not stored, so this is the only time it can be shown to the user.
+    fn create_mnemonic(
+        &self,
+        maybe_token: Option<AuthenticationToken>,
+        user_id: UserId,
+        words: usize,
+        passphrase: Passphrase,
+    ) -> ServiceFuture<Mnemonic>;
+    /// Stores master seed derived from existing mnemonic and recreates the first `recover` keys of each currency.
+    /// Bitcoin keys are recreated for each of `wallet_types`, or with the default wallet type if it's empty.
+    fn import_mnemonic(
+        &self,
+        maybe_token: Option<AuthenticationToken>,
+        user_id: UserId,
+        mnemonic: Mnemonic,
+        passphrase: Passphrase,
+        recover: HashMap<Currency, u32>,
+        wallet_types: Vec<WalletType>,
+    ) -> ServiceFuture<Vec<Key>>;
+    /// Returns account-level extended public key of `currency` and `wallet_type`, its derivation path and
+    /// the wallet type it was derived for. Key with derivation path `{account path}/0/{index}` is
+    /// the child `0/{index}` of this key.
+    fn extended_public_key(
+        &self,
+        maybe_token: Option<AuthenticationToken>,
+        user_id: UserId,
+        currency: Currency,
+        wallet_type: Option<WalletType>,
+    ) -> ServiceFuture<(ExtendedPublicKey, DerivationPath, Option<WalletType>)>;
+}
+
+pub struct SeedsServiceImpl<E: DbExecutor> {
+    auth_service: Arc<AuthService>,
+    blockchain_service: Arc<BlockchainService>,
+    keys_repo: Arc<KeysRepo>,
+    seeds_repo: Arc<SeedsRepo>,
+    users_repo: Arc<UsersRepo>,
+    key_generation: KeyGeneration,
+    db_executor: E,
+}
+
+impl<E: DbExecutor> SeedsServiceImpl<E> {
+    pub fn new(
+        auth_service: Arc<AuthService>,
+        blockchain_service: Arc<BlockchainService>,
+        keys_repo: Arc<KeysRepo>,
+        seeds_repo: Arc<SeedsRepo>,
+        users_repo: Arc<UsersRepo>,
+        key_generation: KeyGeneration,
+        db_executor: E,
+    ) -> Self {
+        Self {
+            auth_service,
+            blockchain_service,
+            keys_repo,
+            seeds_repo,
+            users_repo,
+            key_generation,
+            db_executor,
+        }
+    }
+
+    fn authenticate_seed_owner(&self, maybe_token: Option<AuthenticationToken>, user_id: UserId) -> ServiceFuture<UserId> {
+        let db_executor = self.db_executor.clone();
+        let users_repo = self.users_repo.clone();
+        let key_generation = self.key_generation;
+        Box::new(
+            self.auth_service
+                .authenticate(maybe_token)
+                .and_then(move |user| {
+                    if user.id != user_id {
+                        Err(ectx!(err ErrorContext::NotOwnResources, ErrorKind::Unauthorized => user.id, user_id))
+                    } else {
+                        Ok(user)
+                    }
+                })
+                .and_then(move |user| db_executor.execute(move || seed_owner(&*users_repo, key_generation, user.id))),
+        )
+    }
+}
+
+impl<E: DbExecutor> SeedsService for SeedsServiceImpl<E> {
+    fn create_mnemonic(
+        &self,
+        maybe_token: Option<AuthenticationToken>,
+        user_id: UserId,
+        words: usize,
+        passphrase: Passphrase,
+    ) -> ServiceFuture<Mnemonic> {
+        let db_executor = self.db_executor.clone();
+        let seeds_repo = self.seeds_repo.clone();
+        Box::new(self.authenticate_seed_owner(maybe_token, user_id).and_then(move |owner_id| {
+            db_executor.execute_transaction(move || {
+                let mnemonic = Mnemonic::generate(words)
+                    .map_err(ectx!(try ErrorContext::Mnemonic, ErrorKind::invalid_field("words", "invalid") => words))?;
+                store_mnemonic_seed(&*seeds_repo, owner_id, &mnemonic, &passphrase)?;
+                Ok(mnemonic)
+            })
+        }))
+    }
+
+    fn import_mnemonic(
+        &self,
+        maybe_token: Option<AuthenticationToken>,
+        user_id: UserId,
+        mnemonic: Mnemonic,
+        passphrase: Passphrase,
+        recover: HashMap<Currency, u32>,
+        wallet_types: Vec<WalletType>,
+    ) -> ServiceFuture<Vec<Key>> {
+        let db_executor = self.db_executor.clone();
+        let blockchain_service = self.blockchain_service.clone();
+        let keys_repo = self.keys_repo.clone();
+        let seeds_repo = self.seeds_repo.clone();
+        Box::new(self.authenticate_seed_owner(maybe_token, user_id).and_then(move |owner_id| {
+            db_executor.execute_transaction(move || {
+                check_recover_count(&recover, &wallet_types)?;
+                let seed = store_mnemonic_seed(&*seeds_repo, owner_id, &mnemonic, &passphrase)?;
+                let mut keys = Vec::new();
+                for (currency, count) in recover {
+                    for wallet_type in recovered_wallet_types(currency, &wallet_types) {
+                        keys.extend(recover_keys(
+                            &*blockchain_service,
+                            &*keys_repo,
+                            &*seeds_repo,
+                            &seed,
+                            currency,
+                            wallet_type,
+                            count,
+                        )?);
+                    }
+                }
+                Ok(keys)
+            })
+        }))
+    }
+
+    fn extended_public_key(
+        &self,
+        maybe_token: Option<AuthenticationToken>,
+        user_id: UserId,
+        currency: Currency,
+        wallet_type: Option<WalletType>,
+    ) -> ServiceFuture<(ExtendedPublicKey, DerivationPath, Option<WalletType>)> {
+        let db_executor = self.db_executor.clone();
+        let blockchain_service = self.blockchain_service.clone();
+        let seeds_repo = self.seeds_repo.clone();
+        Box::new(self.authenticate_seed_owner(maybe_token, user_id).and_then(move |owner_id| {
+            db_executor.execute_transaction(move || {
+                let wallet_type = key_wallet_type(currency, wallet_type)?;
+                // The seed is created the same way as on the first key creation, so xpub can be exported before any key exists
+                let seed = find_or_create_seed(&*seeds_repo, owner_id)?;
+                let path = blockchain_service.account_derivation_path(currency, wallet_type);
+                let path_clone = path.clone();
+                let extended_public_key = blockchain_service
+                    .derive_extended_public_key(currency, wallet_type, &seed.master_seed, &path)
+                    .map_err(ectx!(try convert => currency, path_clone))?;
+                Ok((extended_public_key, path, wallet_type))
+            })
+        }))
+    }
+}
+
+/// Returns master seed of `owner_id`, generating a new one if the owner doesn't have it yet
+pub fn find_or_create_seed(seeds_repo: &SeedsRepo, owner_id: UserId) -> Result<Seed, Error> {
+    match seeds_repo.find(owner_id.clone())? {
+        Some(seed) => Ok(seed),
+        None => {
+            let master_seed = MasterSeed::generate().map_err(ectx!(try ErrorContext::MasterSeed, ErrorKind::Internal))?;
+            Ok(seeds_repo.create(owner_id, master_seed)?)
+        }
+    }
+}
+
+/// Returns the owner of the seed that keys of `user_id` are derived from. Mnemonic is only
+/// accepted for the seed that is actually used for key generation.
+fn seed_owner(users_repo: &UsersRepo, key_generation: KeyGeneration, user_id: UserId) -> Result<UserId, Error> {
+    match key_generation {
+        KeyGeneration::Random => Err(ectx!(err ErrorContext::MasterSeed, ErrorKind::invalid_field("seed", "not_used") => user_id)),
+        KeyGeneration::UserSeed => Ok(user_id),
+        KeyGeneration::SystemSeed => {
+            let system_user = users_repo
+                .find_system_user()?
+                .ok_or(ectx!(try err ErrorContext::NoSystemUser, ErrorKind::Internal))?;
+            if system_user.id != user_id {
+                Err(ectx!(err ErrorContext::NotOwnResources, ErrorKind::Unauthorized => user_id))
+            } else {
+                Ok(user_id)
+            }
+        }
+    }
+}
+
+/// Stores master seed derived from `mnemonic` for `owner_id`. Existing seed is never replaced,
+/// since keys derived from it would become unrecoverable.
+pub fn store_mnemonic_seed(seeds_repo: &SeedsRepo, owner_id: UserId, mnemonic: &Mnemonic, passphrase: &Passphrase) -> Result<Seed, Error> {
+    if seeds_repo.find(owner_id.clone())?.is_some() {
+        return Err(ectx!(err ErrorContext::MasterSeed, ErrorKind::invalid_field("seed", "exists") => owner_id));
+    }
+    let master_seed = MasterSeed::from_mnemonic(mnemonic, passphrase)
+        .map_err(ectx!(try ErrorContext::Mnemonic, ErrorKind::invalid_field("mnemonic", "invalid")))?;
+    let owner_id_clone = owner_id.clone();
+    seeds_repo.create(owner_id, master_seed).map_err(ectx!(convert => owner_id_clone))
+}
+
+/// Wallet types of keys of `currency` recreated from seed. Bitcoin keys are recreated for each
+/// of `wallet_types`, or with the default wallet type if it's empty.
+pub fn recovered_wallet_types(currency: Currency, wallet_types: &[WalletType]) -> Vec<Option<WalletType>> {
+    match WalletType::default_for(currency) {
+        None => vec![None],
+        Some(default) if wallet_types.is_empty() => vec![Some(default)],
+        Some(_) => {
+            let mut recovered = Vec::new();
+            for wallet_type in wallet_types {
+                if !recovered.contains(&Some(*wallet_type)) {
+                    recovered.push(Some(*wallet_type));
+                }
+            }
+            recovered
+        }
+    }
+}
+
+/// Checks that the total number of keys recovered for `recover` counts of each currency isn't more
+/// than a page of keys, since all of them are recovered in one transaction
+pub fn check_recover_count(recover: &HashMap<Currency, u32>, wallet_types: &[WalletType]) -> Result<(), Error> {
+    let total: u64 = recover
+        .iter()
+        .map(|(&currency, &count)| u64::from(count) * recovered_wallet_types(currency, wallet_types).len() as u64)
+        .sum();
+    if total > MAX_KEYS_LIMIT as u64 {
+        return Err(ectx!(err ErrorContext::InvalidRecoverCount, ErrorKind::invalid_field("recover", "range") => total));
+    }
+    Ok(())
+}
+
+/// Recreates `count` next keys of `currency` and `wallet_type` derived from `seed`, owned by the seed owner
+pub fn recover_keys(
+    blockchain_service: &BlockchainService,
+    keys_repo: &KeysRepo,
+    seeds_repo: &SeedsRepo,
+    seed: &Seed,
+    currency: Currency,
+    wallet_type: Option<WalletType>,
+    count: u32,
+) -> Result<Vec<Key>, Error> {
+    let account_path = blockchain_service.account_derivation_path(currency, wallet_type);
+    (0..count)
+        .map(|_| {
+            let index = seeds_repo.next_derivation_index(seed.owner_id.clone(), currency, wallet_type)?;
+            let path = account_path.address(index);
+            let path_clone = path.clone();
+            let (private_key, blockchain_address) = blockchain_service
+                .derive_key(currency, wallet_type, &seed.master_seed, &path)
+                .map_err(ectx!(try convert => currency, path_clone))?;
+            let new_key = NewKey {
+                id: KeyId::default(),
+                currency,
+                owner_id: seed.owner_id.clone(),
+                private_key,
+                blockchain_address,
+                derivation_path: Some(path),
+                label: None,
+                metadata: KeyMetadata::default(),
+                wallet_type,
+            };
+            let owner_id = seed.owner_id.clone();
+            keys_repo.create(new_key).map_err(ectx!(convert => owner_id, currency, index))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain::*;
+    use repos::*;
+    use services::ErrorKind;
+    use services::*;
+    use tokio_core::reactor::Core;
+
+    fn create_service(auth_service: Arc<AuthServiceMock>, key_generation: KeyGeneration) -> SeedsServiceImpl<DbExecutorMock> {
+        SeedsServiceImpl::new(
+            auth_service,
+            Arc::new(BlockchainServiceMock),
+            Arc::new(KeysRepoMock::new()),
+            Arc::new(SeedsRepoMock::new()),
+            Arc::new(UsersRepoMock::new()),
+            key_generation,
+            DbExecutorMock::new(),
+        )
+    }
+
+    #[test]
+    fn test_create_mnemonic() {
+        let new_user = NewUser::default();
+        let user_id = new_user.id.clone();
+        let token = new_user.authentication_token.clone();
+        let auth_service = Arc::new(AuthServiceMock::new(vec![(token.clone(), user_id.clone())]));
+        let seeds_service = create_service(auth_service.clone(), KeyGeneration::UserSeed);
+        let mut core = Core::new().unwrap();
+
+        // doesn't create mnemonic with wrong number of words
+        let res = core.run(seeds_service.create_mnemonic(Some(token.clone()), user_id.clone(), 15, Passphrase::default()));
+        assert!(res.is_err());
+
+        let mnemonic = core
+            .run(seeds_service.create_mnemonic(Some(token.clone()), user_id.clone(), 24, Passphrase::default()))
+            .unwrap();
+        assert_eq!(mnemonic.raw().split(' ').count(), 24);
+
+        // doesn't replace existing seed
+        let res = core.run(seeds_service.create_mnemonic(Some(token.clone()), user_id.clone(), 12, Passphrase::default()));
+        assert!(res.is_err());
+        let res = core.run(seeds_service.import_mnemonic(
+            Some(token.clone()),
+            user_id.clone(),
+            mnemonic,
+            Passphrase::default(),
+            HashMap::new(),
+            Vec::new(),
+        ));
+        assert!(res.is_err());
+
+        // doesn't create mnemonic with no token
+        let res = core.run(seeds_service.create_mnemonic(None, UserId::default(), 12, Passphrase::default()));
+        assert!(res.is_err());
+
+        // doesn't create mnemonic if keys are not derived from seeds
+        let seeds_service = create_service(auth_service, KeyGeneration::Random);
+        let res = core.run(seeds_service.create_mnemonic(Some(token.clone()), user_id.clone(), 12, Passphrase::default()));
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_import_mnemonic() {
+        let new_user = NewUser::default();
+        let user_id = new_user.id.clone();
+        let token = new_user.authentication_token.clone();
+        let new_user2 = NewUser::default();
+        let user_id2 = new_user2.id.clone();
+        let token2 = new_user2.authentication_token.clone();
+        let new_user3 = NewUser::default();
+        let user_id3 = new_user3.id.clone();
+        let token3 = new_user3.authentication_token.clone();
+        let auth_service = Arc::new(AuthServiceMock::new(vec![
+            (token.clone(), user_id.clone()),
+            (token2.clone(), user_id2.clone()),
+            (token3.clone(), user_id3.clone()),
+        ]));
+        let seeds_service = create_service(auth_service, KeyGeneration::UserSeed);
+        let mut core = Core::new().unwrap();
+
+        let mnemonic = core
+            .run(seeds_service.create_mnemonic(Some(token.clone()), user_id.clone(), 12, Passphrase::default()))
+            .unwrap();
+        let mut recover = HashMap::new();
+        recover.insert(Currency::Eth, 2);
+
+        // doesn't import invalid mnemonic
+        let res = core.run(seeds_service.import_mnemonic(
+            Some(token2.clone()),
+            user_id2.clone(),
+            Mnemonic::new("abandon abandon".to_string()),
+            Passphrase::default(),
+            recover.clone(),
+            Vec::new(),
+        ));
+        assert!(res.is_err());
+
+        // doesn't recover more keys than a page of keys
+        let mut too_many = HashMap::new();
+        too_many.insert(Currency::Eth, MAX_KEYS_LIMIT as u32 + 1);
+        let res = core.run(seeds_service.import_mnemonic(
+            Some(token2.clone()),
+            user_id2.clone(),
+            mnemonic.clone(),
+            Passphrase::default(),
+            too_many,
+            Vec::new(),
+        ));
+        match res.unwrap_err().kind() {
+            ErrorKind::Validation(_) => (),
+            kind => panic!("Unexpected error {:?}", kind),
+        }
+
+        // keys of all currencies and wallet types count to the limit
+        let mut too_many = HashMap::new();
+        too_many.insert(Currency::Btc, MAX_KEYS_LIMIT as u32 / 2);
+        too_many.insert(Currency::Eth, 1);
+        let res = core.run(seeds_service.import_mnemonic(
+            Some(token2.clone()),
+            user_id2.clone(),
+            mnemonic.clone(),
+            Passphrase::default(),
+            too_many,
+            vec![WalletType::P2pkh, WalletType::P2wpkh],
+        ));
+        match res.unwrap_err().kind() {
+            ErrorKind::Validation(_) => (),
+            kind => panic!("Unexpected error {:?}", kind),
+        }
+
+        // recreates the same addresses from the same mnemonic
+        let keys = core
+            .run(seeds_service.import_mnemonic(
+                Some(token2.clone()),
+                user_id2.clone(),
+                mnemonic.clone(),
+                Passphrase::default(),
+                recover.clone(),
+                Vec::new(),
+            ))
+            .unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[1].derivation_path.clone().unwrap().to_string(), "m/44'/0'/0'/0/1");
+        let blockchain_service = BlockchainServiceMock;
+        let master_seed = MasterSeed::from_mnemonic(&mnemonic, &Passphrase::default()).unwrap();
+        let (_, address) = blockchain_service
+            .derive_key(Currency::Eth, None, &master_seed, keys[0].derivation_path.as_ref().unwrap())
+            .unwrap();
+        assert_eq!(keys[0].blockchain_address, address);
+        assert_eq!(keys[0].owner_id, user_id2);
+
+        // recreates bitcoin keys of every wallet type under its own derivation path
+        let mut recover = HashMap::new();
+        recover.insert(Currency::Btc, 1);
+        let keys = core
+            .run(seeds_service.import_mnemonic(
+                Some(token3.clone()),
+                user_id3.clone(),
+                mnemonic.clone(),
+                Passphrase::default(),
+                recover,
+                vec![WalletType::P2wpkh, WalletType::P2pkh, WalletType::P2wpkh, WalletType::P2shP2wpkh],
+            ))
+            .unwrap();
+        assert_eq!(keys.len(), 3);
+        assert_eq!(keys[0].derivation_path.clone().unwrap().to_string(), "m/84'/0'/0'/0/0");
+        assert_eq!(keys[0].wallet_type, Some(WalletType::P2wpkh));
+        assert_eq!(keys[1].derivation_path.clone().unwrap().to_string(), "m/44'/0'/0'/0/0");
+        assert_eq!(keys[1].wallet_type, Some(WalletType::P2pkh));
+        assert_eq!(keys[2].derivation_path.clone().unwrap().to_string(), "m/49'/0'/0'/0/0");
+        assert_eq!(keys[2].wallet_type, Some(WalletType::P2shP2wpkh));
+    }
+
+    #[test]
+    fn test_extended_public_key() {
+        let new_user = NewUser::default();
+        let user_id = new_user.id.clone();
+        let token = new_user.authentication_token.clone();
+        let auth_service = Arc::new(AuthServiceMock::new(vec![(token.clone(), user_id.clone())]));
+        let keys_repo = Arc::new(KeysRepoMock::new());
+        let seeds_repo = Arc::new(SeedsRepoMock::new());
+        let seeds_service = SeedsServiceImpl::new(
+            auth_service.clone(),
+            Arc::new(BlockchainServiceMock),
+            keys_repo.clone(),
+            seeds_repo.clone(),
+            Arc::new(UsersRepoMock::new()),
+            KeyGeneration::UserSeed,
+            DbExecutorMock::new(),
+        );
+        let keys_service = KeysServiceImpl::new(
+            auth_service,
+            Arc::new(BlockchainServiceMock),
+            keys_repo,
+            seeds_repo.clone(),
+            Arc::new(UsersRepoMock::new()),
+            KeyGeneration::UserSeed,
+            DbExecutorMock::new(),
+        );
+        let mut core = Core::new().unwrap();
+
+        // exports xpub of the seed, that keys are derived from
+        let (xpub, path, wallet_type) = core
+            .run(seeds_service.extended_public_key(Some(token.clone()), user_id.clone(), Currency::Btc, None))
+            .unwrap();
+        assert_eq!(path.to_string(), "m/44'/0'/0'");
+        assert_eq!(wallet_type, Some(WalletType::P2pkh));
+        let key = core
+            .run(keys_service.create(
+                Some(token.clone()),
+                user_id.clone(),
+                Currency::Btc,
+                None,
+                KeyId::default(),
+                None,
+                KeyMetadata::default(),
+            ))
+            .unwrap();
+        assert_eq!(key.derivation_path.unwrap(), path.address(0));
+        let seed = seeds_repo.find(user_id.clone()).unwrap().unwrap();
+        assert_eq!(
+            BlockchainServiceMock
+                .derive_extended_public_key(Currency::Btc, wallet_type, &seed.master_seed, &path)
+                .unwrap(),
+            xpub
+        );
+
+        // exports zpub of P2WPKH keys under BIP84 path
+        let (zpub, path, wallet_type) = core
+            .run(seeds_service.extended_public_key(Some(token.clone()), user_id.clone(), Currency::Btc, Some(WalletType::P2wpkh)))
+            .unwrap();
+        assert_eq!(path.to_string(), "m/84'/0'/0'");
+        assert_eq!(wallet_type, Some(WalletType::P2wpkh));
+        assert_ne!(zpub, xpub);
+
+        // exports ypub of P2SH-P2WPKH keys under BIP49 path
+        let (_, path, wallet_type) = core
+            .run(seeds_service.extended_public_key(Some(token.clone()), user_id.clone(), Currency::Btc, Some(WalletType::P2shP2wpkh)))
+            .unwrap();
+        assert_eq!(path.to_string(), "m/49'/0'/0'");
+        assert_eq!(wallet_type, Some(WalletType::P2shP2wpkh));
+
+        // doesn't export xpub of ethereum keys with wallet type
+        let res =
+            core.run(seeds_service.extended_public_key(Some(token.clone()), user_id.clone(), Currency::Eth, Some(WalletType::P2wpkh)));
+        assert!(res.is_err());
+
+        // doesn't export xpub of other users
+        let res = core.run(seeds_service.extended_public_key(Some(token.clone()), UserId::default(), Currency::Btc, None));
+        assert!(res.is_err());
+    }
+}
@@ -67,14 +67,24 @@ impl<E: DbExecutor> TransactionsService for TransactionsServiceImpl<E> {
                     _ => user_id,
                 };
                 keys_repo
-                    .find_by_address(user_id, blockchain_address)
+                    .find_by_address(user_id, blockchain_address, None)
                     .map_err(ectx!(ErrorKind::Internal => user_id_clone))
                     .and_then(|maybe_key| {
                         maybe_key.ok_or(ectx!(err ErrorContext::NoWallet, ErrorKind::NotFound => user_id_clone2, blockchain_address_clone, currency_clone))
-                    }).and_then(move |key| {
-                        signer
-                            .sign(key.private_key.clone(), transaction.clone())
-                            .map_err(ectx!(convert => key.private_key, transaction))
+                    })
+                    .and_then(|key| active_key(key, "from"))
+                    .and_then(|key| {
+                        // Private key is decrypted only for signing and wiped from memory right after
+                        let key_id = key.id.clone();
+                        let private_key = keys_repo
+                            .decrypt_private_key(&key)
+                            .map_err(ectx!(try ErrorKind::Internal => key_id))?;
+                        let key_id = key.id.clone();
+                        let raw_transaction = signer
+                            .sign(&private_key, transaction.clone())
+                            .map_err(ectx!(try convert => key.id, transaction))?;
+                        record_signature(&*keys_repo, key_id)?;
+                        Ok(raw_transaction)
                     })
             })
         }))
@@ -96,19 +106,132 @@ impl<E: DbExecutor> TransactionsService for TransactionsServiceImpl<E> {
             let currency = Currency::Stq;
             db_executor.execute_transaction(move || {
                 keys_repo
-                    .find_by_address(user_id, blockchain_address)
+                    .find_by_address(user_id, blockchain_address, None)
                     .map_err(ectx!(ErrorKind::Internal => user_id_clone))
                     .and_then(|maybe_key| {
                         maybe_key.ok_or(
                             ectx!(err ErrorContext::NoWallet, ErrorKind::NotFound => user_id_clone2, blockchain_address_clone, currency),
                         )
                     })
-                    .and_then(move |key| {
-                        signer
-                            .approve(key.private_key.clone(), input.clone())
-                            .map_err(ectx!(convert => key.private_key, input))
+                    .and_then(|key| active_key(key, "address"))
+                    .and_then(|key| {
+                        let key_id = key.id.clone();
+                        let private_key = keys_repo
+                            .decrypt_private_key(&key)
+                            .map_err(ectx!(try ErrorKind::Internal => key_id))?;
+                        let key_id = key.id.clone();
+                        let raw_transaction = signer
+                            .approve(&private_key, input.clone())
+                            .map_err(ectx!(try convert => key.id, input))?;
+                        record_signature(&*keys_repo, key_id)?;
+                        Ok(raw_transaction)
                     })
             })
         }))
     }
 }
+
+/// Usage is recorded in the transaction of signing, so that failed signatures are not counted
+fn record_signature(keys_repo: &KeysRepo, key_id: KeyId) -> Result<(), Error> {
+    keys_repo
+        .record_signature(key_id.clone())
+        .map(|_| ())
+        .map_err(ectx!(ErrorKind::Internal => key_id))
+}
+
+/// Disabled and archived keys can't sign, `field` is the input field with the key address
+fn active_key(key: Key, field: &'static str) -> Result<Key, Error> {
+    if key.status != KeyStatus::Active {
+        return Err(ectx!(err ErrorContext::KeyNotActive, ErrorKind::invalid_field(field, "not_active") => key.id, key.status));
+    }
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain::*;
+    use repos::*;
+    use services::*;
+    use tokio_core::reactor::Core;
+
+    fn create_key(keys_repo: &KeysRepoMock, owner_id: UserId, currency: Currency, blockchain_address: &str) -> Key {
+        keys_repo
+            .create(NewKey {
+                id: KeyId::default(),
+                private_key: PrivateKey::new("private_key".to_string()),
+                blockchain_address: BlockchainAddress::new(blockchain_address.to_string()),
+                currency,
+                owner_id,
+                derivation_path: None,
+                label: None,
+                metadata: KeyMetadata::default(),
+                wallet_type: WalletType::default_for(currency),
+            })
+            .unwrap()
+    }
+
+    fn unsigned_transaction(from: &Key) -> UnsignedTransaction {
+        UnsignedTransaction {
+            id: TransactionId::default(),
+            from: from.blockchain_address.clone(),
+            to: BlockchainAddress::new("to".to_string()),
+            currency: from.currency,
+            value: Amount::new(1),
+            fee_price: 1.0,
+            nonce: Some(0),
+            utxos: None,
+            coin_selection: None,
+        }
+    }
+
+    #[test]
+    fn test_usage_tracking() {
+        let new_user = NewUser::default();
+        let user_id = new_user.id.clone();
+        let token = new_user.authentication_token.clone();
+        let auth_service = Arc::new(AuthServiceMock::new(vec![(token.clone(), user_id.clone())]));
+        let keys_repo = Arc::new(KeysRepoMock::new());
+        let transactions_service = TransactionsServiceImpl::new(
+            auth_service,
+            keys_repo.clone(),
+            Arc::new(UsersRepoMock::new()),
+            Arc::new(BlockchainServiceMock),
+            BlockchainAddress::new("controller".to_string()),
+            DbExecutorMock::new(),
+        );
+        let mut core = Core::new().unwrap();
+        let eth_key = create_key(&keys_repo, user_id.clone(), Currency::Eth, "eth");
+        let stq_key = create_key(&keys_repo, user_id.clone(), Currency::Stq, "stq");
+        assert_eq!(eth_key.last_used_at, None);
+        assert_eq!(eth_key.signatures_count, 0);
+
+        for _ in 0..2 {
+            core.run(transactions_service.sign(Some(token.clone()), unsigned_transaction(&eth_key)))
+                .unwrap();
+        }
+        let approve = ApproveInput {
+            id: TransactionId::default(),
+            address: stq_key.blockchain_address.clone(),
+            approve_address: BlockchainAddress::new("spender".to_string()),
+            currency: Currency::Stq,
+            value: Amount::new(1),
+            fee_price: 1.0,
+            nonce: 0,
+        };
+        core.run(transactions_service.approve(Some(token.clone()), approve)).unwrap();
+        let key = keys_repo.find(eth_key.id.clone()).unwrap().unwrap();
+        assert!(key.last_used_at.is_some());
+        assert_eq!(key.signatures_count, 2);
+        let key = keys_repo.find(stq_key.id.clone()).unwrap().unwrap();
+        assert!(key.last_used_at.is_some());
+        assert_eq!(key.signatures_count, 1);
+
+        // failed signatures are not counted
+        keys_repo.update_status(eth_key.id.clone(), KeyStatus::Disabled).unwrap();
+        let res = core.run(transactions_service.sign(Some(token.clone()), unsigned_transaction(&eth_key)));
+        assert!(res.is_err());
+        let key = keys_repo.find(eth_key.id.clone()).unwrap().unwrap();
+        assert_eq!(key.signatures_count, 2);
+    }
+}
@@ -0,0 +1,124 @@
+use std::cmp;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::future;
+use futures_cpupool::CpuPool;
+
+use super::error::*;
+use super::ServiceFuture;
+use blockchain::BlockchainService;
+use config::Vanity;
+use models::*;
+use prelude::*;
+
+/// Number of keys generated between checks of the deadline
+const BATCH_ITERATIONS: usize = 10_000;
+
+/// Searches for random keys with address starting with a prefix, e.g. for branded hot wallets.
+///
+/// The search is split between threads of its own pool, so that it doesn't block
+/// database and request threads. It gives up after `iterations` keys in total or after `timeout`.
+pub struct VanityKeyGenerator {
+    blockchain_service: Arc<BlockchainService>,
+    cpu_pool: CpuPool,
+    threads: usize,
+    iterations: usize,
+    timeout: Duration,
+}
+
+impl VanityKeyGenerator {
+    pub fn new(blockchain_service: Arc<BlockchainService>, config: &Vanity) -> Self {
+        let threads = cmp::max(config.threads, 1);
+        Self {
+            blockchain_service,
+            cpu_pool: CpuPool::new(threads),
+            threads,
+            iterations: config.iterations,
+            timeout: Duration::from_secs(config.timeout_secs),
+        }
+    }
+
+    pub fn generate(&self, currency: Currency, prefix: String) -> ServiceFuture<(PrivateKey, BlockchainAddress)> {
+        let deadline = Instant::now() + self.timeout;
+        // Set by the thread that finds the key, so that the others stop
+        let found = Arc::new(AtomicBool::new(false));
+        let searches: Vec<_> = (0..self.threads)
+            .map(|thread| {
+                let blockchain_service = self.blockchain_service.clone();
+                let prefix = prefix.clone();
+                let found = found.clone();
+                let extra = if thread < self.iterations % self.threads { 1 } else { 0 };
+                let iterations = self.iterations / self.threads + extra;
+                self.cpu_pool
+                    .spawn_fn(move || search(&*blockchain_service, currency, &prefix, iterations, deadline, &found))
+            })
+            .collect();
+        Box::new(future::select_ok(searches).map(|(key, _)| key))
+    }
+}
+
+fn search(
+    blockchain_service: &BlockchainService,
+    currency: Currency,
+    prefix: &str,
+    iterations: usize,
+    deadline: Instant,
+    found: &AtomicBool,
+) -> Result<(PrivateKey, BlockchainAddress), Error> {
+    let mut remaining = iterations;
+    while remaining > 0 && !found.load(Ordering::Relaxed) {
+        if Instant::now() >= deadline {
+            return Err(ectx!(err ErrorContext::VanityTimeout, ErrorKind::NotFound => currency, prefix));
+        }
+        let batch = cmp::min(remaining, BATCH_ITERATIONS);
+        let key = blockchain_service
+            .generate_vanity_key(currency, prefix, batch)
+            .map_err(ectx!(try convert => currency, prefix))?;
+        if let Some(key) = key {
+            found.store(true, Ordering::Relaxed);
+            return Ok(key);
+        }
+        remaining -= batch;
+    }
+    Err(ectx!(err ErrorContext::VanityIterations, ErrorKind::NotFound => currency, prefix, iterations))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain::*;
+    // blockchain errors have the same names
+    use services::ErrorKind;
+
+    fn create_generator(iterations: usize, timeout_secs: u64) -> VanityKeyGenerator {
+        let config = Vanity {
+            threads: 2,
+            iterations,
+            timeout_secs,
+        };
+        VanityKeyGenerator::new(Arc::new(BlockchainServiceMock), &config)
+    }
+
+    #[test]
+    fn test_generate() {
+        let generator = create_generator(100, 60);
+        let (_, address) = generator.generate(Currency::Eth, "abcd".to_string()).wait().unwrap();
+        assert!(address.into_inner().starts_with("abcd"));
+        // one of the threads gets no iterations
+        let generator = create_generator(1, 60);
+        assert!(generator.generate(Currency::Eth, "abcd".to_string()).wait().is_ok());
+
+        let generator = create_generator(0, 60);
+        let error = generator.generate(Currency::Eth, "abcd".to_string()).wait().err().unwrap();
+        match error.kind() {
+            ErrorKind::NotFound => (),
+            kind => panic!("unexpected error: {:?}", kind),
+        }
+        let generator = create_generator(100, 0);
+        assert!(generator.generate(Currency::Eth, "abcd".to_string()).wait().is_err());
+        let generator = create_generator(100, 60);
+        assert!(generator.generate(Currency::Eth, "".to_string()).wait().is_err());
+    }
+}
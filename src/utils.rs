@@ -1,10 +1,17 @@
+use crypto::aead::{AeadDecryptor, AeadEncryptor};
+use crypto::aes_gcm::AesGcm;
 use crypto::buffer::{ReadBuffer, WriteBuffer};
-use failure::Fail;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha256;
+use failure::{Error as FailureError, Fail};
 use futures::future;
 use futures::prelude::*;
 use hyper;
 use regex;
 use sentry::integrations::failure::capture_error;
+use std::ptr;
+use std::sync::atomic;
 
 pub fn format_error<E: Fail>(error: &E) -> String {
     let mut result = String::new();
@@ -103,6 +110,44 @@ pub fn decrypt(encrypted_data: &[u8], key: &[u8], iv: &[u8]) -> Result<Vec<u8>,
     Ok(final_result)
 }
 
+/// Length of AES-GCM authentication tag, appended to ciphertext by `encrypt_aead`
+pub const AEAD_TAG_LEN: usize = 16;
+/// Length of AES-GCM nonce
+pub const AEAD_NONCE_LEN: usize = 12;
+
+/// Encrypts data with AES-256-GCM. Returns ciphertext with authentication tag appended.
+pub fn encrypt_aead(data: &[u8], key: &[u8], nonce: &[u8], associated_data: &[u8]) -> Vec<u8> {
+    let mut encryptor = AesGcm::new(crypto::aes::KeySize::KeySize256, key, nonce, associated_data);
+    let mut result = vec![0u8; data.len()];
+    let mut tag = [0u8; AEAD_TAG_LEN];
+    encryptor.encrypt(data, &mut result, &mut tag);
+    result.extend_from_slice(&tag);
+    result
+}
+
+/// Decrypts ciphertext produced by `encrypt_aead`. Returns `None` if authentication failed,
+/// i.e. ciphertext, nonce, key or associated data doesn't match.
+pub fn decrypt_aead(encrypted_data: &[u8], key: &[u8], nonce: &[u8], associated_data: &[u8]) -> Option<Vec<u8>> {
+    if encrypted_data.len() < AEAD_TAG_LEN {
+        return None;
+    }
+    let (ciphertext, tag) = encrypted_data.split_at(encrypted_data.len() - AEAD_TAG_LEN);
+    let mut decryptor = AesGcm::new(crypto::aes::KeySize::KeySize256, key, nonce, associated_data);
+    let mut result = vec![0u8; ciphertext.len()];
+    if decryptor.decrypt(ciphertext, &mut result, tag) {
+        Some(result)
+    } else {
+        None
+    }
+}
+
+/// Computes HMAC-SHA256 of `data` under `key`
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut hmac = Hmac::new(Sha256::new(), key);
+    hmac.input(data);
+    hmac.result().code().to_vec()
+}
+
 pub fn encode_hex(bytes: &[u8]) -> String {
     let mut res = String::new();
     for byte in bytes.iter() {
@@ -111,15 +156,31 @@ pub fn encode_hex(bytes: &[u8]) -> String {
     res
 }
 
-pub fn decode_hex(hex_str: &str) -> Vec<u8> {
-    hex_str
-        .as_bytes()
-        .chunks(2)
-        .map(|chunk| {
-            let mut hex = String::new();
-            hex.push(chunk[0].into());
-            hex.push(chunk[1].into());
-            u8::from_str_radix(&hex, 16).unwrap()
-        })
-        .collect()
+pub fn decode_hex(hex_str: &str) -> Result<Vec<u8>, FailureError> {
+    if hex_str.len() % 2 != 0 {
+        return Err(format_err!("Odd length of hex string: {}", hex_str.len()));
+    }
+    // Allocated once, so that decoded secrets are not left in reallocated buffers
+    let mut res = Vec::with_capacity(hex_str.len() / 2);
+    for chunk in hex_str.as_bytes().chunks(2) {
+        let high = (chunk[0] as char).to_digit(16);
+        let low = (chunk[1] as char).to_digit(16);
+        match (high, low) {
+            (Some(high), Some(low)) => res.push((high * 16 + low) as u8),
+            _ => {
+                wipe(&mut res);
+                return Err(format_err!("Invalid hex string"));
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Overwrites secret data with zeros. Volatile writes are not optimized away,
+/// even if the memory is freed right after.
+pub fn wipe(bytes: &mut [u8]) {
+    for byte in bytes.iter_mut() {
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+    atomic::compiler_fence(atomic::Ordering::SeqCst);
 }